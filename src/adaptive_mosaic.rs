@@ -0,0 +1,355 @@
+use std::path::Path;
+
+use image::{GenericImageView, ImageResult};
+use rayon::prelude::*;
+
+use crate::alpha::{self, AlphaOptions};
+use crate::image_io::{self, OutputOptions};
+use crate::mosaic_factory::MosaicBuilder;
+use crate::{detail_map, log, ImageData, MosaicFactory};
+
+/// Tile of a quadtree as `(x, y, width, height)`
+type AdaptiveTile = (u32, u32, u32, u32);
+
+/// Mosaic with variable tile size, tiles are split in a quadtree until they match the local detail. The image is
+/// cropped to a multiple of the largest tile like uniform mosaics and tiles are split while their side length halves
+/// evenly, so every quadtree level is a uniform grid.
+pub struct AdaptiveMosaic {
+    pub tile_side_length: u32,
+    pub min_tile_side_length: u32,
+    /// Factory tiled at the smallest side length of the quadtree, its builder sums the tiles of every level
+    pub mosaic_factory: MosaicFactory,
+    /// Per pixel detail, 0 asks for the largest tiles and 255 for the smallest
+    pub detail: Vec<u8>,
+}
+
+impl AdaptiveMosaic {
+    /// Creates the mosaic from image data and a detail map already cropped to a multiple of the tile side length.
+    pub fn new(
+        image_data: ImageData,
+        mosaic_builder: Box<dyn MosaicBuilder>,
+        tile_side_length: u32,
+        min_tile_side_length: u32,
+        detail: Vec<u8>,
+    ) -> AdaptiveMosaic {
+        if min_tile_side_length == 0 || min_tile_side_length > tile_side_length {
+            panic!(
+                "Minimum tile side length must be between 1 and {}",
+                tile_side_length
+            );
+        }
+        let side_lengths = AdaptiveMosaic::side_lengths(tile_side_length, min_tile_side_length);
+        let smallest_side_length = side_lengths[side_lengths.len() - 1];
+        return AdaptiveMosaic {
+            tile_side_length,
+            min_tile_side_length,
+            mosaic_factory: MosaicFactory::from_image_data(
+                image_data,
+                mosaic_builder,
+                smallest_side_length,
+            ),
+            detail,
+        };
+    }
+
+    pub fn with_alpha_options(mut self, alpha_options: AlphaOptions) -> AdaptiveMosaic {
+        self.mosaic_factory = self.mosaic_factory.with_alpha_options(alpha_options);
+        return self;
+    }
+
+    pub fn with_output_options(mut self, output_options: OutputOptions) -> AdaptiveMosaic {
+        self.mosaic_factory = self.mosaic_factory.with_output_options(output_options);
+        return self;
+    }
+
+    /// Uses a grayscale mask of the input size as detail map, bright areas get the smallest tiles.
+    pub fn from_mask<P: AsRef<Path>, M: AsRef<Path>>(
+        input_image_path: P,
+        mask_path: M,
+        mosaic_builder: Box<dyn MosaicBuilder>,
+        tile_side_length: u32,
+        min_tile_side_length: u32,
+    ) -> ImageResult<AdaptiveMosaic> {
+        let img = ImageData::load_image(input_image_path.as_ref())?;
+        let mask = ImageData::load_image(mask_path.as_ref())?;
+        if mask.dimensions() != img.dimensions() {
+            return Err(image_io::parameter_error(format!(
+                "tile size mask size {}x{} does not match image size {}x{}",
+                mask.width(),
+                mask.height(),
+                img.width(),
+                img.height()
+            )));
+        }
+        let detail = ImageData::crop_image(&mask, tile_side_length)
+            .into_luma8()
+            .into_raw();
+        return Ok(AdaptiveMosaic::new(
+            ImageData::new(&img, tile_side_length),
+            mosaic_builder,
+            tile_side_length,
+            min_tile_side_length,
+            detail,
+        ));
+    }
+
+    /// Uses local edge energy of the image as detail map, detailed areas get the smallest tiles.
+    pub fn from_edges<P: AsRef<Path>>(
+        input_image_path: P,
+        mosaic_builder: Box<dyn MosaicBuilder>,
+        tile_side_length: u32,
+        min_tile_side_length: u32,
+    ) -> ImageResult<AdaptiveMosaic> {
+        let img = ImageData::load_image(input_image_path.as_ref())?;
+        let image_data = ImageData::new(&img, tile_side_length);
+        let detail = detail_map::edge_detail(&image_data, min_tile_side_length);
+        return Ok(AdaptiveMosaic::new(
+            image_data,
+            mosaic_builder,
+            tile_side_length,
+            min_tile_side_length,
+            detail,
+        ));
+    }
+
+    /// Side lengths of the quadtree levels from the largest, each level halves the previous one.
+    fn side_lengths(tile_side_length: u32, min_tile_side_length: u32) -> Vec<u32> {
+        let mut side_lengths = vec![tile_side_length];
+        let mut side_length = tile_side_length;
+        while side_length.is_multiple_of(2) && side_length / 2 >= min_tile_side_length {
+            side_length /= 2;
+            side_lengths.push(side_length);
+        }
+        return side_lengths;
+    }
+
+    /// Tile side length requested by the detail of a single pixel.
+    fn requested_side_length(&self, detail: u8) -> u32 {
+        let range = self.tile_side_length - self.min_tile_side_length;
+        return self.tile_side_length - range * detail as u32 / 255;
+    }
+
+    fn max_detail(&self, tile: AdaptiveTile) -> u8 {
+        let (x, y, width, height) = tile;
+        let image_width = self.mosaic_factory.image_data.width;
+        return (y..y + height)
+            .flat_map(|row| {
+                let row_start = (row * image_width + x) as usize;
+                return self.detail[row_start..row_start + width as usize].iter();
+            })
+            .copied()
+            .max()
+            .unwrap_or(0);
+    }
+
+    fn split_tile(&self, tile: AdaptiveTile, tiles: &mut Vec<AdaptiveTile>) {
+        let (x, y, side_length, _) = tile;
+        let half = side_length / 2;
+        let requested = self.requested_side_length(self.max_detail(tile));
+        let is_smallest = side_length == self.mosaic_factory.tile_side_length;
+        if is_smallest || requested >= side_length {
+            tiles.push(tile);
+            return;
+        }
+
+        for (offset_x, offset_y) in [(0, 0), (half, 0), (0, half), (half, half)] {
+            self.split_tile((x + offset_x, y + offset_y, half, half), tiles);
+        }
+    }
+
+    /// Splits the image into quadtree leaves starting from tiles of the maximum side length.
+    pub fn tiles(&self) -> Vec<AdaptiveTile> {
+        let (width, height) = (
+            self.mosaic_factory.image_data.width,
+            self.mosaic_factory.image_data.height,
+        );
+        let side_length = self.tile_side_length;
+        let roots: Vec<AdaptiveTile> = (0..height)
+            .step_by(side_length as usize)
+            .flat_map(|y| {
+                return (0..width)
+                    .step_by(side_length as usize)
+                    .map(move |x| (x, y, side_length, side_length));
+            })
+            .collect();
+
+        return roots
+            .into_par_iter()
+            .flat_map_iter(|root| {
+                let mut tiles = Vec::new();
+                self.split_tile(root, &mut tiles);
+                return tiles;
+            })
+            .collect();
+    }
+
+    /// Channel sums of the tiles of every quadtree level from the smallest, the builder sums the smallest tiles
+    /// and each larger level adds up four tiles of the level below.
+    fn level_sums(&self) -> Vec<Vec<u64>> {
+        let mosaic_factory = &self.mosaic_factory;
+        let channels = mosaic_factory.image_data.channels as usize;
+        let levels =
+            AdaptiveMosaic::side_lengths(self.tile_side_length, self.min_tile_side_length).len();
+
+        let mut level_sums = vec![mosaic_factory
+            .mosaic_builder
+            .sum_tile_channels(mosaic_factory)];
+        let (mut tiles_x, mut tiles_y) = (mosaic_factory.tiles_x, mosaic_factory.tiles_y);
+        for _ in 1..levels {
+            let finer = &level_sums[level_sums.len() - 1];
+            let (finer_tiles_x, finer_tiles_y) = (tiles_x, tiles_y);
+            (tiles_x, tiles_y) = (tiles_x / 2, tiles_y / 2);
+            let mut sums = vec![0; (tiles_x * tiles_y) as usize * channels];
+            for tile_y in 0..finer_tiles_y {
+                for tile_x in 0..finer_tiles_x {
+                    let finer_tile = (tile_y * finer_tiles_x + tile_x) as usize * channels;
+                    let tile = ((tile_y / 2) * tiles_x + tile_x / 2) as usize * channels;
+                    for channel in 0..channels {
+                        sums[tile + channel] += finer[finer_tile + channel];
+                    }
+                }
+            }
+            level_sums.push(sums);
+        }
+        return level_sums;
+    }
+
+    pub fn generate_mosaic(&self) -> Vec<u8> {
+        let tiles = self.tiles();
+        log::verbose!("Adaptive tiles: {}", tiles.len());
+        let mosaic_factory = &self.mosaic_factory;
+        let image_data = &mosaic_factory.image_data;
+        let channels = image_data.channels as usize;
+        let has_alpha = alpha::has_alpha(image_data);
+        let level_sums = self.level_sums();
+
+        let tile_averages: Vec<(AdaptiveTile, Vec<u8>)> = tiles
+            .into_par_iter()
+            .map(|(x, y, width, height)| {
+                let scale = width / mosaic_factory.tile_side_length;
+                let level = scale.trailing_zeros() as usize;
+                let tiles_x = mosaic_factory.tiles_x / scale;
+                let tile = ((y / height) * tiles_x + x / width) as usize * channels;
+                let sums = &level_sums[level][tile..tile + channels];
+                let mut average: Vec<u8> = (0..channels)
+                    .map(|channel| {
+                        let pixels = (width * height) as u64;
+                        return alpha::channel_average(sums, channel, pixels, has_alpha);
+                    })
+                    .collect();
+                if has_alpha {
+                    alpha::resolve_alpha(
+                        &mut average,
+                        image_data.channels,
+                        &mosaic_factory.alpha_options,
+                    );
+                }
+                return ((x, y, width, height), average);
+            })
+            .collect();
+
+        let mut mosaic = vec![0; image_data.data.len()];
+        for ((x, y, width, height), average) in tile_averages {
+            image_data.fill_area(&mut mosaic, x, y, width, height, &average);
+        }
+        return mosaic;
+    }
+
+    pub fn generate_and_save_mosaic<P: AsRef<Path>>(&self, output_img_path: &P) -> ImageResult<()> {
+        let img = self.generate_mosaic();
+        return self.mosaic_factory.save_mosaic(output_img_path, &img);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::args::AlgorithmType;
+    use crate::synthetic::{ChannelLayout, Pattern, SyntheticImage};
+
+    fn edges_mosaic(
+        pattern: Pattern,
+        algorithm_type: AlgorithmType,
+        min_tile_side_length: u32,
+    ) -> AdaptiveMosaic {
+        let img = SyntheticImage::new(pattern, 64, 48, ChannelLayout::Rgba)
+            .with_cell_side_length(4)
+            .with_seed(5)
+            .generate();
+        let image_data = ImageData::new(&img, 16);
+        let detail = detail_map::edge_detail(&image_data, min_tile_side_length);
+        return AdaptiveMosaic::new(
+            image_data,
+            algorithm_type.mosaic_builder(),
+            16,
+            min_tile_side_length,
+            detail,
+        );
+    }
+
+    #[test]
+    fn flat_image_keeps_largest_tiles() {
+        let adaptive_mosaic = edges_mosaic(Pattern::Solid, AlgorithmType::Serial, 4);
+        let tiles = adaptive_mosaic.tiles();
+        assert_eq!(tiles.len(), 4 * 3);
+        assert!(tiles
+            .iter()
+            .all(|&(_, _, width, height)| width == 16 && height == 16));
+    }
+
+    #[test]
+    fn checkerboard_splits_to_smallest_tiles() {
+        let adaptive_mosaic = edges_mosaic(Pattern::Checkerboard, AlgorithmType::Serial, 4);
+        let tiles = adaptive_mosaic.tiles();
+        assert_eq!(tiles.len(), 16 * 12);
+        assert!(tiles
+            .iter()
+            .all(|&(_, _, width, height)| width == 4 && height == 4));
+    }
+
+    #[test]
+    fn side_lengths_halve_while_even() {
+        assert_eq!(AdaptiveMosaic::side_lengths(48, 4), vec![48, 24, 12, 6]);
+        assert_eq!(AdaptiveMosaic::side_lengths(16, 4), vec![16, 8, 4]);
+        assert_eq!(AdaptiveMosaic::side_lengths(5, 1), vec![5]);
+    }
+
+    #[test]
+    fn levels_match_uniform_mosaics() {
+        let img = SyntheticImage::new(Pattern::Noise, 64, 48, ChannelLayout::Rgba)
+            .with_seed(5)
+            .generate();
+        for (detail, tile_side_length) in [(0, 16), (255, 4)] {
+            let adaptive_mosaic = AdaptiveMosaic::new(
+                ImageData::new(&img, 16),
+                AlgorithmType::Parallel.mosaic_builder(),
+                16,
+                4,
+                vec![detail; 64 * 48],
+            );
+            let uniform_mosaic = MosaicFactory::from_image_data(
+                ImageData::new(&img, 16),
+                AlgorithmType::Serial.mosaic_builder(),
+                tile_side_length,
+            );
+            assert_eq!(
+                adaptive_mosaic.generate_mosaic(),
+                uniform_mosaic.generate_mosaic()
+            );
+        }
+    }
+
+    #[test]
+    fn builders_agree() {
+        let mosaics: Vec<Vec<u8>> = [
+            AlgorithmType::Serial,
+            AlgorithmType::Parallel,
+            AlgorithmType::SlowParallel,
+        ]
+        .into_iter()
+        .map(|algorithm_type| edges_mosaic(Pattern::Noise, algorithm_type, 2).generate_mosaic())
+        .collect();
+        assert_eq!(mosaics[0], mosaics[1]);
+        assert_eq!(mosaics[0], mosaics[2]);
+    }
+}
@@ -0,0 +1,179 @@
+use crate::args::AlphaMode;
+use crate::image_data::{ImageData, Sample};
+
+/// Settings controlling how the alpha channel of tile averages is resolved
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AlphaOptions {
+    pub mode: AlphaMode,
+    pub background: [u8; 3],
+    pub threshold: u8,
+}
+
+impl Default for AlphaOptions {
+    fn default() -> AlphaOptions {
+        return AlphaOptions {
+            mode: AlphaMode::Keep,
+            background: [255, 255, 255],
+            threshold: 128,
+        };
+    }
+}
+
+/// Checks whether image data stores samples with a trailing alpha channel.
+pub fn has_alpha<S: Sample>(image_data: &ImageData<S>) -> bool {
+    return matches!(
+        image_data.color,
+        image::ColorType::La8 | image::ColorType::Rgba8 | image::ColorType::Rgba32F
+    );
+}
+
+/// Contribution of one pixel channel to a channel sum, colour is multiplied by alpha so that pixels count by their
+/// coverage.
+pub fn weighted_sample<S: Sample>(pixel: &[S], channel: usize, has_alpha: bool) -> S::Sum {
+    let alpha_channel = pixel.len() - 1;
+    return match has_alpha && channel != alpha_channel {
+        true => pixel[channel].weighted(pixel[alpha_channel]),
+        false => pixel[channel].to_sum(),
+    };
+}
+
+/// Straight average of a channel from the channel sums of one area, colour sums are divided by the summed alpha.
+pub fn channel_average<S: Sample>(
+    sums: &[S::Sum],
+    channel: usize,
+    pixels: u64,
+    has_alpha: bool,
+) -> S {
+    let alpha_channel = sums.len() - 1;
+    let divisor = match has_alpha && channel != alpha_channel {
+        true => sums[alpha_channel],
+        false => S::count(pixels),
+    };
+    return S::average(sums[channel], divisor);
+}
+
+/// Applies the selected alpha mode to straight averages.
+pub fn resolve_alpha<S: Sample>(averages: &mut [S], channels: u8, options: &AlphaOptions) {
+    let channels = channels as usize;
+    averages.chunks_exact_mut(channels).for_each(|pixel| {
+        let alpha = pixel[channels - 1];
+        match options.mode {
+            AlphaMode::Keep => {}
+            AlphaMode::Threshold => {
+                pixel[channels - 1] = match alpha >= S::from_u8(options.threshold) {
+                    true => S::MAX,
+                    false => S::default(),
+                };
+            }
+            AlphaMode::Flatten => {
+                for (channel, value) in pixel[..channels - 1].iter_mut().enumerate() {
+                    let background = match channels {
+                        2 => luma(options.background),
+                        _ => options.background[channel],
+                    };
+                    *value = value.composite(S::from_u8(background), alpha);
+                }
+                pixel[channels - 1] = S::MAX;
+            }
+        }
+    });
+}
+
+fn luma(color: [u8; 3]) -> u8 {
+    let [r, g, b] = color.map(|channel| channel as u32);
+    return ((r * 2126 + g * 7152 + b * 722 + 5000) / 10000) as u8;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::synthetic::{ChannelLayout, Pattern, SyntheticImage};
+    use crate::{MosaicFactory, SerialMosaic};
+
+    fn options(mode: AlphaMode) -> AlphaOptions {
+        return AlphaOptions {
+            mode,
+            background: [0, 100, 200],
+            threshold: 128,
+        };
+    }
+
+    #[test]
+    fn transparent_pixels_do_not_darken_colour() {
+        // One opaque red pixel and three fully transparent black pixels.
+        let sums: Vec<u64> = [[200, 10, 20, 255], [0, 0, 0, 0], [0, 0, 0, 0], [0, 0, 0, 0]]
+            .iter()
+            .fold(vec![0; 4], |mut sums, pixel| {
+                for (channel, sum) in sums.iter_mut().enumerate() {
+                    *sum += weighted_sample(pixel, channel, true);
+                }
+                return sums;
+            });
+        let average: Vec<u8> = (0..4)
+            .map(|channel| channel_average(&sums, channel, 4, true))
+            .collect();
+        assert_eq!(average, vec![200, 10, 20, 63]);
+
+        let transparent: Vec<u8> = (0..4)
+            .map(|channel| channel_average(&[0, 0, 0, 0], channel, 4, true))
+            .collect();
+        assert_eq!(transparent, vec![0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn opaque_layouts_are_plain_averages() {
+        let pixel = [10, 20, 30];
+        assert_eq!(weighted_sample(&pixel, 0, false), 10);
+        assert_eq!(channel_average::<u8>(&[30, 61, 90], 1, 3, false), 20);
+    }
+
+    #[test]
+    fn resolve_alpha_applies_each_mode() {
+        let averages = [200, 100, 50, 255, 200, 100, 50, 127, 200, 100, 50, 0];
+
+        let mut keep = averages;
+        resolve_alpha(&mut keep, 4, &options(AlphaMode::Keep));
+        assert_eq!(keep, averages);
+
+        let mut threshold = averages;
+        resolve_alpha(&mut threshold, 4, &options(AlphaMode::Threshold));
+        assert_eq!(
+            threshold,
+            [200, 100, 50, 255, 200, 100, 50, 0, 200, 100, 50, 0]
+        );
+
+        let mut flatten = averages;
+        resolve_alpha(&mut flatten, 4, &options(AlphaMode::Flatten));
+        assert_eq!(
+            flatten,
+            [200, 100, 50, 255, 100, 100, 125, 255, 0, 100, 200, 255]
+        );
+    }
+
+    #[test]
+    fn threshold_is_inclusive() {
+        let mut averages = [90, 127, 90, 128];
+        resolve_alpha(&mut averages, 2, &options(AlphaMode::Threshold));
+        assert_eq!(averages, [90, 0, 90, 255]);
+    }
+
+    #[test]
+    fn flatten_uses_background_luma_for_gray() {
+        let mut averages = [255, 0];
+        resolve_alpha(&mut averages, 2, &options(AlphaMode::Flatten));
+        assert_eq!(averages, [luma([0, 100, 200]), 255]);
+    }
+
+    #[test]
+    fn mosaic_leaves_image_data_unchanged() {
+        let img = SyntheticImage::new(Pattern::Noise, 16, 16, ChannelLayout::Rgba)
+            .with_seed(5)
+            .generate();
+        let image_data = ImageData::new(&img, 4);
+        let original = image_data.data.clone();
+        let mosaic_factory = MosaicFactory::from_image_data(image_data, Box::new(SerialMosaic), 4)
+            .with_alpha_options(options(AlphaMode::Flatten));
+        mosaic_factory.generate_mosaic();
+        assert_eq!(mosaic_factory.image_data.data, original);
+    }
+}
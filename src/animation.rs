@@ -0,0 +1,448 @@
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Write};
+use std::path::Path;
+
+use image::codecs::gif::{GifDecoder, GifEncoder, Repeat};
+use image::codecs::png::PngDecoder;
+use image::error::{UnsupportedError, UnsupportedErrorKind};
+use image::{
+    AnimationDecoder, Delay, DynamicImage, Frame, ImageError, ImageFormat, ImageResult, RgbaImage,
+};
+use rayon::prelude::*;
+
+use crate::alpha::AlphaOptions;
+use crate::args::{AlgorithmType, OutputScale};
+use crate::image_data::ImageData;
+use crate::image_io::{self, to_image_error, OutputOptions};
+use crate::log;
+use crate::mosaic_factory::MosaicFactory;
+
+/// Delay of a frame rounded to whole milliseconds.
+fn delay_ms(frame: &Frame) -> u32 {
+    let (numerator, denominator) = frame.delay().numer_denom_ms();
+    return (numerator + denominator / 2) / denominator.max(1);
+}
+
+/// Frames of an animated GIF, APNG or WebP composited to the full canvas, with their delays and loop count.
+pub struct Animation {
+    pub frames: Vec<Frame>,
+    /// Number of times the animation is played, `None` loops forever
+    pub plays: Option<u32>,
+}
+
+impl Animation {
+    /// Decodes every frame of an animated image, returns `None` for still images and other formats.
+    pub fn load<P: AsRef<Path>>(path: P) -> ImageResult<Option<Animation>> {
+        let path = path.as_ref();
+        let extension = path
+            .extension()
+            .map(|extension| extension.to_string_lossy().to_lowercase());
+        let animation = match extension.as_deref() {
+            Some("gif") => Animation::load_gif(path)?,
+            Some("png" | "apng") => Animation::load_png(path)?,
+            Some("webp") => Animation::load_webp(path)?,
+            _ => None,
+        };
+        return Ok(animation.filter(|animation| animation.frames.len() > 1));
+    }
+
+    fn load_gif(path: &Path) -> ImageResult<Option<Animation>> {
+        let decoder = GifDecoder::new(BufReader::new(File::open(path)?))?;
+        let frames = decoder.into_frames().collect_frames()?;
+
+        // the looping extension precedes the first frame, files without it are played once
+        let mut options = gif::DecodeOptions::new();
+        options.set_color_output(gif::ColorOutput::Indexed);
+        let mut decoder = options
+            .read_info(BufReader::new(File::open(path)?))
+            .map_err(to_image_error)?;
+        decoder.next_frame_info().map_err(to_image_error)?;
+        let plays = match decoder.repeat() {
+            gif::Repeat::Infinite => None,
+            gif::Repeat::Finite(repetitions) => Some(repetitions as u32 + 1),
+        };
+        return Ok(Some(Animation { frames, plays }));
+    }
+
+    fn load_png(path: &Path) -> ImageResult<Option<Animation>> {
+        let decoder = PngDecoder::new(BufReader::new(File::open(path)?))?;
+        if !decoder.is_apng()? {
+            return Ok(None);
+        }
+        let frames = decoder.apng()?.into_frames().collect_frames()?;
+
+        let reader = png::Decoder::new(BufReader::new(File::open(path)?))
+            .read_info()
+            .map_err(to_image_error)?;
+        let plays = reader
+            .info()
+            .animation_control()
+            .map_or(0, |animation_control| animation_control.num_plays);
+        return Ok(Some(Animation {
+            frames,
+            plays: (plays > 0).then_some(plays),
+        }));
+    }
+
+    /// Frames are read with image-webp directly, the frame iterator of the image crate does not end after the last
+    /// frame.
+    fn load_webp(path: &Path) -> ImageResult<Option<Animation>> {
+        let mut decoder = image_webp::WebPDecoder::new(BufReader::new(File::open(path)?))
+            .map_err(to_image_error)?;
+        if !decoder.is_animated() {
+            return Ok(None);
+        }
+        let (width, height) = decoder.dimensions();
+        let has_alpha = decoder.has_alpha();
+        let mut buffer = vec![0; decoder.output_buffer_size().unwrap_or_default()];
+        let mut frames = Vec::with_capacity(decoder.num_frames() as usize);
+        for _ in 0..decoder.num_frames() {
+            let delay = decoder.read_frame(&mut buffer).map_err(to_image_error)?;
+            let image = match has_alpha {
+                true => RgbaImage::from_raw(width, height, buffer.clone()).unwrap(),
+                false => DynamicImage::ImageRgb8(
+                    image::RgbImage::from_raw(width, height, buffer.clone()).unwrap(),
+                )
+                .to_rgba8(),
+            };
+            frames.push(Frame::from_parts(
+                image,
+                0,
+                0,
+                Delay::from_numer_denom_ms(delay, 1),
+            ));
+        }
+
+        let plays = match decoder.loop_count() {
+            image_webp::LoopCount::Forever => None,
+            image_webp::LoopCount::Times(plays) => Some(plays.get() as u32),
+        };
+        return Ok(Some(Animation { frames, plays }));
+    }
+
+    /// Encodes the animation as GIF, APNG or animated WebP in the format of the output options or the format
+    /// matching the output path extension, `.apng` selects APNG. APNG output uses the PNG compression and filter of
+    /// the output options, the GIF encoder has no settings and WebP frames are always lossless.
+    pub fn save<P: AsRef<Path>>(&self, path: P, output_options: &OutputOptions) -> ImageResult<()> {
+        let path = path.as_ref();
+        let is_apng_path = path
+            .extension()
+            .is_some_and(|extension| extension.eq_ignore_ascii_case("apng"));
+        let format = match output_options.format {
+            None if is_apng_path => ImageFormat::Png,
+            _ => image_io::output_format(path, output_options)?,
+        };
+        if !matches!(
+            format,
+            ImageFormat::Gif | ImageFormat::Png | ImageFormat::WebP
+        ) {
+            return Err(ImageError::Unsupported(
+                UnsupportedError::from_format_and_kind(
+                    format.into(),
+                    UnsupportedErrorKind::GenericFeature(
+                        "animated output must be GIF, PNG or WebP".to_string(),
+                    ),
+                ),
+            ));
+        }
+
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let writer = BufWriter::new(File::create(path)?);
+        return match format {
+            ImageFormat::Gif => self.save_gif(writer),
+            ImageFormat::WebP => self.save_webp(writer),
+            _ => self.save_apng(writer, output_options),
+        };
+    }
+
+    fn save_gif<W: Write>(&self, writer: W) -> ImageResult<()> {
+        let mut encoder = GifEncoder::new(writer);
+        encoder.set_repeat(match self.plays {
+            None => Repeat::Infinite,
+            Some(plays) => Repeat::Finite(plays.saturating_sub(1).min(u16::MAX as u32) as u16),
+        })?;
+        return encoder.encode_frames(self.frames.iter().cloned());
+    }
+
+    /// Frames are written without a palette, a palette would have to cover the colours of every frame.
+    fn save_apng<W: Write>(&self, writer: W, output_options: &OutputOptions) -> ImageResult<()> {
+        let (width, height) = self.frames[0].buffer().dimensions();
+        let mut encoder = png::Encoder::new(writer, width, height);
+        image_io::configure_png_encoder(&mut encoder, output_options);
+        if output_options.png_palette {
+            log::info!("Animated PNG output is written without a palette");
+        }
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder
+            .set_animated(self.frames.len() as u32, self.plays.unwrap_or(0))
+            .map_err(to_image_error)?;
+        let mut writer = encoder.write_header().map_err(to_image_error)?;
+        for frame in &self.frames {
+            writer
+                .set_frame_delay(delay_ms(frame).min(u16::MAX as u32) as u16, 1000)
+                .map_err(to_image_error)?;
+            writer
+                .write_image_data(frame.buffer())
+                .map_err(to_image_error)?;
+        }
+        return writer.finish().map_err(to_image_error);
+    }
+
+    /// Writes the extended WebP container with an ANMF chunk per frame, frames are encoded losslessly.
+    fn save_webp<W: Write>(&self, mut writer: W) -> ImageResult<()> {
+        let (width, height) = self.frames[0].buffer().dimensions();
+        let mut chunks: Vec<u8> = Vec::new();
+        let mut write_chunk = |name: &[u8; 4], payload: &[u8]| {
+            chunks.extend_from_slice(name);
+            chunks.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+            chunks.extend_from_slice(payload);
+            if payload.len() % 2 == 1 {
+                chunks.push(0);
+            }
+        };
+
+        let mut canvas = vec![0x10 | 0x02, 0, 0, 0]; // alpha and animation flags
+        canvas.extend_from_slice(&(width - 1).to_le_bytes()[..3]);
+        canvas.extend_from_slice(&(height - 1).to_le_bytes()[..3]);
+        write_chunk(b"VP8X", &canvas);
+        let mut animation = vec![0; 4]; // transparent background
+        animation.extend_from_slice(
+            &(self.plays.unwrap_or(0).min(u16::MAX as u32) as u16).to_le_bytes(),
+        );
+        write_chunk(b"ANIM", &animation);
+
+        for frame in &self.frames {
+            let mut still = Vec::new();
+            image_webp::WebPEncoder::new(&mut still)
+                .encode(frame.buffer(), width, height, image_webp::ColorType::Rgba8)
+                .map_err(to_image_error)?;
+            // the simple format is RIFF header, size and WEBP followed by a single VP8L chunk
+            let mut payload = vec![0; 6]; // frame offset
+            payload.extend_from_slice(&(width - 1).to_le_bytes()[..3]);
+            payload.extend_from_slice(&(height - 1).to_le_bytes()[..3]);
+            payload.extend_from_slice(&delay_ms(frame).min(0xff_ffff).to_le_bytes()[..3]);
+            payload.push(0x02); // replace the canvas instead of blending
+            payload.extend_from_slice(&still[12..]);
+            write_chunk(b"ANMF", &payload);
+        }
+
+        writer.write_all(b"RIFF")?;
+        writer.write_all(&(chunks.len() as u32 + 4).to_le_bytes())?;
+        writer.write_all(b"WEBP")?;
+        writer.write_all(&chunks)?;
+        return Ok(writer.flush()?);
+    }
+}
+
+/// Mosaics every frame of an animation with the same builder and settings.
+pub struct AnimatedMosaic {
+    pub animation: Animation,
+    pub algorithm_type: AlgorithmType,
+    pub tile_side_length: u32,
+    pub alpha_options: AlphaOptions,
+    pub output_scale: OutputScale,
+    pub output_size: Option<(u32, u32)>,
+    pub output_options: OutputOptions,
+}
+
+impl AnimatedMosaic {
+    pub fn new(
+        animation: Animation,
+        algorithm_type: AlgorithmType,
+        tile_side_length: u32,
+    ) -> AnimatedMosaic {
+        return AnimatedMosaic {
+            animation,
+            algorithm_type,
+            tile_side_length,
+            alpha_options: AlphaOptions::default(),
+            output_scale: OutputScale::Full,
+            output_size: None,
+            output_options: OutputOptions::default(),
+        };
+    }
+
+    pub fn with_alpha_options(mut self, alpha_options: AlphaOptions) -> AnimatedMosaic {
+        self.alpha_options = alpha_options;
+        return self;
+    }
+
+    pub fn with_output_options(mut self, output_options: OutputOptions) -> AnimatedMosaic {
+        self.output_options = output_options;
+        return self;
+    }
+
+    pub fn with_output_size(
+        mut self,
+        output_scale: Option<OutputScale>,
+        output_size: Option<(u32, u32)>,
+    ) -> AnimatedMosaic {
+        self.output_scale = output_scale.unwrap_or(OutputScale::Full);
+        self.output_size = output_size;
+        return self;
+    }
+
+    /// Frames are processed in parallel, frames share the canvas size so they are cropped to the same tile grid.
+    pub fn generate_mosaic(&self) -> Animation {
+        let frames = self
+            .animation
+            .frames
+            .par_iter()
+            .map(|frame| {
+                let image = DynamicImage::ImageRgba8(frame.buffer().clone());
+                let mosaic_factory = MosaicFactory::from_image_data(
+                    ImageData::new(&image, self.tile_side_length),
+                    self.algorithm_type.mosaic_builder(),
+                    self.tile_side_length,
+                )
+                .with_alpha_options(self.alpha_options);
+                let (width, height) = self
+                    .output_size
+                    .unwrap_or(mosaic_factory.output_size(self.output_scale));
+                let mosaic = mosaic_factory.generate_mosaic_with_size(width, height);
+                let buffer = RgbaImage::from_raw(width, height, mosaic).unwrap();
+                return Frame::from_parts(buffer, 0, 0, frame.delay());
+            })
+            .collect();
+        return Animation {
+            frames,
+            plays: self.animation.plays,
+        };
+    }
+
+    pub fn generate_and_save_mosaic<P: AsRef<Path>>(
+        &self,
+        output_image_path: P,
+    ) -> ImageResult<()> {
+        return self
+            .generate_mosaic()
+            .save(output_image_path, &self.output_options);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::args::{OutputFormat, PngCompression};
+    use crate::synthetic::{ChannelLayout, Pattern, SyntheticImage};
+    use crate::temp_dir::TempDir;
+    use clap::ValueEnum;
+
+    fn test_animation() -> Animation {
+        let frames = [Pattern::Gradient, Pattern::Checkerboard, Pattern::Noise]
+            .into_iter()
+            .zip([100, 50, 200])
+            .map(|(pattern, delay)| {
+                let image = SyntheticImage::new(pattern, 37, 29, ChannelLayout::Rgba)
+                    .with_seed(delay as u64)
+                    .generate();
+                return Frame::from_parts(
+                    image.to_rgba8(),
+                    0,
+                    0,
+                    Delay::from_numer_denom_ms(delay, 1),
+                );
+            })
+            .collect();
+        return Animation {
+            frames,
+            plays: Some(3),
+        };
+    }
+
+    #[test]
+    fn animations_keep_frames_delays_and_loop_count() {
+        let animation = test_animation();
+        let directory = TempDir::new("animation");
+        for (extension, lossless) in [("gif", false), ("png", true), ("webp", true)] {
+            let path = directory.join(format!("roundtrip.{}", extension));
+            animation.save(&path, &OutputOptions::default()).unwrap();
+            let loaded = Animation::load(&path).unwrap().unwrap();
+
+            assert_eq!(loaded.plays, animation.plays, "{} loop count", extension);
+            assert_eq!(loaded.frames.len(), animation.frames.len(), "{}", extension);
+            for (loaded_frame, frame) in loaded.frames.iter().zip(&animation.frames) {
+                assert_eq!(
+                    delay_ms(loaded_frame),
+                    delay_ms(frame),
+                    "{} delay",
+                    extension
+                );
+                assert_eq!(
+                    loaded_frame.buffer().dimensions(),
+                    frame.buffer().dimensions()
+                );
+                if lossless {
+                    assert!(
+                        loaded_frame.buffer() == frame.buffer(),
+                        "{} pixels",
+                        extension
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn output_options_select_format_and_png_settings() {
+        let animation = test_animation();
+        let directory = TempDir::new("animation_format");
+        let compressed_size = |png_compression| {
+            let path = directory.join("compressed.apng");
+            let output_options = OutputOptions {
+                png_compression,
+                ..OutputOptions::default()
+            };
+            animation.save(&path, &output_options).unwrap();
+            let loaded = Animation::load(&path).unwrap().unwrap();
+            assert_eq!(loaded.frames.len(), animation.frames.len());
+            return std::fs::metadata(&path).unwrap().len();
+        };
+        assert!(compressed_size(PngCompression::Best) <= compressed_size(PngCompression::Fast));
+
+        let path = directory.join("animation.png");
+        let output_options = OutputOptions {
+            format: Some(OutputFormat::Gif),
+            ..OutputOptions::default()
+        };
+        animation.save(&path, &output_options).unwrap();
+        let reader = image::io::Reader::open(&path).unwrap();
+        let format = reader.with_guessed_format().unwrap().format();
+        assert_eq!(format, Some(ImageFormat::Gif));
+
+        let output_options = OutputOptions {
+            format: Some(OutputFormat::Jpeg),
+            ..OutputOptions::default()
+        };
+        let path = directory.join("animation.jpg");
+        assert!(matches!(
+            animation.save(&path, &output_options),
+            Err(ImageError::Unsupported(_))
+        ));
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn frames_match_still_mosaics() {
+        for algorithm_type in AlgorithmType::value_variants() {
+            let animated_mosaic = AnimatedMosaic::new(test_animation(), algorithm_type.clone(), 8);
+            let mosaic = animated_mosaic.generate_mosaic();
+            assert_eq!(mosaic.plays, Some(3));
+            for (mosaic_frame, frame) in mosaic.frames.iter().zip(&animated_mosaic.animation.frames)
+            {
+                let image = DynamicImage::ImageRgba8(frame.buffer().clone());
+                let mosaic_factory = MosaicFactory::from_image_data(
+                    ImageData::new(&image, 8),
+                    algorithm_type.mosaic_builder(),
+                    8,
+                );
+                assert_eq!(mosaic_frame.buffer().dimensions(), (32, 24));
+                assert!(mosaic_frame.buffer().as_raw() == &mosaic_factory.generate_mosaic());
+                assert_eq!(delay_ms(mosaic_frame), delay_ms(frame));
+            }
+        }
+    }
+}
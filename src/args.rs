@@ -1,26 +1,491 @@
-use clap::{Parser, ValueEnum};
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use image::ImageFormat;
+
+use crate::alpha::AlphaOptions;
+use crate::batch::BatchConfig;
+use crate::image_data::Sample;
+use crate::image_io::OutputOptions;
+use crate::mosaic_factory::MosaicBuilder;
+use crate::parallel_mosaic::ParallelMosaic;
+use crate::region_mosaic::Region;
+use crate::serial_mosaic::SerialMosaic;
+use crate::slow_parallel_mosaic::SlowParallelMosaic;
+use crate::sweep::SweepConfig;
 
 #[derive(Parser, Debug)]
 #[clap(author, version, about, long_about = None)]
 pub struct CLIArgs {
-    /// Path to a source image
+    #[command(subcommand)]
+    pub command: Command,
+
+    /// Print only errors and command results
+    #[arg(short, long, global = true, conflicts_with = "verbose")]
+    pub quiet: bool,
+
+    /// Print additional details such as global averages and tile counts
+    #[arg(short, long, global = true)]
+    pub verbose: bool,
+}
+
+// Options selecting config files, read before the rest of the command line. Not a doc comment, clap would use it
+// as the about text of the whole command when the options are added to it.
+#[derive(Args, Debug)]
+pub struct ConfigArgs {
+    /// Config file applied on top of the user-level and project-local config files
+    #[arg(long, global = true)]
+    pub config: Option<String>,
+
+    /// Preset of the config files applied on top of their top-level settings
+    #[arg(long, global = true)]
+    pub preset: Option<String>,
+
+    /// Print the effective settings of the command and where they come from as TOML instead of running it
+    #[arg(long, global = true)]
+    pub print_config: bool,
+}
+
+/// Source image and tiling shared by commands working on a single image
+#[derive(Args, Debug)]
+pub struct InputArgs {
+    /// Path to a source image, - reads an encoded image from stdin
     pub input_image_path: String,
 
     /// Type of algorithm to use in the image processing
     #[arg(value_enum, default_value = "serial")]
     pub algorithm_type: AlgorithmType,
 
-    /// Path to save an output file
+    /// Tile side length in pixels
+    #[arg(short, long, default_value = "32", value_parser = clap::value_parser!(u32).range(1..))]
+    pub tile_side_length: u32,
+
+    /// Page of a multi-page TIFF input to use, pyramidal TIFF files store each resolution level as a page,
+    /// the page is read chunk by chunk without decoding the rest of the file. Without --streaming the whole
+    /// page is held in memory
+    #[arg(long)]
+    pub tiff_page: Option<u32>,
+}
+
+#[derive(Args, Debug)]
+pub struct AlphaArgs {
+    /// How the alpha channel of each tile is written to the output
+    #[arg(long, value_enum, default_value = "keep")]
+    pub alpha_mode: AlphaMode,
+
+    /// Background colour in hex used by the flatten alpha mode
+    #[arg(long, value_parser = parse_hex_color, default_value = "ffffff")]
+    pub alpha_background: [u8; 3],
+
+    /// Minimum tile alpha kept opaque by the threshold alpha mode
+    #[arg(long, default_value = "128")]
+    pub alpha_threshold: u8,
+}
+
+impl AlphaArgs {
+    pub fn alpha_options(&self) -> AlphaOptions {
+        return AlphaOptions {
+            mode: self.alpha_mode,
+            background: self.alpha_background,
+            threshold: self.alpha_threshold,
+        };
+    }
+}
+
+#[derive(Args, Debug)]
+pub struct CorrectnessArgs {
+    /// How builder stages are fed when checking correctness against the serial algorithm
+    #[arg(long, value_enum, default_value = "per-stage")]
+    pub correctness_mode: CorrectnessMode,
+
+    /// Maximum number of mismatching tiles listed for each stage
+    #[arg(long, default_value = "10")]
+    pub max_mismatches: usize,
+}
+
+#[derive(Args, Debug)]
+pub struct GenerateArgs {
+    #[command(flatten)]
+    pub input: InputArgs,
+
+    #[command(flatten)]
+    pub alpha: AlphaArgs,
+
+    /// Path to save an output file, - writes the encoded image to stdout
     #[arg(short, long)]
-    pub output_image_path: Option<String>,
+    pub output_image_path: String,
 
-    /// Tile side length in pixels
-    #[arg(short, long, default_value = "32")]
+    #[command(flatten)]
+    pub output: OutputArgs,
+
+    /// Tone mapping operator used to produce 8-bit output from HDR or OpenEXR input,
+    /// output keeps linear float samples when omitted
+    #[arg(long, value_enum)]
+    pub tone_mapping: Option<ToneMapping>,
+
+    /// Rectangle in x,y,width,height format to pixelate, disables whole image mosaic
+    #[arg(long, value_parser = Region::parse_rectangle)]
+    pub region: Vec<Region>,
+
+    /// Polygon in x1,y1,x2,y2,x3,y3,... format to pixelate, disables whole image mosaic
+    #[arg(long, value_parser = Region::parse_polygon)]
+    pub region_polygon: Vec<Region>,
+
+    /// JSON file with a list of rectangle and polygon regions to pixelate
+    #[arg(long)]
+    pub regions_file: Option<String>,
+
+    /// Mask image of the input size, pixels brighter than mid grey are pixelated
+    #[arg(long)]
+    pub region_mask: Option<String>,
+
+    /// Hardened redaction resistant to depixelation, pixelates the whole image when no region is provided
+    #[arg(long)]
+    pub redact: bool,
+
+    /// Maximum noise added to each channel of a tile average in redaction mode
+    #[arg(long, default_value = "64")]
+    pub redact_noise: u8,
+
+    /// Maximum number of pixels randomly added to tile rows and columns in redaction mode
+    #[arg(long, default_value = "0")]
+    pub redact_tile_jitter: u32,
+
+    /// Seed making redaction output reproducible, weakens redaction if the seed is disclosed
+    #[arg(long)]
+    pub redact_seed: Option<u64>,
+
+    /// Write the output as a zoomable tile pyramid instead of a single image, the output path names
+    /// the DZI manifest or the IIIF directory
+    #[arg(long, value_enum, conflicts_with_all = ["streaming", "output_format", "region", "region_polygon", "regions_file", "region_mask", "redact"])]
+    pub pyramid: Option<PyramidFormat>,
+
+    /// Side length of the pyramid image tiles
+    #[arg(long, default_value = "256", value_parser = clap::value_parser!(u32).range(1..))]
+    pub pyramid_tile_size: u32,
+
+    /// Identifier written to the IIIF info.json, should be the URL the directory is served from,
+    /// defaults to the directory name
+    #[arg(long, requires = "pyramid")]
+    pub iiif_id: Option<String>,
+
+    /// Decode, process and encode the image in bands of tile rows to bound memory use,
+    /// supports PNG and TIFF input and output
+    #[arg(long, conflicts_with_all = ["region", "region_polygon", "regions_file", "region_mask", "redact", "tile_size_mask"])]
+    pub streaming: bool,
+
+    /// Resolution of the rendered mosaic, full repeats each tile average over the tile, tile writes one pixel per
+    /// tile such as an aggregate raster of a GeoTIFF [default: full]
+    #[arg(long, value_enum, conflicts_with_all = ["streaming", "pyramid", "region", "region_polygon", "regions_file", "region_mask", "redact", "tile_size_mask"])]
+    pub output_scale: Option<OutputScale>,
+
+    /// Size of the rendered mosaic in WIDTHxHEIGHT independent of the input size, the grid of tile averages is
+    /// rescaled with nearest neighbour
+    #[arg(long, value_parser = parse_image_size, conflicts_with_all = ["streaming", "pyramid", "output_scale", "region", "region_polygon", "regions_file", "region_mask", "redact", "tile_size_mask"])]
+    pub output_size: Option<(u32, u32)>,
+
+    /// Mosaic a video: the input is a directory of numbered frames, a y4m file or - for y4m on stdin, the output is
+    /// a y4m file, - for y4m on stdout or a directory receiving numbered PNG frames
+    #[arg(long, conflicts_with_all = ["streaming", "pyramid", "region", "region_polygon", "regions_file", "region_mask", "redact", "tile_size_mask", "output_format"])]
+    pub video: bool,
+
+    /// Weight of previous frames in the moving average of tile averages between 0 and 1, higher values reduce
+    /// flicker but make tiles react slower to changes
+    #[arg(long, default_value = "0", value_parser = parse_smoothing, requires = "video")]
+    pub temporal_smoothing: f32,
+
+    /// Write an animation going from tiles of the tile side length to tiles of the reveal target size, the output
+    /// format must be GIF, PNG or WebP
+    #[arg(long, conflicts_with_all = ["streaming", "pyramid", "video", "output_scale", "output_size", "region", "region_polygon", "regions_file", "region_mask", "redact", "tile_size_mask"])]
+    pub reveal: bool,
+
+    /// Tile side length of the last reveal frame, 1 shows the original image
+    #[arg(long, default_value = "1", requires = "reveal")]
+    pub reveal_to: u32,
+
+    /// Number of frames in the reveal animation
+    #[arg(long, default_value = "10", value_parser = clap::value_parser!(u32).range(1..), requires = "reveal")]
+    pub reveal_frames: u32,
+
+    /// Time each reveal frame is shown in milliseconds
+    #[arg(long, default_value = "100", requires = "reveal")]
+    pub reveal_frame_delay: u32,
+
+    /// Time the last reveal frame is shown in milliseconds before the animation loops
+    #[arg(long, default_value = "1000", requires = "reveal")]
+    pub reveal_hold: u32,
+
+    /// Strategy used to choose tile sizes across the image
+    #[arg(long, value_enum, default_value = "uniform")]
+    pub tiling: TilingStrategy,
+
+    /// Grayscale image of the input size controlling local tile size, white areas get the smallest tiles,
+    /// implies mask tiling
+    #[arg(long)]
+    pub tile_size_mask: Option<String>,
+
+    /// Smallest tile side length in pixels used by variable tile size mosaics
+    #[arg(long, default_value = "4", value_parser = clap::value_parser!(u32).range(1..))]
+    pub min_tile_side_length: u32,
+}
+
+#[derive(Args, Debug)]
+pub struct OutputArgs {
+    /// Format of the output image, required when writing to stdout, derived from the output path extension
+    /// when omitted
+    #[arg(long, value_enum)]
+    pub output_format: Option<OutputFormat>,
+
+    /// Quality of JPEG output from 1 to 100
+    #[arg(long, default_value = "75", value_parser = clap::value_parser!(u8).range(1..=100))]
+    pub jpeg_quality: u8,
+
+    /// Compression level of PNG output
+    #[arg(long, value_enum, default_value = "fast")]
+    pub png_compression: PngCompression,
+
+    /// Filter applied to PNG rows before compression
+    #[arg(long, value_enum, default_value = "adaptive")]
+    pub png_filter: PngFilter,
+
+    /// Write PNG output with a palette when it has at most 256 colours, which shrinks flat-colour mosaics
+    #[arg(long)]
+    pub png_palette: bool,
+
+    /// Speed of AVIF encoding from 1 to 10, slower speeds compress better
+    #[arg(long, default_value = "4", value_parser = clap::value_parser!(u8).range(1..=10))]
+    pub avif_speed: u8,
+
+    /// Encode WebP output losslessly, the only mode of the WebP encoder, false fails as lossy WebP is not available
+    #[arg(long, default_value = "true", action = clap::ArgAction::Set)]
+    pub webp_lossless: bool,
+}
+
+impl OutputArgs {
+    pub fn output_options(&self) -> OutputOptions {
+        return OutputOptions {
+            format: self.output_format,
+            jpeg_quality: self.jpeg_quality,
+            png_compression: self.png_compression,
+            png_filter: self.png_filter,
+            png_palette: self.png_palette,
+            avif_speed: self.avif_speed,
+            webp_lossless: self.webp_lossless,
+        };
+    }
+}
+
+#[derive(Args, Debug)]
+pub struct BenchArgs {
+    #[command(flatten)]
+    pub input: InputArgs,
+
+    #[command(flatten)]
+    pub alpha: AlphaArgs,
+
+    #[command(flatten)]
+    pub correctness: CorrectnessArgs,
+
+    /// Number of measured benchmark iterations
+    #[arg(short, long, default_value = "5", value_parser = clap::value_parser!(u32).range(1..))]
+    pub runs: u32,
+
+    /// Number of unmeasured iterations run before benchmarking
+    #[arg(long, default_value = "1")]
+    pub warmup_runs: u32,
+
+    /// Benchmark all algorithms on the same image and compare them side by side
+    #[arg(long)]
+    pub compare_algorithms: bool,
+
+    /// Format of the benchmark report written to the report path
+    #[arg(long, value_enum)]
+    pub report: Option<ReportFormat>,
+
+    /// Path to save the benchmark report, defaults to report with the format extension
+    #[arg(long, requires = "report")]
+    pub report_path: Option<String>,
+}
+
+#[derive(Args, Debug)]
+pub struct VerifyArgs {
+    #[command(flatten)]
+    pub input: InputArgs,
+
+    #[command(flatten)]
+    pub alpha: AlphaArgs,
+
+    #[command(flatten)]
+    pub correctness: CorrectnessArgs,
+
+    /// Check all algorithms instead of the selected one
+    #[arg(long)]
+    pub all_algorithms: bool,
+
+    /// Path to save an image highlighting tiles which differ from the serial algorithm
+    #[arg(long)]
+    pub diff_image_path: Option<String>,
+}
+
+#[derive(Args, Debug)]
+pub struct IndexArgs {
+    #[command(flatten)]
+    pub input: InputArgs,
+
+    #[command(flatten)]
+    pub alpha: AlphaArgs,
+
+    /// Path to save the JSON tile index
+    #[arg(short, long)]
+    pub output_index_path: String,
+}
+
+#[derive(Args, Debug)]
+pub struct InfoArgs {
+    /// Path to a source image
+    pub input_image_path: String,
+
+    /// Tile side length in pixels used to describe the tile grid
+    #[arg(short, long, default_value = "32", value_parser = clap::value_parser!(u32).range(1..))]
     pub tile_side_length: u32,
 
-    /// Number of benchmarks iterations to run
+    /// Page of a multi-page TIFF input to describe
+    #[arg(long)]
+    pub tiff_page: Option<u32>,
+}
+
+#[derive(Args, Debug)]
+pub struct CompareArgs {
+    /// Path to the baseline JSON report
+    pub baseline_report_path: String,
+
+    /// Path to the JSON report compared against the baseline
+    pub candidate_report_path: String,
+
+    /// Maximum allowed increase of the median stage time in percent
+    #[arg(long, default_value = "10")]
+    pub threshold: f64,
+}
+
+#[derive(Args, Debug)]
+pub struct SweepArgs {
+    /// Path to a source image
+    pub input_image_path: String,
+
+    /// Algorithms to benchmark, all of them by default
+    #[arg(long, value_delimiter = ',')]
+    pub algorithms: Vec<AlgorithmType>,
+
+    /// Tile side lengths to benchmark
+    #[arg(long, value_delimiter = ',', default_value = "8,16,32,64", value_parser = clap::value_parser!(u32).range(1..))]
+    pub tile_sizes: Vec<u32>,
+
+    /// Rayon thread counts to benchmark
+    #[arg(long, value_delimiter = ',', default_value = "1,2,4", value_parser = parse_thread_count)]
+    pub threads: Vec<usize>,
+
+    /// Image sizes in WIDTHxHEIGHT format the source image is resized to, original size by default
+    #[arg(long, value_delimiter = ',', value_parser = parse_image_size)]
+    pub sizes: Vec<(u32, u32)>,
+
+    /// Number of measured runs for each combination
+    #[arg(long, default_value = "5", value_parser = clap::value_parser!(u32).range(1..))]
+    pub runs: u32,
+
+    /// Number of unmeasured runs preceding the benchmark of each combination
+    #[arg(long, default_value = "1")]
+    pub warmup_runs: u32,
+
+    /// Path of the SVG chart with stage timings
+    #[arg(long, default_value = "sweep.svg")]
+    pub chart_path: String,
+}
+
+impl SweepArgs {
+    pub fn sweep_config(&self) -> SweepConfig {
+        return SweepConfig {
+            algorithm_types: match self.algorithms.is_empty() {
+                true => AlgorithmType::value_variants().to_vec(),
+                false => self.algorithms.clone(),
+            },
+            tile_side_lengths: self.tile_sizes.clone(),
+            thread_counts: self.threads.clone(),
+            image_sizes: self.sizes.clone(),
+            warmup_runs: self.warmup_runs,
+            benchmark_runs: self.runs,
+        };
+    }
+}
+
+#[derive(Args, Debug)]
+pub struct BatchArgs {
+    /// Image files, directories searched recursively for images or glob patterns with `*`, `?` and `**`
+    #[arg(required = true)]
+    pub inputs: Vec<String>,
+
+    /// Directory the mosaics are written to, subdirectories of directory and glob inputs are kept
     #[arg(short, long)]
-    pub benchmark_runs: Option<u32>,
+    pub output_directory: String,
+
+    /// Output file name, `{stem}`, `{ext}`, `{tile}` and `{algorithm}` are replaced for each image, `{ext}` is
+    /// the extension of the output format when it is given and the input extension otherwise
+    #[arg(long, default_value = "{stem}_{tile}.{ext}")]
+    pub name_template: String,
+
+    /// Algorithm used to generate the mosaics
+    #[arg(short, long, value_enum, default_value_t = AlgorithmType::Parallel)]
+    pub algorithm_type: AlgorithmType,
+
+    /// Side length of the tiles in pixels
+    #[arg(short, long, default_value = "32", value_parser = clap::value_parser!(u32).range(1..))]
+    pub tile_side_length: u32,
+
+    /// Number of images processed at the same time, all CPUs by default
+    #[arg(short, long, value_parser = parse_thread_count)]
+    pub jobs: Option<usize>,
+
+    /// Keep processing the remaining images after a failure instead of stopping
+    #[arg(long)]
+    pub continue_on_error: bool,
+
+    #[command(flatten)]
+    pub alpha: AlphaArgs,
+
+    #[command(flatten)]
+    pub output: OutputArgs,
+}
+
+impl BatchArgs {
+    pub fn batch_config(&self) -> BatchConfig {
+        return BatchConfig {
+            output_directory: self.output_directory.clone().into(),
+            name_template: self.name_template.clone(),
+            algorithm_type: self.algorithm_type.clone(),
+            tile_side_length: self.tile_side_length,
+            jobs: self.jobs,
+            continue_on_error: self.continue_on_error,
+            alpha_options: self.alpha.alpha_options(),
+            output_options: self.output.output_options(),
+        };
+    }
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Generate a mosaic of an image, animation or video
+    Generate(GenerateArgs),
+    /// Benchmark every stage of an algorithm after checking it against the serial algorithm
+    Bench(BenchArgs),
+    /// Check algorithms against the serial algorithm and fail on any mismatch
+    Verify(VerifyArgs),
+    /// Write the grid of tile averages of an image to a JSON file
+    Index(IndexArgs),
+    /// Print the format, size and colour type of an image and its tile grid
+    Info(InfoArgs),
+    /// Compare two JSON benchmark reports and fail if any stage regressed
+    Compare(CompareArgs),
+    /// Benchmark builders across a matrix of tile sizes, thread counts and image sizes
+    Sweep(SweepArgs),
+    /// Generate mosaics of many images in parallel
+    Batch(BatchArgs),
 }
 
 #[derive(Clone, Debug, ValueEnum)]
@@ -29,3 +494,253 @@ pub enum AlgorithmType {
     Parallel,
     SlowParallel,
 }
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+pub enum ToneMapping {
+    Reinhard,
+    Aces,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum AlphaMode {
+    /// Keep the averaged alpha of each tile
+    Keep,
+    /// Composite tiles over the background colour and make them opaque
+    Flatten,
+    /// Make tiles fully opaque or fully transparent depending on the threshold
+    Threshold,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum ReportFormat {
+    Json,
+    Csv,
+    Markdown,
+}
+
+impl ReportFormat {
+    pub fn extension(&self) -> &'static str {
+        return match self {
+            ReportFormat::Json => "json",
+            ReportFormat::Csv => "csv",
+            ReportFormat::Markdown => "md",
+        };
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum CorrectnessMode {
+    /// Each stage of the checked builder gets serial results of the previous stage as input
+    PerStage,
+    /// Stages of the checked builder are chained, errors propagate to later stages like in real runs
+    EndToEnd,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum PyramidFormat {
+    /// Deep Zoom image with an XML manifest and PNG tiles
+    Dzi,
+    /// IIIF Image API 3.0 level 0 static tiles with info.json and JPEG tiles
+    Iiif,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum OutputScale {
+    /// Output of the input size with every tile filled with its average
+    Full,
+    /// One pixel per tile, a box filter downsample of the input
+    Tile,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum TilingStrategy {
+    /// Tiles of the same size across the whole image
+    Uniform,
+    /// Tile size driven by the brightness of the tile size mask
+    Mask,
+    /// Smaller tiles where Sobel gradient energy is high, keeping faces and text legible
+    Edges,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    Png,
+    Jpeg,
+    Gif,
+    Webp,
+    Avif,
+    Tiff,
+    Bmp,
+    Tga,
+    Pnm,
+    Qoi,
+    Hdr,
+    Exr,
+}
+
+impl OutputFormat {
+    pub fn image_format(&self) -> ImageFormat {
+        return match self {
+            OutputFormat::Png => ImageFormat::Png,
+            OutputFormat::Jpeg => ImageFormat::Jpeg,
+            OutputFormat::Gif => ImageFormat::Gif,
+            OutputFormat::Webp => ImageFormat::WebP,
+            OutputFormat::Avif => ImageFormat::Avif,
+            OutputFormat::Tiff => ImageFormat::Tiff,
+            OutputFormat::Bmp => ImageFormat::Bmp,
+            OutputFormat::Tga => ImageFormat::Tga,
+            OutputFormat::Pnm => ImageFormat::Pnm,
+            OutputFormat::Qoi => ImageFormat::Qoi,
+            OutputFormat::Hdr => ImageFormat::Hdr,
+            OutputFormat::Exr => ImageFormat::OpenExr,
+        };
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum PngCompression {
+    Default,
+    Fast,
+    Best,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum PngFilter {
+    None,
+    Sub,
+    Up,
+    Avg,
+    Paeth,
+    /// Picks the filter for each row
+    Adaptive,
+}
+
+impl PngCompression {
+    pub fn compression(&self) -> png::Compression {
+        return match self {
+            PngCompression::Default => png::Compression::Default,
+            PngCompression::Fast => png::Compression::Fast,
+            PngCompression::Best => png::Compression::Best,
+        };
+    }
+}
+
+impl PngFilter {
+    pub fn filter(&self) -> (png::FilterType, png::AdaptiveFilterType) {
+        return match self {
+            PngFilter::None => (
+                png::FilterType::NoFilter,
+                png::AdaptiveFilterType::NonAdaptive,
+            ),
+            PngFilter::Sub => (png::FilterType::Sub, png::AdaptiveFilterType::NonAdaptive),
+            PngFilter::Up => (png::FilterType::Up, png::AdaptiveFilterType::NonAdaptive),
+            PngFilter::Avg => (png::FilterType::Avg, png::AdaptiveFilterType::NonAdaptive),
+            PngFilter::Paeth => (png::FilterType::Paeth, png::AdaptiveFilterType::NonAdaptive),
+            PngFilter::Adaptive => (png::FilterType::Sub, png::AdaptiveFilterType::Adaptive),
+        };
+    }
+}
+
+impl AlgorithmType {
+    pub fn mosaic_builder<S: Sample>(&self) -> Box<dyn MosaicBuilder<S>> {
+        return match self {
+            AlgorithmType::Serial => Box::new(SerialMosaic),
+            AlgorithmType::Parallel => Box::new(ParallelMosaic),
+            AlgorithmType::SlowParallel => Box::new(SlowParallelMosaic),
+        };
+    }
+}
+
+pub fn parse_hex_color(value: &str) -> Result<[u8; 3], String> {
+    let hex = value.trim_start_matches('#');
+    if hex.len() != 6 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(format!("expected colour in rrggbb format, got: {}", value));
+    }
+    let channel = |index: usize| u8::from_str_radix(&hex[index..index + 2], 16).unwrap();
+    return Ok([channel(0), channel(2), channel(4)]);
+}
+
+fn parse_smoothing(value: &str) -> Result<f32, String> {
+    match value.parse::<f32>() {
+        Ok(smoothing) if (0.0..1.0).contains(&smoothing) => return Ok(smoothing),
+        _ => return Err(format!("expected value from 0 up to 1, got: {}", value)),
+    }
+}
+
+fn parse_thread_count(value: &str) -> Result<usize, String> {
+    match value.parse::<usize>() {
+        Ok(threads) if threads > 0 => return Ok(threads),
+        _ => return Err(format!("expected number greater than 0, got: {}", value)),
+    }
+}
+
+fn parse_image_size(value: &str) -> Result<(u32, u32), String> {
+    let size = value.split_once('x').and_then(|(width, height)| {
+        return Some((width.trim().parse().ok()?, height.trim().parse().ok()?));
+    });
+    match size {
+        Some((width, height)) if width > 0 && height > 0 => return Ok((width, height)),
+        _ => {
+            return Err(format!(
+                "expected size in WIDTHxHEIGHT format, got: {}",
+                value
+            ))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::{CommandFactory, Parser};
+
+    use super::*;
+
+    fn parse_sweep(args: &[&str]) -> Result<(Vec<u32>, Vec<usize>, u32), clap::Error> {
+        let cli_args = CLIArgs::try_parse_from(
+            ["mosaic_generator", "sweep", "synthetic:noise:64x64"]
+                .iter()
+                .chain(args),
+        )?;
+        match cli_args.command {
+            Command::Sweep(sweep_args) => {
+                return Ok((sweep_args.tile_sizes, sweep_args.threads, sweep_args.runs))
+            }
+            command => panic!("Expected sweep command, got {:?}", command),
+        }
+    }
+
+    #[test]
+    fn sweep_parses_lists() {
+        assert_eq!(
+            parse_sweep(&[]).unwrap(),
+            (vec![8, 16, 32, 64], vec![1, 2, 4], 5)
+        );
+        assert_eq!(
+            parse_sweep(&["--tile-sizes", "4,12", "--threads", "3", "--runs", "2"]).unwrap(),
+            (vec![4, 12], vec![3], 2)
+        );
+    }
+
+    #[test]
+    fn sweep_rejects_zero_values() {
+        for args in [
+            ["--tile-sizes", "8,0"],
+            ["--threads", "0,2"],
+            ["--runs", "0"],
+        ] {
+            let error = parse_sweep(&args).unwrap_err();
+            assert_eq!(
+                error.kind(),
+                clap::error::ErrorKind::ValueValidation,
+                "{}",
+                error
+            );
+        }
+    }
+
+    #[test]
+    fn config_options_keep_command_about() {
+        let command = ConfigArgs::augment_args(CLIArgs::command());
+        assert_eq!(command.get_about(), CLIArgs::command().get_about());
+    }
+}
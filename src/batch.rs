@@ -0,0 +1,520 @@
+use std::collections::HashMap;
+use std::path::{Component, Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use image::ImageFormat;
+use rayon::prelude::*;
+
+use crate::alpha::AlphaOptions;
+use crate::args::AlgorithmType;
+use crate::image_data::ImageData;
+use crate::image_io::{self, OutputOptions};
+use crate::log;
+use crate::mosaic_factory::MosaicFactory;
+
+pub struct BatchConfig {
+    pub output_directory: PathBuf,
+    /// Output file name with `{stem}`, `{ext}`, `{tile}` and `{algorithm}` placeholders
+    pub name_template: String,
+    pub algorithm_type: AlgorithmType,
+    pub tile_side_length: u32,
+    /// Number of images processed at the same time, all CPUs when `None`
+    pub jobs: Option<usize>,
+    pub continue_on_error: bool,
+    pub alpha_options: AlphaOptions,
+    pub output_options: OutputOptions,
+}
+
+/// Image found in the batch inputs, directories and glob patterns keep the path below their base directory.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BatchInput {
+    pub path: PathBuf,
+    pub relative_directory: PathBuf,
+}
+
+pub struct BatchSummary {
+    pub succeeded: usize,
+    pub skipped: usize,
+    pub failures: Vec<(PathBuf, String)>,
+}
+
+fn is_glob(pattern: &str) -> bool {
+    return pattern.contains(['*', '?']);
+}
+
+/// Matches a single path component against a pattern where `*` matches any run of characters and `?` one character.
+fn matches_component(pattern: &[char], name: &[char]) -> bool {
+    return match pattern.split_first() {
+        None => name.is_empty(),
+        Some(('*', rest)) => (0..=name.len()).any(|skip| matches_component(rest, &name[skip..])),
+        Some(('?', rest)) => !name.is_empty() && matches_component(rest, &name[1..]),
+        Some((c, rest)) => name.first() == Some(c) && matches_component(rest, &name[1..]),
+    };
+}
+
+fn sorted_entries(directory: &Path) -> Vec<PathBuf> {
+    let mut entries: Vec<PathBuf> = match std::fs::read_dir(directory) {
+        Ok(entries) => entries
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .collect(),
+        Err(_) => Vec::new(),
+    };
+    entries.sort();
+    return entries;
+}
+
+/// Expands the remaining pattern components below a directory, `**` matches any number of directories.
+fn expand_glob(directory: &Path, components: &[String], matches: &mut Vec<PathBuf>) {
+    let Some((component, rest)) = components.split_first() else {
+        if directory.is_file() {
+            matches.push(directory.to_path_buf());
+        }
+        return;
+    };
+    if component == "**" {
+        expand_glob(directory, rest, matches);
+        for entry in sorted_entries(directory) {
+            if entry.is_dir() {
+                expand_glob(&entry, components, matches);
+            }
+        }
+        return;
+    }
+    if !is_glob(component) {
+        expand_glob(&directory.join(component), rest, matches);
+        return;
+    }
+    let pattern: Vec<char> = component.chars().collect();
+    for entry in sorted_entries(directory) {
+        let name: Vec<char> = entry
+            .file_name()
+            .unwrap()
+            .to_string_lossy()
+            .chars()
+            .collect();
+        if matches_component(&pattern, &name) {
+            expand_glob(&entry, rest, matches);
+        }
+    }
+}
+
+fn is_image_path(path: &Path) -> bool {
+    return path.is_file() && ImageFormat::from_path(path).is_ok();
+}
+
+fn relative_directory(path: &Path, base: &Path) -> PathBuf {
+    return path
+        .parent()
+        .and_then(|parent| parent.strip_prefix(base).ok())
+        .map(Path::to_path_buf)
+        .unwrap_or_default();
+}
+
+/// Resolves files, directories searched recursively for images and glob patterns into a list of images.
+pub fn collect_inputs(inputs: &[String]) -> Result<Vec<BatchInput>, String> {
+    let mut batch_inputs = Vec::new();
+    for input in inputs {
+        let path = Path::new(input);
+        if is_glob(input) {
+            let components: Vec<String> = path
+                .components()
+                .map(|component| component.as_os_str().to_string_lossy().into_owned())
+                .collect();
+            let fixed = components
+                .iter()
+                .take_while(|component| !is_glob(component))
+                .count();
+            let base: PathBuf = match fixed {
+                0 => PathBuf::from("."),
+                _ => path.components().take(fixed).collect(),
+            };
+            let mut matches = Vec::new();
+            expand_glob(&base, &components[fixed..], &mut matches);
+            if matches.is_empty() {
+                return Err(format!("no files match {}", input));
+            }
+            batch_inputs.extend(matches.into_iter().map(|path| BatchInput {
+                relative_directory: relative_directory(&path, &base),
+                path,
+            }));
+        } else if path.is_dir() {
+            let mut matches = Vec::new();
+            expand_glob(path, &["**".to_string(), "*".to_string()], &mut matches);
+            batch_inputs.extend(
+                matches
+                    .into_iter()
+                    .filter(|entry| is_image_path(entry))
+                    .map(|entry| BatchInput {
+                        relative_directory: relative_directory(&entry, path),
+                        path: entry,
+                    }),
+            );
+        } else if path.is_file() {
+            batch_inputs.push(BatchInput {
+                path: path.to_path_buf(),
+                relative_directory: PathBuf::new(),
+            });
+        } else {
+            return Err(format!("input does not exist: {}", input));
+        }
+    }
+    return Ok(batch_inputs);
+}
+
+/// Output path of an input, `..` components are dropped so that outputs stay inside the output directory.
+pub fn output_path(config: &BatchConfig, input: &BatchInput) -> PathBuf {
+    let stem = input.path.file_stem().unwrap_or_default().to_string_lossy();
+    let extension = match config.output_options.format {
+        Some(output_format) => output_format.image_format().extensions_str()[0].into(),
+        None => input.path.extension().unwrap_or_default().to_string_lossy(),
+    };
+    let name = config
+        .name_template
+        .replace("{stem}", &stem)
+        .replace("{ext}", &extension)
+        .replace("{tile}", &config.tile_side_length.to_string())
+        .replace(
+            "{algorithm}",
+            config.algorithm_type.mosaic_builder::<u8>().name(),
+        );
+    let relative_directory: PathBuf = input
+        .relative_directory
+        .components()
+        .filter(|component| matches!(component, Component::Normal(_)))
+        .collect();
+    return config.output_directory.join(relative_directory).join(name);
+}
+
+/// Generates and saves the mosaic of a single image like the generate command, errors fail only this image.
+fn process_image(config: &BatchConfig, input: &Path, output: &Path) -> Result<(), String> {
+    if config.tile_side_length == 0 {
+        return Err("tile side length must be greater than 0".to_string());
+    }
+    image_io::output_format(output, &config.output_options).map_err(|e| e.to_string())?;
+    let img = image::open(input).map_err(|e| e.to_string())?;
+    if img.width() < config.tile_side_length || img.height() < config.tile_side_length {
+        return Err(format!(
+            "image of {}x{} is smaller than a tile of {}",
+            img.width(),
+            img.height(),
+            config.tile_side_length
+        ));
+    }
+
+    let mosaic_factory = MosaicFactory::from_image_data(
+        ImageData::new(&img, config.tile_side_length),
+        config.algorithm_type.mosaic_builder(),
+        config.tile_side_length,
+    )
+    .with_alpha_options(config.alpha_options)
+    .with_output_options(config.output_options);
+    return mosaic_factory
+        .generate_and_save_mosaic(&output)
+        .map_err(|e| e.to_string());
+}
+
+/// Processes images in parallel. Without `continue_on_error` images not started before the first failure are
+/// skipped. Inputs whose output path is already taken by an earlier input fail instead of overwriting it.
+pub fn run_batch(config: &BatchConfig, inputs: &[BatchInput]) -> BatchSummary {
+    let mut first_input_of_output: HashMap<PathBuf, &Path> = HashMap::new();
+    let jobs: Vec<(&BatchInput, PathBuf, Option<String>)> = inputs
+        .iter()
+        .map(|input| {
+            let output = output_path(config, input);
+            let collision = match first_input_of_output.get(&output) {
+                Some(first_input) => Some(format!(
+                    "output {} is already written for {}",
+                    output.display(),
+                    first_input.display()
+                )),
+                None => {
+                    first_input_of_output.insert(output.clone(), &input.path);
+                    None
+                }
+            };
+            return (input, output, collision);
+        })
+        .collect();
+
+    let thread_pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(config.jobs.unwrap_or(0))
+        .build()
+        .unwrap();
+    let stopped = AtomicBool::new(false);
+    let finished = AtomicUsize::new(0);
+    let results: Vec<Option<Result<(), String>>> = thread_pool.install(|| {
+        return jobs
+            .par_iter()
+            .map(|(input, output, collision)| {
+                if stopped.load(Ordering::Relaxed) {
+                    return None;
+                }
+                let result = match collision {
+                    Some(collision) => Err(collision.clone()),
+                    None => process_image(config, &input.path, output),
+                };
+                if result.is_err() && !config.continue_on_error {
+                    stopped.store(true, Ordering::Relaxed);
+                }
+                let finished = finished.fetch_add(1, Ordering::Relaxed) + 1;
+                match &result {
+                    Ok(_) => log::info!(
+                        "[{}/{}] {} -> {}",
+                        finished,
+                        jobs.len(),
+                        input.path.display(),
+                        output.display()
+                    ),
+                    Err(e) => log::info!(
+                        "[{}/{}] {} failed: {}",
+                        finished,
+                        jobs.len(),
+                        input.path.display(),
+                        e
+                    ),
+                }
+                return Some(result);
+            })
+            .collect();
+    });
+
+    let mut summary = BatchSummary {
+        succeeded: 0,
+        skipped: 0,
+        failures: Vec::new(),
+    };
+    for ((input, _, _), result) in jobs.iter().zip(results) {
+        match result {
+            Some(Ok(_)) => summary.succeeded += 1,
+            Some(Err(e)) => summary.failures.push((input.path.clone(), e)),
+            None => summary.skipped += 1,
+        }
+    }
+    return summary;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::args::{AlphaMode, OutputFormat};
+    use crate::synthetic::{ChannelLayout, Pattern, SyntheticImage};
+    use crate::temp_dir::TempDir;
+
+    fn test_config(output_directory: PathBuf) -> BatchConfig {
+        return BatchConfig {
+            output_directory,
+            name_template: "{stem}_{tile}.{ext}".to_string(),
+            algorithm_type: AlgorithmType::Serial,
+            tile_side_length: 4,
+            jobs: Some(2),
+            continue_on_error: false,
+            alpha_options: AlphaOptions::default(),
+            output_options: OutputOptions::default(),
+        };
+    }
+
+    fn save_image(path: &Path) {
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        SyntheticImage::new(Pattern::Noise, 16, 12, ChannelLayout::Rgb)
+            .generate()
+            .save(path)
+            .unwrap();
+    }
+
+    fn matches(pattern: &str, name: &str) -> bool {
+        let pattern: Vec<char> = pattern.chars().collect();
+        let name: Vec<char> = name.chars().collect();
+        return matches_component(&pattern, &name);
+    }
+
+    #[test]
+    fn wildcards_match_components() {
+        assert!(matches("*.png", "photo.png"));
+        assert!(matches("*.png", ".png"));
+        assert!(!matches("*.png", "photo.jpg"));
+        assert!(matches("img_??.*", "img_01.jpg"));
+        assert!(!matches("img_??.*", "img_1.jpg"));
+        assert!(matches("*a*b*", "xaybz"));
+        assert!(!matches("*a*b*", "xbya"));
+    }
+
+    #[test]
+    fn inputs_keep_subdirectories() {
+        let directory = TempDir::new("batch_inputs");
+        for name in ["a.png", "z.png", "nested/b.png", "nested/deeper/c.jpg"] {
+            save_image(&directory.join(name));
+        }
+        std::fs::write(directory.join("nested/notes.txt"), "not an image").unwrap();
+
+        let relative_directories = |inputs: Vec<BatchInput>| -> Vec<(String, PathBuf)> {
+            return inputs
+                .into_iter()
+                .map(|input| {
+                    let name = input
+                        .path
+                        .file_name()
+                        .unwrap()
+                        .to_string_lossy()
+                        .into_owned();
+                    return (name, input.relative_directory);
+                })
+                .collect();
+        };
+        let expected = vec![
+            ("a.png".to_string(), PathBuf::new()),
+            ("z.png".to_string(), PathBuf::new()),
+            ("b.png".to_string(), PathBuf::from("nested")),
+            ("c.jpg".to_string(), PathBuf::from("nested/deeper")),
+        ];
+        let walked = collect_inputs(&[directory.path().to_string_lossy().into_owned()]).unwrap();
+        assert_eq!(relative_directories(walked), expected);
+
+        let glob = format!("{}/**/*.png", directory.path().display());
+        let globbed = collect_inputs(&[glob]).unwrap();
+        assert_eq!(relative_directories(globbed), expected[..3].to_vec());
+
+        let glob = format!("{}/*/*", directory.path().display());
+        let globbed = collect_inputs(&[glob]).unwrap();
+        assert_eq!(globbed.len(), 2);
+
+        let missing = format!("{}/*.gif", directory.path().display());
+        assert!(collect_inputs(&[missing]).is_err());
+    }
+
+    #[test]
+    fn output_names_follow_template() {
+        let mut config = test_config(PathBuf::from("out"));
+        let input = BatchInput {
+            path: PathBuf::from("photos/trip/beach.jpg"),
+            relative_directory: PathBuf::from("trip"),
+        };
+        assert_eq!(
+            output_path(&config, &input),
+            PathBuf::from("out/trip/beach_4.jpg")
+        );
+        config.name_template = "{algorithm}/{stem}.png".to_string();
+        assert_eq!(
+            output_path(&config, &input),
+            PathBuf::from("out/trip/serial/beach.png")
+        );
+        let input = BatchInput {
+            path: PathBuf::from("beach.jpg"),
+            relative_directory: PathBuf::from("../.."),
+        };
+        assert_eq!(
+            output_path(&config, &input),
+            PathBuf::from("out/serial/beach.png")
+        );
+    }
+
+    #[test]
+    fn failures_are_summarised() {
+        let directory = TempDir::new("batch_failures");
+        for name in ["a.png", "b.png", "c.png", "nested/a.png"] {
+            save_image(&directory.join("in").join(name));
+        }
+        std::fs::write(directory.join("in/broken.png"), "not an image").unwrap();
+        let inputs =
+            collect_inputs(&[directory.join("in").to_string_lossy().into_owned()]).unwrap();
+
+        let mut config = test_config(directory.join("out"));
+        config.continue_on_error = true;
+        let summary = run_batch(&config, &inputs);
+        assert_eq!(summary.succeeded, 4);
+        assert_eq!(summary.skipped, 0);
+        assert_eq!(summary.failures.len(), 1);
+        assert!(summary.failures[0].0.ends_with("broken.png"));
+        assert!(directory.join("out/nested/a_4.png").is_file());
+
+        // without subdirectories both a.png inputs map to the same output
+        config.name_template = "{stem}.png".to_string();
+        config.jobs = Some(1);
+        let flattened: Vec<BatchInput> = inputs
+            .iter()
+            .map(|input| BatchInput {
+                path: input.path.clone(),
+                relative_directory: PathBuf::new(),
+            })
+            .collect();
+        let summary = run_batch(&config, &flattened);
+        assert_eq!(summary.failures.len(), 2);
+        assert!(summary.failures[1].1.contains("already written"));
+
+        config.continue_on_error = false;
+        let summary = run_batch(&config, &flattened);
+        assert_eq!(summary.failures.len(), 1);
+        assert_eq!(summary.succeeded + summary.skipped, 4);
+    }
+
+    #[test]
+    fn images_use_alpha_and_output_options() {
+        let directory = TempDir::new("batch_options");
+        let input = directory.join("in/transparent.png");
+        std::fs::create_dir_all(input.parent().unwrap()).unwrap();
+        SyntheticImage::new(Pattern::Checkerboard, 16, 12, ChannelLayout::Rgba)
+            .generate()
+            .save(&input)
+            .unwrap();
+        let inputs = collect_inputs(&[input.to_string_lossy().into_owned()]).unwrap();
+
+        let mut config = test_config(directory.join("out"));
+        config.alpha_options = AlphaOptions {
+            mode: AlphaMode::Flatten,
+            ..AlphaOptions::default()
+        };
+        config.output_options = OutputOptions {
+            format: Some(OutputFormat::Bmp),
+            ..OutputOptions::default()
+        };
+        let summary = run_batch(&config, &inputs);
+        assert!(summary.failures.is_empty(), "{:?}", summary.failures);
+
+        let output = directory.join("out/transparent_4.bmp");
+        let reader = image::io::Reader::open(&output)
+            .unwrap()
+            .with_guessed_format()
+            .unwrap();
+        assert_eq!(reader.format(), Some(ImageFormat::Bmp));
+        let mosaic = reader.decode().unwrap().to_rgba8();
+        assert!(mosaic.pixels().all(|pixel| pixel[3] == 255));
+    }
+
+    #[test]
+    fn invalid_images_fail_alone() {
+        let directory = TempDir::new("batch_invalid");
+        for name in ["a.png", "c.png"] {
+            save_image(&directory.join("in").join(name));
+        }
+        let png = std::fs::read(directory.join("in/a.png")).unwrap();
+        std::fs::write(directory.join("in/b_truncated.png"), &png[..png.len() / 2]).unwrap();
+        std::fs::write(directory.join("in/b_text.png"), "not an image").unwrap();
+        SyntheticImage::new(Pattern::Noise, 2, 2, ChannelLayout::Rgb)
+            .generate()
+            .save(directory.join("in/b_tiny.png"))
+            .unwrap();
+        let inputs =
+            collect_inputs(&[directory.join("in").to_string_lossy().into_owned()]).unwrap();
+
+        let mut config = test_config(directory.join("out"));
+        config.continue_on_error = true;
+        let summary = run_batch(&config, &inputs);
+        assert_eq!(summary.succeeded, 2);
+        let failed: Vec<&Path> = summary
+            .failures
+            .iter()
+            .map(|(path, _)| path.as_path())
+            .collect();
+        assert_eq!(
+            failed,
+            ["b_text.png", "b_tiny.png", "b_truncated.png"]
+                .map(|name| directory.join("in").join(name))
+        );
+        assert!(summary.failures[1].1.contains("smaller than a tile"));
+        assert!(directory.join("out/a_4.png").is_file());
+        assert!(directory.join("out/c_4.png").is_file());
+
+        config.tile_side_length = 0;
+        let summary = run_batch(&config, &inputs);
+        assert_eq!(summary.failures.len(), inputs.len());
+    }
+}
@@ -0,0 +1,219 @@
+use std::fmt;
+use std::time::Duration;
+
+/// Summary statistics of the samples collected for a single stage
+#[derive(Clone, Debug, PartialEq)]
+pub struct StageStatistics {
+    pub samples: Vec<Duration>,
+    pub mean: Duration,
+    pub median: Duration,
+    pub p95: Duration,
+    pub min: Duration,
+    pub max: Duration,
+    pub std_dev: Duration,
+    /// Number of samples outside of Tukey fences (1.5 interquartile range beyond the quartiles)
+    pub outliers: usize,
+}
+
+impl StageStatistics {
+    pub fn from_samples(samples: Vec<Duration>) -> StageStatistics {
+        if samples.is_empty() {
+            panic!("Cannot calculate statistics without benchmark samples");
+        }
+        let mut sorted = samples.clone();
+        sorted.sort_unstable();
+
+        let seconds: Vec<f64> = samples.iter().map(Duration::as_secs_f64).collect();
+        let mean = seconds.iter().sum::<f64>() / seconds.len() as f64;
+        let variance = match seconds.len() {
+            1 => 0.0,
+            len => {
+                let squares: f64 = seconds.iter().map(|sample| (sample - mean).powi(2)).sum();
+                squares / (len - 1) as f64
+            }
+        };
+
+        let lower_quartile = percentile(&sorted, 25.0).as_secs_f64();
+        let upper_quartile = percentile(&sorted, 75.0).as_secs_f64();
+        let fence = 1.5 * (upper_quartile - lower_quartile);
+        let outliers = seconds
+            .iter()
+            .filter(|&&sample| sample < lower_quartile - fence || sample > upper_quartile + fence)
+            .count();
+
+        return StageStatistics {
+            mean: Duration::from_secs_f64(mean),
+            median: percentile(&sorted, 50.0),
+            p95: percentile(&sorted, 95.0),
+            min: sorted[0],
+            max: sorted[sorted.len() - 1],
+            std_dev: Duration::from_secs_f64(variance.sqrt()),
+            outliers,
+            samples,
+        };
+    }
+}
+
+/// Percentile of sorted samples with linear interpolation between closest ranks.
+fn percentile(sorted: &[Duration], percent: f64) -> Duration {
+    let rank = percent / 100.0 * (sorted.len() - 1) as f64;
+    let lower = sorted[rank.floor() as usize].as_secs_f64();
+    let upper = sorted[rank.ceil() as usize].as_secs_f64();
+    return Duration::from_secs_f64(lower + (upper - lower) * rank.fract());
+}
+
+/// Benchmark results of all stages of a single builder
+#[derive(Clone, Debug, PartialEq)]
+pub struct BenchmarkResults {
+    pub builder_name: String,
+    pub warmup_runs: u32,
+    pub megapixels: f64,
+    pub sum_tile_channels: StageStatistics,
+    pub calc_tile_average: StageStatistics,
+    pub create_mosaic: StageStatistics,
+    pub total: StageStatistics,
+}
+
+impl BenchmarkResults {
+    pub fn stages(&self) -> [(&'static str, &StageStatistics); 4] {
+        return [
+            ("Stage 1", &self.sum_tile_channels),
+            ("Stage 2", &self.calc_tile_average),
+            ("Stage 3", &self.create_mosaic),
+            ("Total", &self.total),
+        ];
+    }
+
+    /// Megapixels processed per second based on the median total time.
+    pub fn throughput(&self) -> f64 {
+        return self.megapixels / self.total.median.as_secs_f64();
+    }
+}
+
+impl fmt::Display for BenchmarkResults {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "{} ({} runs, {} warm-up)",
+            self.builder_name,
+            self.total.samples.len(),
+            self.warmup_runs
+        )?;
+        writeln!(
+            f,
+            "{:<8} {:>12} {:>12} {:>12} {:>12} {:>12} {:>12} {:>8}",
+            "stage", "mean", "median", "p95", "min", "max", "std dev", "outliers"
+        )?;
+        for (name, statistics) in self.stages() {
+            writeln!(
+                f,
+                "{:<8} {:>12} {:>12} {:>12} {:>12} {:>12} {:>12} {:>8}",
+                name,
+                format!("{:.2?}", statistics.mean),
+                format!("{:.2?}", statistics.median),
+                format!("{:.2?}", statistics.p95),
+                format!("{:.2?}", statistics.min),
+                format!("{:.2?}", statistics.max),
+                format!("{:.2?}", statistics.std_dev),
+                statistics.outliers
+            )?;
+        }
+        return write!(f, "Throughput: {:.2} MP/s", self.throughput());
+    }
+}
+
+/// Side by side comparison of median stage times of several builders.
+pub fn comparison_table(results: &[BenchmarkResults]) -> String {
+    let mut table = format!(
+        "{:<16} {:>12} {:>12} {:>12} {:>12} {:>12}\n",
+        "builder", "stage 1", "stage 2", "stage 3", "total", "MP/s"
+    );
+    for result in results {
+        table += &format!(
+            "{:<16} {:>12} {:>12} {:>12} {:>12} {:>12.2}\n",
+            result.builder_name,
+            format!("{:.2?}", result.sum_tile_channels.median),
+            format!("{:.2?}", result.calc_tile_average.median),
+            format!("{:.2?}", result.create_mosaic.median),
+            format!("{:.2?}", result.total.median),
+            result.throughput()
+        );
+    }
+    return table;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn milliseconds(samples: &[u64]) -> Vec<Duration> {
+        return samples
+            .iter()
+            .map(|&ms| Duration::from_millis(ms))
+            .collect();
+    }
+
+    /// Compares durations built from floating point seconds, which may be off by a few nanoseconds.
+    fn assert_duration(actual: Duration, expected_ms: f64) {
+        let error = (actual.as_secs_f64() * 1000.0 - expected_ms).abs();
+        assert!(error < 1e-6, "{:?} != {}ms", actual, expected_ms);
+    }
+
+    #[test]
+    fn odd_samples_use_middle_rank() {
+        let statistics = StageStatistics::from_samples(milliseconds(&[5, 1, 3, 2, 4]));
+        assert_duration(statistics.median, 3.0);
+        assert_duration(statistics.mean, 3.0);
+        assert_duration(statistics.p95, 4.8);
+        assert_duration(statistics.std_dev, 2.5f64.sqrt());
+        assert_eq!(statistics.min, Duration::from_millis(1));
+        assert_eq!(statistics.max, Duration::from_millis(5));
+        assert_eq!(statistics.outliers, 0);
+        assert_eq!(statistics.samples, milliseconds(&[5, 1, 3, 2, 4]));
+    }
+
+    #[test]
+    fn even_samples_interpolate_between_ranks() {
+        let statistics = StageStatistics::from_samples(milliseconds(&[4, 1, 3, 2]));
+        assert_duration(statistics.median, 2.5);
+        assert_duration(statistics.mean, 2.5);
+        assert_duration(statistics.p95, 3.85);
+        assert_eq!(statistics.outliers, 0);
+    }
+
+    #[test]
+    fn single_sample_is_every_statistic() {
+        let statistics = StageStatistics::from_samples(milliseconds(&[7]));
+        for duration in [
+            statistics.mean,
+            statistics.median,
+            statistics.p95,
+            statistics.min,
+            statistics.max,
+        ] {
+            assert_duration(duration, 7.0);
+        }
+        assert_eq!(statistics.std_dev, Duration::ZERO);
+        assert_eq!(statistics.outliers, 0);
+    }
+
+    #[test]
+    fn outliers_are_outside_tukey_fences() {
+        // Quartiles are 11.75ms and 15.25ms, so the fences are at 6.5ms and 20.5ms.
+        let statistics =
+            StageStatistics::from_samples(milliseconds(&[10, 11, 12, 13, 100, 14, 15, 16]));
+        assert_eq!(statistics.outliers, 1);
+        assert_duration(statistics.median, 13.5);
+        assert_eq!(statistics.max, Duration::from_millis(100));
+
+        let statistics =
+            StageStatistics::from_samples(milliseconds(&[6, 10, 11, 12, 13, 14, 15, 20]));
+        assert_eq!(statistics.outliers, 1);
+    }
+
+    #[test]
+    #[should_panic(expected = "without benchmark samples")]
+    fn empty_samples_are_rejected() {
+        StageStatistics::from_samples(Vec::new());
+    }
+}
@@ -0,0 +1,435 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+
+use clap::parser::ValueSource;
+use clap::{Arg, ArgAction, ArgMatches, Command};
+use toml::{Table, Value};
+
+/// Config file looked up in the working directory, overrides the user-level config file
+pub const PROJECT_CONFIG_FILE_NAME: &str = "mosaic_generator.toml";
+
+/// Options handled before parsing, they cannot be set from a config file
+const RESERVED_KEYS: [&str; 5] = ["help", "version", "config", "preset", "print-config"];
+
+/// Settings of a config file. Top-level keys apply to every run and `[presets.NAME]` tables are applied on top of
+/// them with `--preset NAME`. Keys are long option names such as `tile-side-length`, positional arguments use their
+/// name such as `algorithm-type`.
+pub struct ConfigFile {
+    pub path: PathBuf,
+    pub defaults: Table,
+    pub presets: BTreeMap<String, Table>,
+}
+
+/// Option value taken from a config file with a description of where it came from
+#[derive(Clone, Debug, PartialEq)]
+pub struct Setting {
+    pub value: Value,
+    pub origin: String,
+}
+
+impl ConfigFile {
+    pub fn parse(text: &str, path: &Path) -> Result<ConfigFile, String> {
+        let mut defaults: Table = text
+            .parse()
+            .map_err(|e| format!("Error parsing config {}: {}", path.display(), e))?;
+        let presets = match defaults.remove("presets") {
+            None => BTreeMap::new(),
+            Some(Value::Table(presets)) => presets
+                .into_iter()
+                .map(|(name, preset)| match preset {
+                    Value::Table(preset) => return Ok((name, preset)),
+                    _ => {
+                        return Err(format!(
+                            "Preset {} in config {} is not a table",
+                            name,
+                            path.display()
+                        ))
+                    }
+                })
+                .collect::<Result<_, _>>()?,
+            Some(_) => {
+                return Err(format!(
+                    "presets in config {} must be a table of tables",
+                    path.display()
+                ))
+            }
+        };
+        return Ok(ConfigFile {
+            path: path.to_path_buf(),
+            defaults,
+            presets,
+        });
+    }
+
+    /// Reads a config file, a missing file is not an error.
+    pub fn load(path: &Path) -> Result<Option<ConfigFile>, String> {
+        match std::fs::read_to_string(path) {
+            Ok(text) => return ConfigFile::parse(&text, path).map(Some),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(format!("Error reading config {}: {}", path.display(), e)),
+        }
+    }
+}
+
+/// `$XDG_CONFIG_HOME/mosaic_generator/config.toml`, with `%APPDATA%` on Windows and `~/.config` as fallbacks.
+pub fn user_config_path() -> Option<PathBuf> {
+    let config_directory = std::env::var_os("XDG_CONFIG_HOME")
+        .or_else(|| std::env::var_os("APPDATA"))
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+    return Some(
+        config_directory
+            .join("mosaic_generator")
+            .join("config.toml"),
+    );
+}
+
+/// Loads the user-level, project-local and explicitly given config files in increasing precedence.
+pub fn load_config_files(config_path: Option<&str>) -> Result<Vec<ConfigFile>, String> {
+    let mut config_files = Vec::new();
+    let default_paths = user_config_path()
+        .into_iter()
+        .chain([PathBuf::from(PROJECT_CONFIG_FILE_NAME)]);
+    for path in default_paths {
+        config_files.extend(ConfigFile::load(&path)?);
+    }
+    if let Some(config_path) = config_path {
+        match ConfigFile::load(Path::new(config_path))? {
+            Some(config_file) => config_files.push(config_file),
+            None => return Err(format!("Config file does not exist: {}", config_path)),
+        }
+    }
+    return Ok(config_files);
+}
+
+/// Merges top-level settings of all files and then the selected preset of all files, later files win.
+pub fn merge_settings(
+    config_files: &[ConfigFile],
+    preset: Option<&str>,
+) -> Result<BTreeMap<String, Setting>, String> {
+    let mut settings = BTreeMap::new();
+    for config_file in config_files {
+        for (key, value) in &config_file.defaults {
+            let setting = Setting {
+                value: value.clone(),
+                origin: config_file.path.display().to_string(),
+            };
+            settings.insert(key.clone(), setting);
+        }
+    }
+
+    if let Some(preset) = preset {
+        let presets: Vec<(&ConfigFile, &Table)> = config_files
+            .iter()
+            .filter_map(|config_file| {
+                return config_file
+                    .presets
+                    .get(preset)
+                    .map(|table| (config_file, table));
+            })
+            .collect();
+        if presets.is_empty() {
+            let available: BTreeSet<&String> = config_files
+                .iter()
+                .flat_map(|config_file| config_file.presets.keys())
+                .collect();
+            return Err(format!(
+                "Preset {} is not defined, available presets: {:?}",
+                preset, available
+            ));
+        }
+        for (config_file, table) in presets {
+            for (key, value) in table {
+                let setting = Setting {
+                    value: value.clone(),
+                    origin: format!("preset {} in {}", preset, config_file.path.display()),
+                };
+                settings.insert(key.clone(), setting);
+            }
+        }
+    }
+    return Ok(settings);
+}
+
+fn option_key(arg: &Arg) -> String {
+    return match arg.get_long() {
+        Some(long) => long.to_string(),
+        None => arg.get_id().as_str().replace('_', "-"),
+    };
+}
+
+/// Converts a TOML value to the command line values of an option.
+fn option_values(key: &str, value: &Value) -> Result<Vec<String>, String> {
+    return match value {
+        Value::String(value) => Ok(vec![value.clone()]),
+        Value::Integer(value) => Ok(vec![value.to_string()]),
+        Value::Float(value) => Ok(vec![value.to_string()]),
+        Value::Boolean(value) => Ok(vec![value.to_string()]),
+        Value::Array(values) => values
+            .iter()
+            .map(|value| match value {
+                Value::Array(_) | Value::Table(_) => Err(format!("Nested value of {}", key)),
+                value => option_values(key, value).map(|mut values| values.remove(0)),
+            })
+            .collect(),
+        _ => Err(format!("Unsupported value of {}: {}", key, value)),
+    };
+}
+
+fn apply_to_command(
+    mut command: Command,
+    values: &BTreeMap<String, Vec<String>>,
+    used_keys: &mut BTreeSet<String>,
+) -> Command {
+    command = command.mut_args(|arg| {
+        let key = option_key(&arg);
+        if RESERVED_KEYS.contains(&key.as_str()) {
+            return arg;
+        }
+        match values.get(&key) {
+            Some(default_values) => {
+                used_keys.insert(key);
+                return arg.default_values(default_values.clone());
+            }
+            None => return arg,
+        }
+    });
+
+    let subcommand_names: Vec<String> = command
+        .get_subcommands()
+        .map(|subcommand| subcommand.get_name().to_string())
+        .collect();
+    for name in subcommand_names {
+        command = command.mut_subcommand(name, |subcommand| {
+            return apply_to_command(subcommand, values, used_keys);
+        });
+    }
+    return command;
+}
+
+/// Turns settings into default values of the options with the same name in every command, so that options given on
+/// the command line take precedence and values are validated like command line values.
+pub fn apply_settings(
+    command: Command,
+    settings: &BTreeMap<String, Setting>,
+) -> Result<Command, String> {
+    let values: BTreeMap<String, Vec<String>> = settings
+        .iter()
+        .map(|(key, setting)| {
+            return option_values(key, &setting.value)
+                .map(|values| (key.clone(), values))
+                .map_err(|e| format!("{} in {}", e, setting.origin));
+        })
+        .collect::<Result<_, _>>()?;
+    let mut used_keys = BTreeSet::new();
+    let command = apply_to_command(command, &values, &mut used_keys);
+    if let Some((key, setting)) = settings.iter().find(|(key, _)| !used_keys.contains(*key)) {
+        return Err(format!("Unknown option {} in {}", key, setting.origin));
+    }
+    return Ok(command);
+}
+
+fn toml_value(raw: &str, is_flag: bool) -> Value {
+    if is_flag || raw == "true" || raw == "false" {
+        return Value::Boolean(raw == "true");
+    }
+    if let Ok(value) = raw.parse::<i64>() {
+        return Value::Integer(value);
+    }
+    if let Ok(value) = raw.parse::<f64>() {
+        return Value::Float(value);
+    }
+    return Value::String(raw.to_string());
+}
+
+/// Effective options of the invoked command as TOML usable as a preset, each line notes where the value came from.
+pub fn render_effective_settings(
+    command: &Command,
+    matches: &ArgMatches,
+    settings: &BTreeMap<String, Setting>,
+) -> String {
+    let (command, matches) = match matches.subcommand() {
+        Some((name, subcommand_matches)) => match command.find_subcommand(name) {
+            Some(subcommand) => (subcommand, subcommand_matches),
+            None => (command, matches),
+        },
+        None => (command, matches),
+    };
+
+    let mut rendered = format!("# effective settings of {}\n", command.get_name());
+    for arg in command.get_arguments() {
+        let key = option_key(arg);
+        if RESERVED_KEYS.contains(&key.as_str()) {
+            continue;
+        }
+        let Some(raw_values) = matches.get_raw(arg.get_id().as_str()) else {
+            continue;
+        };
+        let is_flag = matches!(arg.get_action(), ArgAction::SetTrue | ArgAction::SetFalse);
+        let values: Vec<Value> = raw_values
+            .map(|raw| toml_value(&raw.to_string_lossy(), is_flag))
+            .collect();
+        let value = match arg.get_action() {
+            ArgAction::Append => Value::Array(values),
+            _ => values.into_iter().next().unwrap(),
+        };
+        let origin = match (
+            matches.value_source(arg.get_id().as_str()),
+            settings.get(&key),
+        ) {
+            (Some(ValueSource::CommandLine), _) => "command line",
+            (_, Some(setting)) => setting.origin.as_str(),
+            _ => "default",
+        };
+        rendered += &format!("{} = {} # {}\n", key, value, origin);
+    }
+    return rendered;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::args::{AlgorithmType, CLIArgs, Command as CLICommand};
+    use clap::{CommandFactory, FromArgMatches};
+
+    const USER_CONFIG: &str = r#"
+tile-side-length = 16
+alpha-mode = "flatten"
+
+[presets.print]
+tile-side-length = 8
+algorithm-type = "parallel"
+region = ["0,0,10,10", "5,5,10,10"]
+"#;
+
+    const PROJECT_CONFIG: &str = r#"
+alpha-background = "000000"
+
+[presets.print]
+tile-side-length = 4
+"#;
+
+    fn config_files() -> Vec<ConfigFile> {
+        return vec![
+            ConfigFile::parse(USER_CONFIG, Path::new("user.toml")).unwrap(),
+            ConfigFile::parse(PROJECT_CONFIG, Path::new("project.toml")).unwrap(),
+        ];
+    }
+
+    fn parse(settings: &BTreeMap<String, Setting>, args: &[&str]) -> CLIArgs {
+        let command = apply_settings(CLIArgs::command(), settings).unwrap();
+        let matches = command
+            .try_get_matches_from(["mosaic_generator"].iter().chain(args))
+            .unwrap();
+        return CLIArgs::from_arg_matches(&matches).unwrap();
+    }
+
+    #[test]
+    fn later_files_and_presets_take_precedence() {
+        let settings = merge_settings(&config_files(), None).unwrap();
+        assert_eq!(settings["tile-side-length"].value, Value::Integer(16));
+        assert_eq!(settings["alpha-background"].origin, "project.toml");
+
+        let settings = merge_settings(&config_files(), Some("print")).unwrap();
+        assert_eq!(settings["tile-side-length"].value, Value::Integer(4));
+        assert_eq!(
+            settings["tile-side-length"].origin,
+            "preset print in project.toml"
+        );
+        assert_eq!(settings["alpha-mode"].origin, "user.toml");
+
+        let error = merge_settings(&config_files(), Some("poster"))
+            .err()
+            .unwrap();
+        assert!(error.contains("\"print\""), "{}", error);
+    }
+
+    #[test]
+    fn command_line_overrides_settings() {
+        let settings = merge_settings(&config_files(), Some("print")).unwrap();
+        let CLICommand::Generate(generate_args) =
+            parse(&settings, &["generate", "image.png", "-o", "out.png"]).command
+        else {
+            panic!("expected generate command");
+        };
+        assert_eq!(generate_args.input.tile_side_length, 4);
+        assert!(matches!(
+            generate_args.input.algorithm_type,
+            AlgorithmType::Parallel
+        ));
+        assert_eq!(generate_args.alpha.alpha_background, [0, 0, 0]);
+        assert_eq!(generate_args.region.len(), 2);
+
+        let CLICommand::Generate(generate_args) = parse(
+            &settings,
+            &[
+                "generate",
+                "image.png",
+                "serial",
+                "-o",
+                "out.png",
+                "-t",
+                "12",
+            ],
+        )
+        .command
+        else {
+            panic!("expected generate command");
+        };
+        assert_eq!(generate_args.input.tile_side_length, 12);
+        assert!(matches!(
+            generate_args.input.algorithm_type,
+            AlgorithmType::Serial
+        ));
+
+        // options are applied to every command having them
+        let CLICommand::Info(info_args) = parse(&settings, &["info", "image.png"]).command else {
+            panic!("expected info command");
+        };
+        assert_eq!(info_args.tile_side_length, 4);
+    }
+
+    #[test]
+    fn invalid_settings_are_rejected() {
+        let config_file = ConfigFile::parse("tile-size = 16", Path::new("typo.toml")).unwrap();
+        let settings = merge_settings(&[config_file], None).unwrap();
+        let error = apply_settings(CLIArgs::command(), &settings).err().unwrap();
+        assert_eq!(error, "Unknown option tile-size in typo.toml");
+
+        assert!(ConfigFile::parse("presets = 1", Path::new("presets.toml")).is_err());
+        assert!(ConfigFile::parse("[presets]\nprint = 1", Path::new("presets.toml")).is_err());
+    }
+
+    #[test]
+    fn effective_settings_name_their_origin() {
+        let settings = merge_settings(&config_files(), None).unwrap();
+        let command = apply_settings(CLIArgs::command(), &settings).unwrap();
+        let matches = command
+            .clone()
+            .try_get_matches_from([
+                "mosaic_generator",
+                "generate",
+                "image.png",
+                "-o",
+                "out.png",
+                "--streaming",
+            ])
+            .unwrap();
+        let rendered = render_effective_settings(&command, &matches, &settings);
+        assert!(rendered.starts_with("# effective settings of generate\n"));
+        assert!(
+            rendered.contains("\ntile-side-length = 16 # user.toml\n"),
+            "{}",
+            rendered
+        );
+        assert!(rendered.contains("\nalgorithm-type = \"serial\" # default\n"));
+        assert!(rendered.contains("\nstreaming = true # command line\n"));
+
+        // rendered settings can be read back as a config file
+        let rendered_config = ConfigFile::parse(&rendered, Path::new("rendered.toml")).unwrap();
+        assert_eq!(
+            rendered_config.defaults["alpha-mode"],
+            Value::from("flatten")
+        );
+    }
+}
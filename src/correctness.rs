@@ -0,0 +1,390 @@
+use std::fmt;
+
+use crate::args::CorrectnessMode;
+use crate::mosaic_factory::MosaicBuilder;
+use crate::{MosaicFactory, SerialMosaic};
+
+/// Single value differing from the serial implementation
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Mismatch {
+    /// Tile coordinates in tiles, missing for the global average
+    pub tile: Option<(u32, u32)>,
+    /// Pixel coordinates, present only for the mosaic stage
+    pub pixel: Option<(u32, u32)>,
+    pub channel: u8,
+    pub expected: u64,
+    pub actual: u64,
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some((tile_x, tile_y)) = self.tile {
+            write!(f, "tile ({}, {}) ", tile_x, tile_y)?;
+        }
+        if let Some((x, y)) = self.pixel {
+            write!(f, "pixel ({}, {}) ", x, y)?;
+        }
+        return write!(
+            f,
+            "channel {}: expected {}, actual {}",
+            self.channel, self.expected, self.actual
+        );
+    }
+}
+
+/// Comparison of a single stage output with the serial implementation
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StageDiagnostics {
+    pub stage: &'static str,
+    /// Number of differing values, values missing from the shorter output are included
+    pub mismatches: usize,
+    pub max_absolute_error: u64,
+    /// Output lengths as `(expected, actual)` when they differ
+    pub length_mismatch: Option<(usize, usize)>,
+    /// First mismatching values, at most one per tile
+    pub first_mismatches: Vec<Mismatch>,
+}
+
+/// Correctness of a builder compared with the serial implementation
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CorrectnessDiagnostics {
+    pub builder_name: String,
+    pub mode: CorrectnessMode,
+    pub sum_tile_channels: StageDiagnostics,
+    pub calc_tile_average: StageDiagnostics,
+    pub create_mosaic: StageDiagnostics,
+    pub global_average: StageDiagnostics,
+    /// Tiles with any mismatching value in row-major order
+    pub wrong_tiles: Vec<bool>,
+}
+
+impl CorrectnessDiagnostics {
+    pub fn stages(&self) -> [&StageDiagnostics; 4] {
+        return [
+            &self.sum_tile_channels,
+            &self.calc_tile_average,
+            &self.create_mosaic,
+            &self.global_average,
+        ];
+    }
+
+    pub fn is_correct(&self) -> bool {
+        return self.stages().iter().all(|stage| stage.mismatches == 0);
+    }
+
+    pub fn max_absolute_error(&self) -> u64 {
+        return self
+            .stages()
+            .iter()
+            .map(|stage| stage.max_absolute_error)
+            .max()
+            .unwrap_or(0);
+    }
+
+    /// RGB mosaic of the serial implementation dimmed to grey with wrong tiles painted red.
+    pub fn diff_image(&self, mosaic_factory: &MosaicFactory) -> Vec<u8> {
+        let tile_sum = SerialMosaic.sum_tile_channels(mosaic_factory);
+        let tile_average = SerialMosaic.calc_tile_average(mosaic_factory, &tile_sum);
+        let expected_mosaic = SerialMosaic.create_mosaic(mosaic_factory, &tile_average.0);
+        let channels = mosaic_factory.image_data.channels as usize;
+        let width = mosaic_factory.image_data.width;
+        return expected_mosaic
+            .chunks_exact(channels)
+            .enumerate()
+            .flat_map(|(index, pixel)| {
+                let (x, y) = (index as u32 % width, index as u32 / width);
+                let tile_x = x / mosaic_factory.tile_side_length;
+                let tile_y = y / mosaic_factory.tile_side_length;
+                if self.wrong_tiles[(tile_y * mosaic_factory.tiles_x + tile_x) as usize] {
+                    return [255, 0, 0];
+                }
+                let color_channels = if channels == 2 || channels == 4 {
+                    channels - 1
+                } else {
+                    channels
+                };
+                let grey = pixel[..color_channels]
+                    .iter()
+                    .map(|&value| value as u32)
+                    .sum::<u32>()
+                    / color_channels as u32;
+                let dimmed = (64 + grey / 4) as u8;
+                return [dimmed, dimmed, dimmed];
+            })
+            .collect();
+    }
+}
+
+impl fmt::Display for CorrectnessDiagnostics {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mode = match self.mode {
+            CorrectnessMode::PerStage => "per-stage",
+            CorrectnessMode::EndToEnd => "end-to-end",
+        };
+        let verdict = match self.is_correct() {
+            true => "matches serial",
+            false => "differs from serial",
+        };
+        write!(
+            f,
+            "{} correctness ({}): {}",
+            self.builder_name, mode, verdict
+        )?;
+        for stage in self.stages() {
+            write!(
+                f,
+                "\n{} incorrect values: {}, max absolute error: {}",
+                stage.stage, stage.mismatches, stage.max_absolute_error
+            )?;
+            if let Some((expected, actual)) = stage.length_mismatch {
+                write!(f, "\n  expected {} values, got {}", expected, actual)?;
+            }
+            for mismatch in &stage.first_mismatches {
+                write!(f, "\n  {}", mismatch)?;
+            }
+        }
+        return Ok(());
+    }
+}
+
+/// Runs a builder and the serial implementation on the image of the factory and compares every stage.
+pub fn check_builder(
+    mosaic_factory: &MosaicFactory,
+    mosaic_builder: &dyn MosaicBuilder,
+    mode: CorrectnessMode,
+    max_mismatches: usize,
+) -> CorrectnessDiagnostics {
+    let serial_stage_1 = SerialMosaic.sum_tile_channels(mosaic_factory);
+    let serial_stage_2 = SerialMosaic.calc_tile_average(mosaic_factory, &serial_stage_1);
+    let serial_stage_3 = SerialMosaic.create_mosaic(mosaic_factory, &serial_stage_2.0);
+
+    let new_stage_1 = mosaic_builder.sum_tile_channels(mosaic_factory);
+    let new_stage_2 = match mode {
+        CorrectnessMode::PerStage => {
+            mosaic_builder.calc_tile_average(mosaic_factory, &serial_stage_1)
+        }
+        CorrectnessMode::EndToEnd => mosaic_builder.calc_tile_average(mosaic_factory, &new_stage_1),
+    };
+    let new_stage_3 = match mode {
+        CorrectnessMode::PerStage => {
+            mosaic_builder.create_mosaic(mosaic_factory, &serial_stage_2.0)
+        }
+        CorrectnessMode::EndToEnd => mosaic_builder.create_mosaic(mosaic_factory, &new_stage_2.0),
+    };
+
+    let channels = mosaic_factory.image_data.channels as usize;
+    let tiles_x = mosaic_factory.tiles_x;
+    let width = mosaic_factory.image_data.width;
+    let tile_side_length = mosaic_factory.tile_side_length;
+    let locate_tile = |index: usize| {
+        let tile = (index / channels) as u32;
+        return (
+            Some((tile % tiles_x, tile / tiles_x)),
+            None,
+            (index % channels) as u8,
+        );
+    };
+    let locate_pixel = |index: usize| {
+        let pixel = (index / channels) as u32;
+        let (x, y) = (pixel % width, pixel / width);
+        return (
+            Some((x / tile_side_length, y / tile_side_length)),
+            Some((x, y)),
+            (index % channels) as u8,
+        );
+    };
+
+    let mut wrong_tiles = vec![false; (mosaic_factory.tiles_x * mosaic_factory.tiles_y) as usize];
+    return CorrectnessDiagnostics {
+        builder_name: mosaic_builder.name().to_string(),
+        mode,
+        sum_tile_channels: compare_stage(
+            "Stage 1",
+            &serial_stage_1,
+            &new_stage_1,
+            max_mismatches,
+            tiles_x,
+            &mut wrong_tiles,
+            locate_tile,
+        ),
+        calc_tile_average: compare_stage(
+            "Stage 2",
+            &serial_stage_2.0,
+            &new_stage_2.0,
+            max_mismatches,
+            tiles_x,
+            &mut wrong_tiles,
+            locate_tile,
+        ),
+        create_mosaic: compare_stage(
+            "Stage 3",
+            &serial_stage_3,
+            &new_stage_3,
+            max_mismatches,
+            tiles_x,
+            &mut wrong_tiles,
+            locate_pixel,
+        ),
+        global_average: compare_stage(
+            "Global average",
+            &serial_stage_2.1,
+            &new_stage_2.1,
+            max_mismatches,
+            tiles_x,
+            &mut wrong_tiles,
+            |index| (None, None, index as u8),
+        ),
+        wrong_tiles,
+    };
+}
+
+/// Compares stage output with the serial output. `locate` maps a value index to its tile and pixel
+/// coordinates and channel, tiles of mismatching values are marked in `wrong_tiles`.
+fn compare_stage<T: Copy + Into<u64>>(
+    stage: &'static str,
+    expected: &[T],
+    actual: &[T],
+    max_mismatches: usize,
+    tiles_x: u32,
+    wrong_tiles: &mut [bool],
+    locate: impl Fn(usize) -> (Option<(u32, u32)>, Option<(u32, u32)>, u8),
+) -> StageDiagnostics {
+    let mut mismatches = 0;
+    let mut max_absolute_error = 0;
+    let mut first_mismatches: Vec<Mismatch> = Vec::new();
+
+    for (index, (&expected_value, &actual_value)) in expected.iter().zip(actual).enumerate() {
+        let (expected_value, actual_value) = (expected_value.into(), actual_value.into());
+        if expected_value == actual_value {
+            continue;
+        }
+        mismatches += 1;
+        max_absolute_error = max_absolute_error.max(expected_value.abs_diff(actual_value));
+
+        let (tile, pixel, channel) = locate(index);
+        if let Some((tile_x, tile_y)) = tile {
+            wrong_tiles[(tile_y * tiles_x + tile_x) as usize] = true;
+        }
+        let tile_listed = tile.is_some()
+            && first_mismatches
+                .iter()
+                .any(|mismatch| mismatch.tile == tile);
+        if !tile_listed && first_mismatches.len() < max_mismatches {
+            first_mismatches.push(Mismatch {
+                tile,
+                pixel,
+                channel,
+                expected: expected_value,
+                actual: actual_value,
+            });
+        }
+    }
+
+    let length_mismatch =
+        (expected.len() != actual.len()).then_some((expected.len(), actual.len()));
+    if let Some((expected_length, actual_length)) = length_mismatch {
+        mismatches += expected_length.abs_diff(actual_length);
+    }
+    return StageDiagnostics {
+        stage,
+        mismatches,
+        max_absolute_error,
+        length_mismatch,
+        first_mismatches,
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::synthetic::{ChannelLayout, Pattern, SyntheticImage};
+    use crate::ImageData;
+
+    /// Serial builder with a wrong sum in the first channel of tile (1, 0).
+    struct BrokenSumMosaic;
+
+    impl MosaicBuilder for BrokenSumMosaic {
+        fn name(&self) -> &'static str {
+            return "broken-sum";
+        }
+
+        fn sum_tile_channels(&self, mosaic_factory: &MosaicFactory) -> Vec<u64> {
+            let mut tile_sum = SerialMosaic.sum_tile_channels(mosaic_factory);
+            let channels = mosaic_factory.image_data.channels as usize;
+            tile_sum[channels] += 10 * mosaic_factory.tile_pixels as u64;
+            return tile_sum;
+        }
+
+        fn calc_tile_average(
+            &self,
+            mosaic_factory: &MosaicFactory,
+            tile_sum: &[u64],
+        ) -> (Vec<u8>, Vec<u8>) {
+            return SerialMosaic.calc_tile_average(mosaic_factory, tile_sum);
+        }
+
+        fn create_mosaic(&self, mosaic_factory: &MosaicFactory, tile_average: &[u8]) -> Vec<u8> {
+            return SerialMosaic.create_mosaic(mosaic_factory, tile_average);
+        }
+    }
+
+    fn mosaic_factory() -> MosaicFactory {
+        let img = SyntheticImage::new(Pattern::Solid, 32, 16, ChannelLayout::Rgb).generate();
+        return MosaicFactory::from_image_data(ImageData::new(&img, 8), Box::new(SerialMosaic), 8);
+    }
+
+    #[test]
+    fn per_stage_mode_isolates_broken_stage() {
+        let diagnostics = check_builder(
+            &mosaic_factory(),
+            &BrokenSumMosaic,
+            CorrectnessMode::PerStage,
+            10,
+        );
+        assert_eq!(diagnostics.sum_tile_channels.mismatches, 1);
+        assert_eq!(diagnostics.calc_tile_average.mismatches, 0);
+        assert_eq!(diagnostics.create_mosaic.mismatches, 0);
+        assert_eq!(
+            diagnostics.sum_tile_channels.first_mismatches,
+            vec![Mismatch {
+                tile: Some((1, 0)),
+                pixel: None,
+                channel: 0,
+                expected: 128 * 64,
+                actual: 138 * 64,
+            }]
+        );
+    }
+
+    #[test]
+    fn end_to_end_mode_propagates_errors() {
+        let mosaic_factory = mosaic_factory();
+        let diagnostics = check_builder(
+            &mosaic_factory,
+            &BrokenSumMosaic,
+            CorrectnessMode::EndToEnd,
+            10,
+        );
+        assert_eq!(diagnostics.calc_tile_average.mismatches, 1);
+        assert_eq!(diagnostics.calc_tile_average.max_absolute_error, 10);
+        assert_eq!(diagnostics.create_mosaic.mismatches, 64);
+        assert_eq!(diagnostics.create_mosaic.first_mismatches.len(), 1);
+        assert_eq!(
+            diagnostics.create_mosaic.first_mismatches[0].pixel,
+            Some((8, 0))
+        );
+        assert_eq!(
+            diagnostics
+                .wrong_tiles
+                .iter()
+                .filter(|&&wrong| wrong)
+                .count(),
+            1
+        );
+        assert!(!diagnostics.is_correct());
+
+        let diff_image = diagnostics.diff_image(&mosaic_factory);
+        assert_eq!(&diff_image[8 * 3..9 * 3], &[255, 0, 0]);
+        assert_ne!(&diff_image[..3], &[255, 0, 0]);
+    }
+}
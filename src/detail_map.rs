@@ -0,0 +1,151 @@
+use rayon::prelude::*;
+
+use crate::ImageData;
+
+/// Converts pixels to luma, alpha is ignored.
+fn luma(image_data: &ImageData) -> Vec<f32> {
+    let channels = image_data.channels as usize;
+    return image_data
+        .data
+        .par_chunks_exact(channels)
+        .map(|pixel| match channels {
+            1 | 2 => pixel[0] as f32,
+            _ => 0.2126 * pixel[0] as f32 + 0.7152 * pixel[1] as f32 + 0.0722 * pixel[2] as f32,
+        })
+        .collect();
+}
+
+/// Sobel gradient magnitude of each pixel, borders are clamped.
+fn sobel(values: &[f32], width: usize, height: usize) -> Vec<f32> {
+    let at = |x: isize, y: isize| -> f32 {
+        let x = x.clamp(0, width as isize - 1) as usize;
+        let y = y.clamp(0, height as isize - 1) as usize;
+        return values[y * width + x];
+    };
+
+    return (0..width * height)
+        .into_par_iter()
+        .map(|index| {
+            let (x, y) = ((index % width) as isize, (index / width) as isize);
+            let gradient_x = at(x + 1, y - 1) + 2.0 * at(x + 1, y) + at(x + 1, y + 1)
+                - at(x - 1, y - 1)
+                - 2.0 * at(x - 1, y)
+                - at(x - 1, y + 1);
+            let gradient_y = at(x - 1, y + 1) + 2.0 * at(x, y + 1) + at(x + 1, y + 1)
+                - at(x - 1, y - 1)
+                - 2.0 * at(x, y - 1)
+                - at(x + 1, y - 1);
+            return (gradient_x * gradient_x + gradient_y * gradient_y).sqrt();
+        })
+        .collect();
+}
+
+/// Box blur implemented as two separable passes over summed rows and columns.
+fn box_blur(values: &[f32], width: usize, height: usize, radius: usize) -> Vec<f32> {
+    fn blur_line(line: &[f32], radius: usize) -> Vec<f32> {
+        let mut prefix = vec![0.0; line.len() + 1];
+        for (index, value) in line.iter().enumerate() {
+            prefix[index + 1] = prefix[index] + value;
+        }
+        return (0..line.len())
+            .map(|index| {
+                let start = index.saturating_sub(radius);
+                let end = (index + radius + 1).min(line.len());
+                return (prefix[end] - prefix[start]) / (end - start) as f32;
+            })
+            .collect();
+    }
+
+    let rows: Vec<f32> = values
+        .par_chunks_exact(width)
+        .flat_map_iter(|row| blur_line(row, radius))
+        .collect();
+    let columns: Vec<Vec<f32>> = (0..width)
+        .into_par_iter()
+        .map(|x| {
+            let column: Vec<f32> = (0..height).map(|y| rows[y * width + x]).collect();
+            return blur_line(&column, radius);
+        })
+        .collect();
+    return (0..width * height)
+        .map(|index| columns[index % width][index / width])
+        .collect();
+}
+
+/// Local detail from Sobel gradient energy, smoothed over `radius` pixels and scaled so the 95th
+/// percentile maps to full detail.
+pub fn edge_detail(image_data: &ImageData, radius: u32) -> Vec<u8> {
+    let (width, height) = (image_data.width as usize, image_data.height as usize);
+    let gradient = sobel(&luma(image_data), width, height);
+    let energy = box_blur(&gradient, width, height, radius as usize);
+
+    let mut sorted = energy.clone();
+    sorted.par_sort_unstable_by(|a, b| a.total_cmp(b));
+    let scale = match sorted.get(sorted.len() * 95 / 100) {
+        Some(&percentile) if percentile > 0.0 => 255.0 / percentile,
+        _ => 0.0,
+    };
+
+    return energy
+        .par_iter()
+        .map(|value| (value * scale).min(255.0) as u8)
+        .collect();
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{DynamicImage, GrayImage, Luma};
+
+    use super::*;
+    use crate::adaptive_mosaic::AdaptiveMosaic;
+    use crate::args::AlgorithmType;
+    use crate::synthetic::{ChannelLayout, Pattern, SyntheticImage};
+
+    /// Grayscale image which is black left of `edge_x` and white from it.
+    fn step_edge(width: u32, height: u32, edge_x: u32) -> ImageData {
+        let img = GrayImage::from_fn(width, height, |x, _| {
+            return Luma([if x < edge_x { 0 } else { 255 }]);
+        });
+        return ImageData::new(&DynamicImage::ImageLuma8(img), 1);
+    }
+
+    #[test]
+    fn flat_image_has_no_detail() {
+        let img = SyntheticImage::new(Pattern::Solid, 32, 16, ChannelLayout::Rgb).generate();
+        let detail = edge_detail(&ImageData::new(&img, 1), 2);
+        assert_eq!(detail, vec![0; 32 * 16]);
+    }
+
+    #[test]
+    fn step_edge_peaks_on_edge_columns() {
+        let detail = edge_detail(&step_edge(16, 8, 8), 1);
+        for row in detail.chunks_exact(16) {
+            let peak = *row.iter().max().unwrap();
+            assert_eq!(peak, 255);
+            assert_eq!((row[7], row[8]), (peak, peak));
+            assert!(row[6] < peak && row[9] < peak, "{:?}", row);
+            assert!(row[..5].iter().chain(&row[11..]).all(|&value| value == 0));
+        }
+    }
+
+    #[test]
+    fn detail_selects_tile_sizes() {
+        let image_data = step_edge(64, 32, 32);
+        let detail = edge_detail(&image_data, 4);
+        let adaptive_mosaic = AdaptiveMosaic::new(
+            image_data,
+            AlgorithmType::Serial.mosaic_builder(),
+            16,
+            4,
+            detail,
+        );
+        let tiles = adaptive_mosaic.tiles();
+        for &(x, _, width, _) in &tiles {
+            if !(16..48).contains(&x) {
+                assert_eq!(width, 16);
+            }
+        }
+        assert!(tiles.iter().any(|&(x, _, width, _)| x < 32 && width == 4));
+        assert!(tiles.iter().any(|&(x, _, width, _)| x >= 32 && width == 4));
+    }
+}
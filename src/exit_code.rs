@@ -0,0 +1,39 @@
+/// Process exit code of a failed check: `verify` found mismatches, `compare` found a regression or `batch` images
+/// failed, kept stable so that scripts can tell failures apart
+pub const CHECK_FAILED: i32 = 1;
+/// Invalid command line arguments
+pub const USAGE: i32 = 2;
+/// The command could not complete, for example because the input could not be read or the output written
+pub const ERROR: i32 = 3;
+
+/// Error ending a command with the exit code it is reported with
+#[derive(Debug)]
+pub struct CommandError {
+    pub exit_code: i32,
+    pub message: String,
+}
+
+impl CommandError {
+    /// Options which cannot be used together with the input, reported like invalid command line arguments.
+    pub fn usage(message: impl Into<String>) -> CommandError {
+        return CommandError {
+            exit_code: USAGE,
+            message: message.into(),
+        };
+    }
+}
+
+impl From<String> for CommandError {
+    fn from(message: String) -> CommandError {
+        return CommandError {
+            exit_code: ERROR,
+            message,
+        };
+    }
+}
+
+impl From<&str> for CommandError {
+    fn from(message: &str) -> CommandError {
+        return CommandError::from(message.to_string());
+    }
+}
@@ -0,0 +1,327 @@
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter};
+use std::path::Path;
+
+use image::ColorType;
+use tiff::decoder::Decoder;
+use tiff::encoder::{colortype, TiffEncoder};
+use tiff::tags::Tag;
+
+/// GeoTIFF key storing whether raster coordinates refer to pixel corners or centres.
+const RASTER_TYPE_GEO_KEY: u16 = 1025;
+const RASTER_PIXEL_IS_POINT: u16 = 2;
+
+fn to_io_error(e: tiff::TiffError) -> io::Error {
+    return io::Error::other(e.to_string());
+}
+
+/// Georeferencing tags of a GeoTIFF page. Geo keys and parameters are copied unchanged, the tiepoints, pixel scale
+/// and transformation mapping raster to model coordinates are updated when the raster is cropped or resampled.
+#[derive(Clone, Debug, PartialEq)]
+pub struct GeoReference {
+    pub width: u32,
+    pub height: u32,
+    pub pixel_scale: Option<Vec<f64>>,
+    pub tiepoints: Option<Vec<f64>>,
+    pub transformation: Option<Vec<f64>>,
+    pub geo_key_directory: Vec<u16>,
+    pub geo_double_params: Option<Vec<f64>>,
+    pub geo_ascii_params: Option<String>,
+}
+
+impl GeoReference {
+    pub fn is_tiff_path<P: AsRef<Path>>(path: P) -> bool {
+        return path.as_ref().extension().is_some_and(|extension| {
+            let extension = extension.to_string_lossy().to_lowercase();
+            return extension == "tif" || extension == "tiff";
+        });
+    }
+
+    /// Reads georeferencing of a TIFF page, returns `None` for files that are not GeoTIFF.
+    pub fn read<P: AsRef<Path>>(path: P, page: u32) -> io::Result<Option<GeoReference>> {
+        if !GeoReference::is_tiff_path(&path) {
+            return Ok(None);
+        }
+        let mut decoder =
+            Decoder::new(BufReader::new(File::open(path.as_ref())?)).map_err(to_io_error)?;
+        if page > 0 {
+            decoder.seek_to_image(page as usize).map_err(to_io_error)?;
+        }
+
+        let geo_key_directory = match decoder
+            .find_tag(Tag::GeoKeyDirectoryTag)
+            .map_err(to_io_error)?
+        {
+            Some(value) => value.into_u16_vec().map_err(to_io_error)?,
+            None => return Ok(None),
+        };
+        let mut find_f64_vec = |tag: Tag| -> io::Result<Option<Vec<f64>>> {
+            return match decoder.find_tag(tag).map_err(to_io_error)? {
+                Some(value) => Ok(Some(value.into_f64_vec().map_err(to_io_error)?)),
+                None => Ok(None),
+            };
+        };
+        let pixel_scale = find_f64_vec(Tag::ModelPixelScaleTag)?;
+        let tiepoints = find_f64_vec(Tag::ModelTiepointTag)?;
+        let transformation = find_f64_vec(Tag::ModelTransformationTag)?;
+        let geo_double_params = find_f64_vec(Tag::GeoDoubleParamsTag)?;
+        let geo_ascii_params = match decoder
+            .find_tag(Tag::GeoAsciiParamsTag)
+            .map_err(to_io_error)?
+        {
+            Some(value) => Some(value.into_string().map_err(to_io_error)?),
+            None => None,
+        };
+        if tiepoints.is_none() && transformation.is_none() {
+            return Ok(None);
+        }
+
+        let (width, height) = decoder.dimensions().map_err(to_io_error)?;
+        return Ok(Some(GeoReference {
+            width,
+            height,
+            pixel_scale,
+            tiepoints,
+            transformation,
+            geo_key_directory,
+            geo_double_params,
+            geo_ascii_params,
+        }));
+    }
+
+    /// Whether raster coordinates address pixel centres rather than pixel corners.
+    fn is_pixel_is_point(&self) -> bool {
+        // header of four values followed by (key, location, count, value) entries
+        return self
+            .geo_key_directory
+            .get(4..)
+            .unwrap_or_default()
+            .chunks_exact(4)
+            .any(|entry| {
+                return entry[0] == RASTER_TYPE_GEO_KEY
+                    && entry[1] == 0
+                    && entry[3] == RASTER_PIXEL_IS_POINT;
+            });
+    }
+
+    /// Georeferencing of the centred crop to `tile_side_length` multiples, resampled to the output size. Output
+    /// pixel `(u', v')` covers the input area starting at `(margin_x + u' * scale_x, margin_y + v' * scale_y)`.
+    pub fn resampled(
+        &self,
+        tile_side_length: u32,
+        output_width: u32,
+        output_height: u32,
+    ) -> GeoReference {
+        let cropped_width = (self.width / tile_side_length) * tile_side_length;
+        let cropped_height = (self.height / tile_side_length) * tile_side_length;
+        let scale_x = cropped_width as f64 / output_width as f64;
+        let scale_y = cropped_height as f64 / output_height as f64;
+        let mut offset_x = ((self.width - cropped_width) / 2) as f64;
+        let mut offset_y = ((self.height - cropped_height) / 2) as f64;
+        if self.is_pixel_is_point() {
+            offset_x += (scale_x - 1.0) / 2.0;
+            offset_y += (scale_y - 1.0) / 2.0;
+        }
+
+        let pixel_scale = self.pixel_scale.as_ref().map(|pixel_scale| {
+            let mut pixel_scale = pixel_scale.clone();
+            pixel_scale[0] *= scale_x;
+            pixel_scale[1] *= scale_y;
+            return pixel_scale;
+        });
+        // tiepoints are (i, j, k, x, y, z) records tying raster coordinates to model coordinates
+        let tiepoints = self.tiepoints.as_ref().map(|tiepoints| {
+            let mut tiepoints = tiepoints.clone();
+            for tiepoint in tiepoints.chunks_exact_mut(6) {
+                tiepoint[0] = (tiepoint[0] - offset_x) / scale_x;
+                tiepoint[1] = (tiepoint[1] - offset_y) / scale_y;
+            }
+            // a single tiepoint with a pixel scale is moved back to the raster origin, model y grows upwards
+            if let (6, Some(pixel_scale)) = (tiepoints.len(), &pixel_scale) {
+                tiepoints[3] -= tiepoints[0] * pixel_scale[0];
+                tiepoints[4] += tiepoints[1] * pixel_scale[1];
+                tiepoints[0] = 0.0;
+                tiepoints[1] = 0.0;
+            }
+            return tiepoints;
+        });
+        // row-major 4x4 matrix applied to (i, j, k, 1)
+        let transformation = self.transformation.as_ref().map(|transformation| {
+            let mut transformation = transformation.clone();
+            for row in transformation.chunks_exact_mut(4) {
+                row[3] += row[0] * offset_x + row[1] * offset_y;
+                row[0] *= scale_x;
+                row[1] *= scale_y;
+            }
+            return transformation;
+        });
+
+        return GeoReference {
+            width: output_width,
+            height: output_height,
+            pixel_scale,
+            tiepoints,
+            transformation,
+            ..self.clone()
+        };
+    }
+
+    /// Saves an 8-bit image with this georeferencing.
+    pub fn save_tiff<P: AsRef<Path>>(
+        &self,
+        path: P,
+        color: ColorType,
+        data: &[u8],
+    ) -> io::Result<()> {
+        if let Some(parent) = path.as_ref().parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let file = BufWriter::new(File::create(path)?);
+        let mut encoder = TiffEncoder::new(file).map_err(to_io_error)?;
+        return match color {
+            ColorType::L8 => self.save_tiff_image::<colortype::Gray8>(&mut encoder, data),
+            ColorType::Rgb8 => self.save_tiff_image::<colortype::RGB8>(&mut encoder, data),
+            ColorType::Rgba8 => self.save_tiff_image::<colortype::RGBA8>(&mut encoder, data),
+            _ => Err(io::Error::other(format!(
+                "GeoTIFF output does not support {:?} images",
+                color
+            ))),
+        };
+    }
+
+    fn save_tiff_image<C: colortype::ColorType<Inner = u8>>(
+        &self,
+        encoder: &mut TiffEncoder<BufWriter<File>>,
+        data: &[u8],
+    ) -> io::Result<()> {
+        let mut image = encoder
+            .new_image::<C>(self.width, self.height)
+            .map_err(to_io_error)?;
+        let directory = image.encoder();
+        directory
+            .write_tag(Tag::GeoKeyDirectoryTag, self.geo_key_directory.as_slice())
+            .map_err(to_io_error)?;
+        for (tag, values) in [
+            (Tag::ModelPixelScaleTag, &self.pixel_scale),
+            (Tag::ModelTiepointTag, &self.tiepoints),
+            (Tag::ModelTransformationTag, &self.transformation),
+            (Tag::GeoDoubleParamsTag, &self.geo_double_params),
+        ] {
+            if let Some(values) = values {
+                directory
+                    .write_tag(tag, values.as_slice())
+                    .map_err(to_io_error)?;
+            }
+        }
+        if let Some(geo_ascii_params) = &self.geo_ascii_params {
+            directory
+                .write_tag(Tag::GeoAsciiParamsTag, geo_ascii_params.as_str())
+                .map_err(to_io_error)?;
+        }
+        return image.write_data(data).map_err(to_io_error);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::temp_dir::TempDir;
+
+    fn geo_keys(pixel_is_point: bool) -> Vec<u16> {
+        let raster_type = if pixel_is_point { 2 } else { 1 };
+        return vec![1, 1, 0, 2, 1024, 0, 1, 1, 1025, 0, 1, raster_type];
+    }
+
+    /// Model coordinates of a raster position, from the transformation or the first tiepoint and pixel scale.
+    fn model_coordinates(geo_reference: &GeoReference, i: f64, j: f64) -> (f64, f64) {
+        if let Some(m) = &geo_reference.transformation {
+            return (m[0] * i + m[1] * j + m[3], m[4] * i + m[5] * j + m[7]);
+        }
+        let tiepoint = geo_reference.tiepoints.as_ref().unwrap();
+        let pixel_scale = geo_reference.pixel_scale.as_ref().unwrap();
+        return (
+            tiepoint[3] + (i - tiepoint[0]) * pixel_scale[0],
+            tiepoint[4] - (j - tiepoint[1]) * pixel_scale[1],
+        );
+    }
+
+    fn assert_close(actual: (f64, f64), expected: (f64, f64)) {
+        assert!(
+            (actual.0 - expected.0).abs() < 1e-6 && (actual.1 - expected.1).abs() < 1e-6,
+            "{:?} != {:?}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn resampling_follows_crop_and_tile_size() {
+        let tiepoint_reference = GeoReference {
+            width: 103,
+            height: 57,
+            pixel_scale: Some(vec![2.0, 3.0, 0.0]),
+            tiepoints: Some(vec![4.0, 5.0, 0.0, 500000.0, 4000000.0, 0.0]),
+            transformation: None,
+            geo_key_directory: geo_keys(false),
+            geo_double_params: None,
+            geo_ascii_params: Some("WGS 84 / UTM zone 33N|".to_string()),
+        };
+        let transformation_reference = GeoReference {
+            pixel_scale: None,
+            tiepoints: None,
+            transformation: Some(vec![
+                2.0, 0.5, 0.0, 500000.0, 0.25, -3.0, 0.0, 4000000.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0,
+                0.0, 1.0,
+            ]),
+            ..tiepoint_reference.clone()
+        };
+
+        for geo_reference in [tiepoint_reference, transformation_reference] {
+            for pixel_is_point in [false, true] {
+                let geo_reference = GeoReference {
+                    geo_key_directory: geo_keys(pixel_is_point),
+                    ..geo_reference.clone()
+                };
+                // 103x57 cropped to 100x50 with margins (1, 3), one output pixel per 10x10 tile
+                let resampled = geo_reference.resampled(10, 10, 5);
+                assert_eq!((resampled.width, resampled.height), (10, 5));
+                assert_eq!(resampled.geo_ascii_params, geo_reference.geo_ascii_params);
+                let centre_offset = if pixel_is_point { 4.5 } else { 0.0 };
+                for (u, v) in [(0.0, 0.0), (3.0, 2.0), (9.0, 4.0)] {
+                    assert_close(
+                        model_coordinates(&resampled, u, v),
+                        model_coordinates(
+                            &geo_reference,
+                            1.0 + u * 10.0 + centre_offset,
+                            3.0 + v * 10.0 + centre_offset,
+                        ),
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn saved_georeferencing_is_read_back() {
+        let geo_reference = GeoReference {
+            width: 3,
+            height: 2,
+            pixel_scale: Some(vec![20.0, 20.0, 0.0]),
+            tiepoints: Some(vec![0.0, 0.0, 0.0, 500000.0, 4000000.0, 0.0]),
+            transformation: None,
+            geo_key_directory: geo_keys(false),
+            geo_double_params: Some(vec![6378137.0]),
+            geo_ascii_params: Some("WGS 84|".to_string()),
+        };
+        let directory = TempDir::new("geotiff");
+        let path = directory.join("georeferenced.tif");
+        let data: Vec<u8> = (0..18).collect();
+        geo_reference
+            .save_tiff(&path, ColorType::Rgb8, &data)
+            .unwrap();
+
+        assert_eq!(GeoReference::read(&path, 0).unwrap(), Some(geo_reference));
+        let image = image::open(&path).unwrap();
+        assert_eq!(image.as_bytes(), data.as_slice());
+    }
+}
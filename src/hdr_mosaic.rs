@@ -0,0 +1,224 @@
+use std::path::Path;
+
+use image::{ImageFormat, ImageResult};
+use rayon::prelude::*;
+
+use crate::args::ToneMapping;
+use crate::image_data::{ImageData, Sample};
+use crate::image_io;
+use crate::mosaic_factory::{MosaicBuilder, MosaicFactory};
+
+/// Checks whether the path points to a format holding linear float samples.
+pub fn is_hdr_path<P: AsRef<Path>>(path: P) -> bool {
+    return matches!(
+        ImageFormat::from_path(path),
+        Ok(ImageFormat::Hdr | ImageFormat::OpenExr)
+    );
+}
+
+/// Mosaics of high dynamic range images averaged in linear float
+impl MosaicFactory<f32> {
+    pub fn new_hdr<P: AsRef<Path>>(
+        input_image_path: P,
+        mosaic_builder: Box<dyn MosaicBuilder<f32>>,
+        tile_side_length: u32,
+    ) -> ImageResult<MosaicFactory<f32>> {
+        let image_data = ImageData::from_hdr_path(input_image_path, tile_side_length)?;
+        return Ok(MosaicFactory::from_image_data(
+            image_data,
+            mosaic_builder,
+            tile_side_length,
+        ));
+    }
+
+    /// Generates the mosaic at any size and saves it as HDR/EXR or, when tone mapping is selected, as an 8-bit
+    /// image.
+    pub fn generate_and_save_hdr_mosaic<P: AsRef<Path>>(
+        &self,
+        output_img_path: &P,
+        width: u32,
+        height: u32,
+        tone_mapping: Option<ToneMapping>,
+    ) -> ImageResult<()> {
+        let img = self.generate_mosaic_with_size(width, height);
+        let Some(tone_mapping) = tone_mapping else {
+            let format = image_io::output_format(output_img_path.as_ref(), &self.output_options)?;
+            if !matches!(format, ImageFormat::Hdr | ImageFormat::OpenExr) {
+                return Err(image_io::parameter_error(
+                    "HDR output requires HDR or OpenEXR format, select --tone-mapping for 8-bit output",
+                ));
+            }
+            return image_io::save_buffer(
+                output_img_path,
+                &f32::as_bytes(&img),
+                width,
+                height,
+                self.image_data.color,
+                &self.output_options,
+            );
+        };
+
+        let color = match self.image_data.channels {
+            4 => image::ColorType::Rgba8,
+            _ => image::ColorType::Rgb8,
+        };
+        let tone_mapped = tone_map(&img, self.image_data.channels, tone_mapping);
+        return image_io::save_buffer(
+            output_img_path,
+            &tone_mapped,
+            width,
+            height,
+            color,
+            &self.output_options,
+        );
+    }
+}
+
+fn reinhard(value: f32) -> f32 {
+    return value / (1.0 + value);
+}
+
+// Narkowicz fit of the ACES filmic reference rendering transform
+fn aces(value: f32) -> f32 {
+    let (a, b, c, d, e) = (2.51, 0.03, 2.43, 0.59, 0.14);
+    return (value * (a * value + b)) / (value * (c * value + d) + e);
+}
+
+fn linear_to_srgb(value: f32) -> f32 {
+    if value <= 0.003_130_8 {
+        return value * 12.92;
+    }
+    return 1.055 * value.powf(1.0 / 2.4) - 0.055;
+}
+
+/// Maps linear radiance to display referred sRGB bytes, alpha is only clamped.
+pub fn tone_map(img: &[f32], channels: u8, tone_mapping: ToneMapping) -> Vec<u8> {
+    let curve: fn(f32) -> f32 = match tone_mapping {
+        ToneMapping::Reinhard => reinhard,
+        ToneMapping::Aces => aces,
+    };
+
+    return img
+        .par_iter()
+        .enumerate()
+        .map(|(index, &value)| {
+            let value = value.max(0.0);
+            let is_alpha = channels == 4 && index % 4 == 3;
+            let display_value = if is_alpha {
+                value
+            } else {
+                linear_to_srgb(curve(value).clamp(0.0, 1.0))
+            };
+            return (display_value.clamp(0.0, 1.0) * 255.0).round() as u8;
+        })
+        .collect();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::args::AlgorithmType;
+    use crate::temp_dir::TempDir;
+    use clap::ValueEnum;
+
+    /// Float image of 8x4 pixels with values above 1 and two tiles of 4 pixels.
+    fn hdr_image_data(channels: u8) -> ImageData<f32> {
+        let data = (0..8 * 4 * channels as u32)
+            .map(|index| (index % 7) as f32 * 0.75)
+            .collect();
+        return ImageData {
+            width: 8,
+            height: 4,
+            channels,
+            data,
+            color: match channels {
+                4 => image::ColorType::Rgba32F,
+                _ => image::ColorType::Rgb32F,
+            },
+        };
+    }
+
+    #[test]
+    fn curves_compress_highlights() {
+        assert_eq!(reinhard(0.0), 0.0);
+        assert_eq!(reinhard(1.0), 0.5);
+        assert!(reinhard(1e6) < 1.0);
+        assert_eq!(aces(0.0), 0.0);
+        assert!((aces(1.0) - 0.803_797).abs() < 1e-5);
+        // the fit overshoots 1 for very bright input, tone mapping clamps it
+        assert!(aces(1e6) > 1.0);
+        assert_eq!(linear_to_srgb(0.0), 0.0);
+        assert!((linear_to_srgb(1.0) - 1.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn tone_map_writes_srgb_bytes_and_clamps_alpha() {
+        let img = [0.0, 1.0, 4.0, 0.5, -1.0, 1e6, 1.0, 2.0];
+        assert_eq!(
+            tone_map(&img, 4, ToneMapping::Reinhard),
+            vec![0, 188, 231, 128, 0, 255, 188, 255]
+        );
+        assert_eq!(
+            tone_map(&img[..6], 3, ToneMapping::Aces),
+            vec![0, 232, 252, 206, 0, 255]
+        );
+    }
+
+    #[test]
+    fn builders_agree_on_float_samples() {
+        let mosaics: Vec<Vec<f32>> = AlgorithmType::value_variants()
+            .iter()
+            .map(|algorithm_type| {
+                return MosaicFactory::from_image_data(
+                    hdr_image_data(4),
+                    algorithm_type.mosaic_builder(),
+                    4,
+                )
+                .generate_mosaic();
+            })
+            .collect();
+        assert!(mosaics.windows(2).all(|pair| pair[0] == pair[1]));
+        assert!(mosaics[0].iter().any(|&value| value > 1.0));
+    }
+
+    #[test]
+    fn hdr_and_exr_round_trip() {
+        let directory = TempDir::new("hdr");
+        for (extension, channels, tolerance) in [("exr", 4, 0.0), ("exr", 3, 0.0), ("hdr", 3, 0.01)]
+        {
+            let mosaic_factory = MosaicFactory::from_image_data(
+                hdr_image_data(channels),
+                Box::new(crate::SerialMosaic),
+                4,
+            );
+            let path = directory.join(format!("mosaic_{}.{}", channels, extension));
+            mosaic_factory
+                .generate_and_save_hdr_mosaic(&path, 8, 4, None)
+                .unwrap();
+
+            let expected = mosaic_factory.generate_mosaic();
+            let loaded = ImageData::from_hdr_path(&path, 1).unwrap();
+            assert_eq!((loaded.width, loaded.height), (8, 4));
+            assert_eq!(loaded.channels, channels, "{}", extension);
+            for (&actual, &expected) in loaded.data.iter().zip(&expected) {
+                assert!(
+                    (actual - expected).abs() <= expected * tolerance,
+                    "{}: {} != {}",
+                    extension,
+                    actual,
+                    expected
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn float_output_requires_hdr_format() {
+        let mosaic_factory =
+            MosaicFactory::from_image_data(hdr_image_data(3), Box::new(crate::SerialMosaic), 4);
+        let error = mosaic_factory
+            .generate_and_save_hdr_mosaic(&"mosaic.png", 8, 4, None)
+            .unwrap_err();
+        assert!(error.to_string().contains("--tone-mapping"));
+    }
+}
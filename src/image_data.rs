@@ -1,20 +1,235 @@
+use std::borrow::Cow;
+use std::fmt;
+use std::io;
+use std::ops::AddAssign;
 use std::path::Path;
 
 use image::io::Reader as ImageReader;
-use image::{DynamicImage, GenericImageView, ImageError};
+use image::{
+    DynamicImage, GenericImageView, GrayAlphaImage, GrayImage, ImageFormat, ImageResult, RgbImage,
+    RgbaImage,
+};
+
+use crate::image_io;
+use crate::streaming::RowSource;
+use crate::synthetic::SyntheticImage;
+use crate::tiff_reader::TiffRowSource;
+
+/// Channel sample of image data, tiles are summed in `Sum` and averaged back to the sample type
+pub trait Sample: Copy + Default + PartialOrd + Send + Sync + fmt::Debug + 'static {
+    type Sum: Copy + Default + AddAssign + std::iter::Sum + Send + Sync + fmt::Debug;
+
+    /// Sample value of a fully opaque alpha channel
+    const MAX: Self;
+
+    fn to_sum(self) -> Self::Sum;
+
+    /// Colour sample multiplied by the alpha of its pixel.
+    fn weighted(self, alpha: Self) -> Self::Sum;
+
+    /// Sum of samples divided by the number of samples or the summed alpha, a zero divisor gives zero.
+    fn average(sum: Self::Sum, divisor: Self::Sum) -> Self;
+
+    fn count(samples: u64) -> Self::Sum;
+
+    /// Converts an 8-bit value, 255 maps to `MAX`.
+    fn from_u8(value: u8) -> Self;
+
+    /// Composites the sample with the given coverage over a background sample.
+    fn composite(self, background: Self, alpha: Self) -> Self;
+
+    /// Samples of an image with the colour type they are stored in.
+    fn from_image(img: DynamicImage) -> (image::ColorType, Vec<Self>);
+
+    /// Samples as the raw bytes expected by image encoders.
+    fn as_bytes(samples: &[Self]) -> Cow<'_, [u8]>;
+}
+
+impl Sample for u8 {
+    type Sum = u64;
+
+    const MAX: u8 = 255;
+
+    fn to_sum(self) -> u64 {
+        return self as u64;
+    }
+
+    fn weighted(self, alpha: u8) -> u64 {
+        return self as u64 * alpha as u64;
+    }
+
+    fn average(sum: u64, divisor: u64) -> u8 {
+        return match divisor {
+            0 => 0,
+            _ => (sum / divisor) as u8,
+        };
+    }
+
+    fn count(samples: u64) -> u64 {
+        return samples;
+    }
+
+    fn from_u8(value: u8) -> u8 {
+        return value;
+    }
+
+    fn composite(self, background: u8, alpha: u8) -> u8 {
+        let (value, background, alpha) = (self as u32, background as u32, alpha as u32);
+        return ((value * alpha + background * (255 - alpha) + 127) / 255) as u8;
+    }
+
+    fn from_image(img: DynamicImage) -> (image::ColorType, Vec<u8>) {
+        return (img.color(), img.into_bytes());
+    }
+
+    fn as_bytes(samples: &[u8]) -> Cow<'_, [u8]> {
+        return Cow::Borrowed(samples);
+    }
+}
+
+/// Linear samples of Radiance HDR and OpenEXR images, alpha ranges from 0 to 1
+impl Sample for f32 {
+    type Sum = f64;
+
+    const MAX: f32 = 1.0;
+
+    fn to_sum(self) -> f64 {
+        return self as f64;
+    }
+
+    fn weighted(self, alpha: f32) -> f64 {
+        return self as f64 * alpha as f64;
+    }
+
+    fn average(sum: f64, divisor: f64) -> f32 {
+        if divisor == 0.0 {
+            return 0.0;
+        }
+        return (sum / divisor) as f32;
+    }
+
+    fn count(samples: u64) -> f64 {
+        return samples as f64;
+    }
+
+    fn from_u8(value: u8) -> f32 {
+        return value as f32 / 255.0;
+    }
+
+    fn composite(self, background: f32, alpha: f32) -> f32 {
+        return self * alpha + background * (1.0 - alpha);
+    }
+
+    fn from_image(img: DynamicImage) -> (image::ColorType, Vec<f32>) {
+        return match img.color().has_alpha() {
+            true => (image::ColorType::Rgba32F, img.into_rgba32f().into_raw()),
+            false => (image::ColorType::Rgb32F, img.into_rgb32f().into_raw()),
+        };
+    }
+
+    fn as_bytes(samples: &[f32]) -> Cow<'_, [u8]> {
+        return Cow::Owned(
+            samples
+                .iter()
+                .flat_map(|sample| sample.to_ne_bytes())
+                .collect(),
+        );
+    }
+}
 
 #[derive(Clone, Debug, PartialEq, Eq)]
-pub struct ImageData {
+pub struct ImageData<S = u8> {
     pub width: u32,
     pub height: u32,
     pub channels: u8,
-    pub data: Vec<u8>,
+    pub data: Vec<S>,
     pub color: image::ColorType,
 }
 
+impl<S: Sample> ImageData<S> {
+    /// Crops the image to a multiple of the tile side length and converts it to samples of this type.
+    pub fn from_image(img: &DynamicImage, tile_side_length: u32) -> ImageData<S> {
+        let cropped_img = ImageData::crop_image(img, tile_side_length);
+        let (width, height) = (cropped_img.width(), cropped_img.height());
+        let (color, data) = S::from_image(cropped_img);
+        return ImageData {
+            width,
+            height,
+            channels: color.channel_count(),
+            data,
+            color,
+        };
+    }
+
+    /// Averages pixels of an area, colour is weighted by alpha when present.
+    pub fn area_average(&self, x: u32, y: u32, width: u32, height: u32) -> Vec<S> {
+        let channels = self.channels as usize;
+        let has_alpha = crate::alpha::has_alpha(self);
+        let mut sum: Vec<S::Sum> = vec![S::Sum::default(); channels];
+
+        for row in y..y + height {
+            let row_start = ((row * self.width + x) as usize) * channels;
+            let row_end = row_start + width as usize * channels;
+            for pixel in self.data[row_start..row_end].chunks_exact(channels) {
+                for (channel, channel_sum) in sum.iter_mut().enumerate() {
+                    *channel_sum += crate::alpha::weighted_sample(pixel, channel, has_alpha);
+                }
+            }
+        }
+
+        let pixels = (width * height) as u64;
+        return (0..channels)
+            .map(|channel| crate::alpha::channel_average(&sum, channel, pixels, has_alpha))
+            .collect();
+    }
+
+    /// Fills an area of a buffer laid out like this image with a single pixel value.
+    pub fn fill_area(
+        &self,
+        buffer: &mut [S],
+        x: u32,
+        y: u32,
+        width: u32,
+        height: u32,
+        pixel: &[S],
+    ) {
+        let channels = self.channels as usize;
+        for row in y..y + height {
+            let row_start = ((row * self.width + x) as usize) * channels;
+            let row_end = row_start + width as usize * channels;
+            buffer[row_start..row_end]
+                .chunks_exact_mut(channels)
+                .for_each(|buffer_pixel| buffer_pixel.copy_from_slice(pixel));
+        }
+    }
+}
+
+impl ImageData<f32> {
+    /// Loads linear float samples of any image, HDR and OpenEXR files keep their full range.
+    pub fn from_hdr_path<P: AsRef<Path>>(
+        path: P,
+        tile_side_length: u32,
+    ) -> ImageResult<ImageData<f32>> {
+        let img = ImageData::load_image(path.as_ref())?;
+        return Ok(ImageData::from_image(&img, tile_side_length));
+    }
+}
+
 impl ImageData {
+    /// Crops the image to a multiple of the tile side length, images with other than 8-bit samples are converted
+    /// to 8 bits as the builders work on 8-bit samples.
     pub fn new(img: &DynamicImage, tile_side_length: u32) -> ImageData {
         let cropped_img = ImageData::crop_image(img, tile_side_length);
+        let cropped_img = match cropped_img.color() {
+            image::ColorType::L8
+            | image::ColorType::La8
+            | image::ColorType::Rgb8
+            | image::ColorType::Rgba8 => cropped_img,
+            image::ColorType::L16 => DynamicImage::ImageLuma8(cropped_img.to_luma8()),
+            image::ColorType::La16 => DynamicImage::ImageLumaA8(cropped_img.to_luma_alpha8()),
+            color if color.has_alpha() => DynamicImage::ImageRgba8(cropped_img.to_rgba8()),
+            _ => DynamicImage::ImageRgb8(cropped_img.to_rgb8()),
+        };
         return ImageData {
             width: cropped_img.width(),
             height: cropped_img.height(),
@@ -24,28 +239,44 @@ impl ImageData {
         };
     }
 
-    pub fn from_path<P: AsRef<Path>>(path: P, tile_side_length: u32) -> ImageData {
-        let img = ImageData::load_image(path.as_ref());
-        return ImageData::new(&img, tile_side_length);
+    pub fn from_path<P: AsRef<Path>>(path: P, tile_side_length: u32) -> ImageResult<ImageData> {
+        let img = ImageData::load_image(path.as_ref())?;
+        return Ok(ImageData::new(&img, tile_side_length));
     }
 
-    fn get_pixel_channel(&self, x: u32, y: u32, channel: u8) -> Option<&u8> {
-        if x >= self.width || y >= self.height {
-            return None;
-        }
-        let index = ((y * self.width + x) * self.channels as u32) as usize;
-        return Some(&self.data[index + channel as usize]);
-    }
+    /// Reads rows of the centred crop from a row source, rows outside the crop are decoded and discarded.
+    pub fn from_row_source(
+        row_source: &mut dyn RowSource,
+        tile_side_length: u32,
+    ) -> io::Result<ImageData> {
+        let (original_width, original_height) = (row_source.width(), row_source.height());
+        let width = (original_width / tile_side_length) * tile_side_length;
+        let height = (original_height / tile_side_length) * tile_side_length;
+        let margin_x = (original_width - width) / 2;
+        let margin_y = (original_height - height) / 2;
+        let color = row_source.color();
+        let channels = color.channel_count();
 
-    fn get_pixel_channel_mut(&mut self, x: u32, y: u32, channel: u8) -> Option<&mut u8> {
-        if x >= self.width || y >= self.height {
-            return None;
+        let mut row = vec![0; original_width as usize * channels as usize];
+        let crop_start = margin_x as usize * channels as usize;
+        let crop_end = crop_start + width as usize * channels as usize;
+        let mut data = Vec::with_capacity(width as usize * height as usize * channels as usize);
+        for y in 0..margin_y + height {
+            row_source.read_row(&mut row)?;
+            if y >= margin_y {
+                data.extend_from_slice(&row[crop_start..crop_end]);
+            }
         }
-        let index = ((y * self.width + x) * self.channels as u32) as usize;
-        return Some(&mut self.data[index + channel as usize]);
+        return Ok(ImageData {
+            width,
+            height,
+            channels,
+            data,
+            color,
+        });
     }
 
-    fn crop_image(img: &DynamicImage, tile_side_length: u32) -> DynamicImage {
+    pub fn crop_image(img: &DynamicImage, tile_side_length: u32) -> DynamicImage {
         let (original_width, original_height) = img.dimensions();
         let new_width = (original_width / tile_side_length) * tile_side_length;
         let new_height = (original_height / tile_side_length) * tile_side_length;
@@ -54,23 +285,50 @@ impl ImageData {
         return img.crop_imm(margin_x, margin_y, new_width, new_height);
     }
 
+    /// Wraps an 8-bit buffer in an image of the matching colour type.
+    pub fn image_from_buffer(
+        data: Vec<u8>,
+        width: u32,
+        height: u32,
+        color: image::ColorType,
+    ) -> DynamicImage {
+        return match color {
+            image::ColorType::L8 => {
+                DynamicImage::ImageLuma8(GrayImage::from_raw(width, height, data).unwrap())
+            }
+            image::ColorType::La8 => {
+                DynamicImage::ImageLumaA8(GrayAlphaImage::from_raw(width, height, data).unwrap())
+            }
+            image::ColorType::Rgb8 => {
+                DynamicImage::ImageRgb8(RgbImage::from_raw(width, height, data).unwrap())
+            }
+            image::ColorType::Rgba8 => {
+                DynamicImage::ImageRgba8(RgbaImage::from_raw(width, height, data).unwrap())
+            }
+            _ => panic!("Unsupported colour type: {:?}", color),
+        };
+    }
+
     fn image_channels(img: &DynamicImage) -> u8 {
         return img.color().channel_count();
     }
 
-    fn load_image<P: AsRef<Path>>(image_path: P) -> DynamicImage {
-        fn local_load_image(image_path: &Path) -> Result<DynamicImage, ImageError> {
-            return ImageReader::open(image_path)?.decode();
+    /// Loads image from disk or generates it in memory for synthetic image specifications.
+    pub fn load_image<P: AsRef<Path>>(image_path: P) -> ImageResult<DynamicImage> {
+        let image_path = image_path.as_ref();
+        if SyntheticImage::is_synthetic_path(image_path) {
+            let spec = image_path.to_string_lossy();
+            return match SyntheticImage::parse(&spec) {
+                Ok(synthetic_image) => Ok(synthetic_image.generate()),
+                Err(e) => Err(image_io::parameter_error(e)),
+            };
         }
-
-        let maybe_image = local_load_image(image_path.as_ref());
-        match maybe_image {
-            Ok(image) => {
-                return image;
-            }
-            Err(e) => {
-                panic!("Error loading image: {}", e);
-            }
+        if image_io::is_standard_stream(image_path) {
+            return image_io::read_standard_input();
+        }
+        if ImageFormat::from_path(image_path).is_ok_and(|format| format == ImageFormat::Tiff) {
+            TiffRowSource::check_first_page(image_path)?;
         }
+        return ImageReader::open(image_path)?.decode();
     }
 }
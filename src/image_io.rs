@@ -0,0 +1,491 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufWriter, Cursor, Read, Seek, Write};
+use std::path::Path;
+
+use image::codecs::avif::AvifEncoder;
+use image::codecs::hdr::HdrEncoder;
+use image::codecs::jpeg::JpegEncoder;
+use image::error::{ParameterError, ParameterErrorKind, UnsupportedError, UnsupportedErrorKind};
+use image::io::Reader as ImageReader;
+use image::{ColorType, DynamicImage, ImageEncoder, ImageError, ImageFormat, ImageResult, Rgb};
+
+use crate::args::{OutputFormat, PngCompression, PngFilter};
+use crate::log;
+
+/// Path standing for stdin as input and stdout as output.
+pub const STANDARD_STREAM_PATH: &str = "-";
+
+/// Settings controlling how output images are encoded, encoder settings apply only to their own format
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct OutputOptions {
+    /// Format of the output, derived from the output path extension when not set
+    pub format: Option<OutputFormat>,
+    pub jpeg_quality: u8,
+    pub png_compression: PngCompression,
+    pub png_filter: PngFilter,
+    /// Writes PNG output with a palette when it has at most 256 colours
+    pub png_palette: bool,
+    pub avif_speed: u8,
+    /// WebP output is always lossless, `false` asks for lossy encoding which fails
+    pub webp_lossless: bool,
+}
+
+impl Default for OutputOptions {
+    fn default() -> OutputOptions {
+        return OutputOptions {
+            format: None,
+            jpeg_quality: 75,
+            png_compression: PngCompression::Fast,
+            png_filter: PngFilter::Adaptive,
+            png_palette: false,
+            avif_speed: 4,
+            webp_lossless: true,
+        };
+    }
+}
+
+pub fn to_image_error(e: impl ToString) -> ImageError {
+    return ImageError::IoError(io::Error::other(e.to_string()));
+}
+
+/// Error of an input or setting the image cannot be processed with.
+pub fn parameter_error(message: impl Into<String>) -> ImageError {
+    return ImageError::Parameter(ParameterError::from_kind(ParameterErrorKind::Generic(
+        message.into(),
+    )));
+}
+
+pub fn is_standard_stream<P: AsRef<Path>>(path: P) -> bool {
+    return path.as_ref() == Path::new(STANDARD_STREAM_PATH);
+}
+
+/// Reads all of stdin into a reader with the format detected from the encoded bytes.
+pub fn standard_input_reader() -> ImageResult<ImageReader<Cursor<Vec<u8>>>> {
+    let mut bytes = Vec::new();
+    io::stdin().lock().read_to_end(&mut bytes)?;
+    return Ok(ImageReader::new(Cursor::new(bytes)).with_guessed_format()?);
+}
+
+/// Decodes an image from stdin.
+pub fn read_standard_input() -> ImageResult<DynamicImage> {
+    return standard_input_reader()?.decode();
+}
+
+fn prepare_file(path: &Path) -> io::Result<()> {
+    let prefix = path
+        .parent()
+        .ok_or_else(|| io::Error::from(io::ErrorKind::NotFound))?;
+    return std::fs::create_dir_all(prefix);
+}
+
+/// Format of the output options or the format matching the output path extension, lossy WebP fails before anything
+/// is written.
+pub fn output_format(path: &Path, output_options: &OutputOptions) -> ImageResult<ImageFormat> {
+    let format = match output_options.format {
+        Some(output_format) => Ok(output_format.image_format()),
+        None if is_standard_stream(path) => Err(parameter_error(
+            "writing to standard output requires --output-format",
+        )),
+        None => ImageFormat::from_path(path),
+    }?;
+    if format == ImageFormat::WebP {
+        check_webp_lossless(output_options)?;
+    }
+    return Ok(format);
+}
+
+/// Saves raw image buffer in the format of the output options or the format matching the output path extension,
+/// the standard stream path writes the encoded image to stdout.
+pub fn save_buffer<P: AsRef<Path>>(
+    output_img_path: &P,
+    img: &[u8],
+    width: u32,
+    height: u32,
+    color: ColorType,
+    output_options: &OutputOptions,
+) -> ImageResult<()> {
+    let path = output_img_path.as_ref();
+    let format = output_format(path, output_options)?;
+
+    if is_standard_stream(path) {
+        let mut encoded = Cursor::new(Vec::new());
+        encode(
+            &mut encoded,
+            img,
+            width,
+            height,
+            color,
+            format,
+            output_options,
+        )?;
+        let mut stdout = io::stdout().lock();
+        stdout.write_all(encoded.get_ref())?;
+        stdout.flush()?;
+        return Ok(());
+    }
+
+    prepare_file(path)?;
+    let mut writer = BufWriter::new(File::create(path)?);
+    encode(
+        &mut writer,
+        img,
+        width,
+        height,
+        color,
+        format,
+        output_options,
+    )?;
+    writer.flush()?;
+    return Ok(());
+}
+
+/// Fails when lossy WebP output is requested, the only available WebP encoder is lossless.
+pub fn check_webp_lossless(output_options: &OutputOptions) -> ImageResult<()> {
+    if output_options.webp_lossless {
+        return Ok(());
+    }
+    return Err(parameter_error(
+        "lossy WebP encoding is not available, WebP output is always lossless",
+    ));
+}
+
+/// Encodes raw image buffer with the encoder settings of the output options. WebP has no settings besides
+/// `webp_lossless`, the only available WebP encoder is lossless.
+pub fn encode<W: Write + Seek>(
+    writer: &mut W,
+    img: &[u8],
+    width: u32,
+    height: u32,
+    color: ColorType,
+    format: ImageFormat,
+    output_options: &OutputOptions,
+) -> ImageResult<()> {
+    return match format {
+        ImageFormat::Png => encode_png(writer, img, width, height, color, output_options),
+        ImageFormat::Jpeg => JpegEncoder::new_with_quality(writer, output_options.jpeg_quality)
+            .write_image(img, width, height, color.into()),
+        ImageFormat::Avif => {
+            // quality 80 is the default of the AVIF encoder
+            AvifEncoder::new_with_speed_quality(writer, output_options.avif_speed, 80).write_image(
+                img,
+                width,
+                height,
+                color.into(),
+            )
+        }
+        ImageFormat::Hdr => encode_hdr(writer, img, width, height, color),
+        ImageFormat::WebP => {
+            check_webp_lossless(output_options)?;
+            image::write_buffer_with_format(writer, img, width, height, color, format)
+        }
+        _ => image::write_buffer_with_format(writer, img, width, height, color, format),
+    };
+}
+
+/// Encodes float samples as Radiance HDR, which has no alpha channel.
+fn encode_hdr<W: Write>(
+    writer: W,
+    img: &[u8],
+    width: u32,
+    height: u32,
+    color: ColorType,
+) -> ImageResult<()> {
+    if !matches!(color, ColorType::Rgb32F | ColorType::Rgba32F) {
+        return Err(ImageError::Unsupported(
+            UnsupportedError::from_format_and_kind(
+                ImageFormat::Hdr.into(),
+                UnsupportedErrorKind::Color(color.into()),
+            ),
+        ));
+    }
+    let pixels: Vec<Rgb<f32>> = img
+        .chunks_exact(color.bytes_per_pixel() as usize)
+        .map(|pixel| {
+            return Rgb(std::array::from_fn(|channel| {
+                let bytes = &pixel[channel * 4..channel * 4 + 4];
+                return f32::from_ne_bytes(bytes.try_into().unwrap());
+            }));
+        })
+        .collect();
+    return HdrEncoder::new(writer).encode(&pixels, width as usize, height as usize);
+}
+
+/// Applies the PNG compression and filter of the output options.
+pub fn configure_png_encoder<W: Write>(
+    encoder: &mut png::Encoder<W>,
+    output_options: &OutputOptions,
+) {
+    encoder.set_compression(output_options.png_compression.compression());
+    let (filter, adaptive_filter) = output_options.png_filter.filter();
+    encoder.set_filter(filter);
+    encoder.set_adaptive_filter(adaptive_filter);
+}
+
+fn encode_png<W: Write>(
+    writer: W,
+    img: &[u8],
+    width: u32,
+    height: u32,
+    color: ColorType,
+    output_options: &OutputOptions,
+) -> ImageResult<()> {
+    let is_8_bit = color.bytes_per_pixel() == color.channel_count();
+    let palette = match output_options.png_palette && is_8_bit {
+        true => Palette::new(img, color.channel_count() as usize),
+        false => None,
+    };
+    if output_options.png_palette && palette.is_none() {
+        log::info!("Output has more than 256 colours, writing PNG without a palette");
+    }
+
+    let mut encoder = png::Encoder::new(writer, width, height);
+    configure_png_encoder(&mut encoder, output_options);
+
+    let Some(palette) = palette else {
+        encoder.set_color(match color {
+            ColorType::L8 | ColorType::L16 => png::ColorType::Grayscale,
+            ColorType::La8 | ColorType::La16 => png::ColorType::GrayscaleAlpha,
+            ColorType::Rgb8 | ColorType::Rgb16 => png::ColorType::Rgb,
+            ColorType::Rgba8 | ColorType::Rgba16 => png::ColorType::Rgba,
+            _ => {
+                return Err(to_image_error(format!(
+                    "PNG output does not support {:?}",
+                    color
+                )))
+            }
+        });
+        encoder.set_depth(match is_8_bit {
+            true => png::BitDepth::Eight,
+            false => png::BitDepth::Sixteen,
+        });
+        let mut writer = encoder.write_header().map_err(to_image_error)?;
+        if is_8_bit {
+            return writer.write_image_data(img).map_err(to_image_error);
+        }
+        // 16-bit samples are stored big endian
+        let data: Vec<u8> = img
+            .chunks_exact(2)
+            .flat_map(|sample| u16::from_ne_bytes([sample[0], sample[1]]).to_be_bytes())
+            .collect();
+        return writer.write_image_data(&data).map_err(to_image_error);
+    };
+
+    let bit_depth = palette.bit_depth();
+    encoder.set_color(png::ColorType::Indexed);
+    encoder.set_depth(bit_depth);
+    encoder.set_palette(
+        palette
+            .colors
+            .iter()
+            .flat_map(|color| &color[..3])
+            .copied()
+            .collect::<Vec<u8>>(),
+    );
+    if palette.colors.iter().any(|color| color[3] != 255) {
+        encoder.set_trns(
+            palette
+                .colors
+                .iter()
+                .map(|color| color[3])
+                .collect::<Vec<u8>>(),
+        );
+    }
+    let mut writer = encoder.write_header().map_err(to_image_error)?;
+    let data = palette.packed_indices(width as usize, bit_depth as usize);
+    return writer.write_image_data(&data).map_err(to_image_error);
+}
+
+/// Distinct colours of an 8-bit image as RGBA and the palette index of every pixel
+struct Palette {
+    colors: Vec<[u8; 4]>,
+    indices: Vec<u8>,
+}
+
+impl Palette {
+    /// Returns `None` when the image has more than 256 colours.
+    fn new(img: &[u8], channels: usize) -> Option<Palette> {
+        let mut colors: Vec<[u8; 4]> = Vec::new();
+        let mut color_indices: HashMap<[u8; 4], u8> = HashMap::new();
+        let mut indices = Vec::with_capacity(img.len() / channels);
+        let mut previous: Option<([u8; 4], u8)> = None;
+        for pixel in img.chunks_exact(channels) {
+            let color = match *pixel {
+                [l] => [l, l, l, 255],
+                [l, a] => [l, l, l, a],
+                [r, g, b] => [r, g, b, 255],
+                [r, g, b, a] => [r, g, b, a],
+                _ => return None,
+            };
+            // neighbouring pixels of a mosaic mostly share their tile colour
+            let index = match previous {
+                Some((previous_color, index)) if previous_color == color => index,
+                _ => match color_indices.get(&color) {
+                    Some(index) => *index,
+                    None if colors.len() == 256 => return None,
+                    None => {
+                        let index = colors.len() as u8;
+                        colors.push(color);
+                        color_indices.insert(color, index);
+                        index
+                    }
+                },
+            };
+            previous = Some((color, index));
+            indices.push(index);
+        }
+        return Some(Palette { colors, indices });
+    }
+
+    /// Smallest PNG bit depth able to index every colour.
+    fn bit_depth(&self) -> png::BitDepth {
+        return match self.colors.len() {
+            0..=2 => png::BitDepth::One,
+            3..=4 => png::BitDepth::Two,
+            5..=16 => png::BitDepth::Four,
+            _ => png::BitDepth::Eight,
+        };
+    }
+
+    /// Indices packed into rows of `bits` bits per pixel, rows start at a byte boundary.
+    fn packed_indices(&self, width: usize, bits: usize) -> Vec<u8> {
+        let row_bytes = (width * bits).div_ceil(8);
+        let height = self.indices.len() / width;
+        let mut data = vec![0; row_bytes * height];
+        for (row, indices) in data
+            .chunks_exact_mut(row_bytes)
+            .zip(self.indices.chunks_exact(width))
+        {
+            for (x, index) in indices.iter().enumerate() {
+                let bit = x * bits;
+                row[bit / 8] |= index << (8 - bits - bit % 8);
+            }
+        }
+        return data;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::args::AlgorithmType;
+    use crate::image_data::ImageData;
+    use crate::mosaic_factory::MosaicFactory;
+    use crate::synthetic::{ChannelLayout, Pattern, SyntheticImage};
+    use crate::temp_dir::TempDir;
+
+    fn encode_png_bytes(img: &DynamicImage, png_palette: bool) -> Vec<u8> {
+        let output_options = OutputOptions {
+            png_palette,
+            ..OutputOptions::default()
+        };
+        let mut encoded = Cursor::new(Vec::new());
+        let (width, height) = (img.width(), img.height());
+        encode(
+            &mut encoded,
+            img.as_bytes(),
+            width,
+            height,
+            img.color(),
+            ImageFormat::Png,
+            &output_options,
+        )
+        .unwrap();
+        return encoded.into_inner();
+    }
+
+    fn png_header(encoded: &[u8]) -> (png::ColorType, png::BitDepth) {
+        let reader = png::Decoder::new(encoded).read_info().unwrap();
+        return (reader.info().color_type, reader.info().bit_depth);
+    }
+
+    #[test]
+    fn output_format_overrides_extension() {
+        let img = SyntheticImage::new(Pattern::Noise, 8, 6, ChannelLayout::Rgb).generate();
+        let directory = TempDir::new("image_io");
+        let path = directory.join("mosaic.img");
+        let output_options = OutputOptions {
+            format: Some(OutputFormat::Png),
+            ..OutputOptions::default()
+        };
+        save_buffer(&path, img.as_bytes(), 8, 6, img.color(), &output_options).unwrap();
+
+        let reader = ImageReader::open(&path)
+            .unwrap()
+            .with_guessed_format()
+            .unwrap();
+        assert_eq!(reader.format(), Some(ImageFormat::Png));
+        assert!(reader.decode().unwrap() == img);
+    }
+
+    #[test]
+    fn webp_output_is_only_lossless() {
+        let img = SyntheticImage::new(Pattern::Noise, 8, 6, ChannelLayout::Rgba).generate();
+        let directory = TempDir::new("image_io");
+        let path = directory.join("mosaic.webp");
+        let lossy = OutputOptions {
+            webp_lossless: false,
+            ..OutputOptions::default()
+        };
+        let error = save_buffer(&path, img.as_bytes(), 8, 6, img.color(), &lossy).unwrap_err();
+        assert!(error.to_string().contains("always lossless"), "{}", error);
+        assert!(!path.exists());
+
+        let lossless = OutputOptions::default();
+        save_buffer(&path, img.as_bytes(), 8, 6, img.color(), &lossless).unwrap();
+        assert!(image::open(&path).unwrap() == img);
+    }
+
+    #[test]
+    fn palette_png_keeps_mosaic_colours() {
+        for layout in [
+            ChannelLayout::L,
+            ChannelLayout::La,
+            ChannelLayout::Rgb,
+            ChannelLayout::Rgba,
+        ] {
+            let img = SyntheticImage::new(Pattern::Gradient, 192, 144, layout).generate();
+            let mosaic_factory = MosaicFactory::from_image_data(
+                ImageData::new(&img, 48),
+                AlgorithmType::Serial.mosaic_builder(),
+                48,
+            );
+            let mosaic = ImageData::image_from_buffer(
+                mosaic_factory.generate_mosaic(),
+                192,
+                144,
+                img.color(),
+            );
+
+            let palette_png = encode_png_bytes(&mosaic, true);
+            // 12 tiles fit a 4-bit palette
+            assert_eq!(
+                png_header(&palette_png),
+                (png::ColorType::Indexed, png::BitDepth::Four),
+                "{:?}",
+                layout
+            );
+            let decoded = image::load_from_memory(&palette_png).unwrap();
+            assert!(decoded.to_rgba8() == mosaic.to_rgba8(), "{:?}", layout);
+            // grayscale samples already take a byte, the palette costs more than the smaller indices save
+            if layout != ChannelLayout::L {
+                assert!(
+                    palette_png.len() < encode_png_bytes(&mosaic, false).len(),
+                    "{:?}",
+                    layout
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn palette_png_falls_back_above_256_colours() {
+        let img = SyntheticImage::new(Pattern::Noise, 64, 64, ChannelLayout::Rgb).generate();
+        let encoded = encode_png_bytes(&img, true);
+        assert_eq!(
+            png_header(&encoded),
+            (png::ColorType::Rgb, png::BitDepth::Eight)
+        );
+        assert!(image::load_from_memory(&encoded).unwrap() == img);
+    }
+}
@@ -0,0 +1,158 @@
+use std::fmt;
+use std::path::Path;
+
+use image::io::Reader as ImageReader;
+use image::{ColorType, ImageDecoder, ImageFormat, ImageResult};
+
+use crate::animation::Animation;
+use crate::geotiff::GeoReference;
+use crate::image_data::ImageData;
+use crate::image_io;
+use crate::streaming::RowSource;
+use crate::synthetic::SyntheticImage;
+use crate::tiff_reader::TiffRowSource;
+
+/// Properties of an input image read without decoding pixels where the format allows it
+pub struct ImageInfo {
+    /// `None` for synthetic images
+    pub format: Option<ImageFormat>,
+    pub width: u32,
+    pub height: u32,
+    pub color: ColorType,
+    /// Number of frames of animated GIF, PNG and WebP input
+    pub frames: Option<usize>,
+    pub tiff_pages: Option<u32>,
+    pub georeferenced: bool,
+}
+
+impl ImageInfo {
+    /// Reads image properties, `tiff_page` selects the described page of TIFF files.
+    pub fn read<P: AsRef<Path>>(path: P, tiff_page: Option<u32>) -> ImageResult<ImageInfo> {
+        let path = path.as_ref();
+        if SyntheticImage::is_synthetic_path(path) {
+            let img = ImageData::load_image(path)?;
+            return Ok(ImageInfo {
+                format: None,
+                width: img.width(),
+                height: img.height(),
+                color: img.color(),
+                frames: None,
+                tiff_pages: None,
+                georeferenced: false,
+            });
+        }
+
+        if image_io::is_standard_stream(path) {
+            let reader = image_io::standard_input_reader()?;
+            let format = reader.format();
+            let decoder = reader.into_decoder()?;
+            let (width, height) = decoder.dimensions();
+            return Ok(ImageInfo {
+                format,
+                width,
+                height,
+                color: decoder.color_type(),
+                frames: None,
+                tiff_pages: None,
+                georeferenced: false,
+            });
+        }
+
+        let reader = ImageReader::open(path)?.with_guessed_format()?;
+        let format = reader.format();
+        if let (Some(ImageFormat::Tiff), Some(page)) = (format, tiff_page) {
+            let row_source = TiffRowSource::open(path, page)?;
+            return Ok(ImageInfo {
+                format,
+                width: row_source.width(),
+                height: row_source.height(),
+                color: row_source.color(),
+                frames: None,
+                tiff_pages: Some(TiffRowSource::page_count(path)?),
+                georeferenced: GeoReference::read(path, page)?.is_some(),
+            });
+        }
+
+        let decoder = reader.into_decoder()?;
+        let (width, height) = decoder.dimensions();
+        let color = decoder.color_type();
+        let (tiff_pages, georeferenced) = match format {
+            Some(ImageFormat::Tiff) => (
+                Some(TiffRowSource::page_count(path)?),
+                GeoReference::read(path, 0)?.is_some(),
+            ),
+            _ => (None, false),
+        };
+        return Ok(ImageInfo {
+            format,
+            width,
+            height,
+            color,
+            frames: Animation::load(path)?.map(|animation| animation.frames.len()),
+            tiff_pages,
+            georeferenced,
+        });
+    }
+
+    /// Number of tiles along each axis and the number of pixels cropped along each axis to fit whole tiles.
+    pub fn tile_grid(&self, tile_side_length: u32) -> ((u32, u32), (u32, u32)) {
+        let tiles = (
+            self.width / tile_side_length,
+            self.height / tile_side_length,
+        );
+        let cropped = (
+            self.width % tile_side_length,
+            self.height % tile_side_length,
+        );
+        return (tiles, cropped);
+    }
+}
+
+impl fmt::Display for ImageInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.format {
+            Some(format) => writeln!(f, "Format: {:?}", format)?,
+            None => writeln!(f, "Format: synthetic")?,
+        }
+        writeln!(f, "Size: {}x{}", self.width, self.height)?;
+        write!(f, "Colour type: {:?}", self.color)?;
+        if let Some(frames) = self.frames {
+            write!(f, "\nFrames: {}", frames)?;
+        }
+        if let Some(tiff_pages) = self.tiff_pages {
+            write!(f, "\nTIFF pages: {}", tiff_pages)?;
+            write!(f, "\nGeoreferenced: {}", self.georeferenced)?;
+        }
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::synthetic::{ChannelLayout, Pattern};
+    use crate::temp_dir::TempDir;
+
+    #[test]
+    fn reads_properties_without_decoding() {
+        let directory = TempDir::new("info");
+        let path = directory.join("image.png");
+        SyntheticImage::new(Pattern::Gradient, 70, 45, ChannelLayout::Rgba)
+            .generate()
+            .save(&path)
+            .unwrap();
+
+        let image_info = ImageInfo::read(&path, None).unwrap();
+        assert_eq!(image_info.format, Some(ImageFormat::Png));
+        assert_eq!((image_info.width, image_info.height), (70, 45));
+        assert_eq!(image_info.color, ColorType::Rgba8);
+        assert_eq!(image_info.frames, None);
+        assert_eq!(image_info.tiff_pages, None);
+        assert_eq!(image_info.tile_grid(16), ((4, 2), (6, 13)));
+
+        let image_info = ImageInfo::read("synthetic:noise:32x16:layout=l", None).unwrap();
+        assert_eq!(image_info.format, None);
+        assert_eq!(image_info.color, ColorType::L8);
+        assert_eq!(image_info.tile_grid(8), ((4, 2), (0, 0)));
+    }
+}
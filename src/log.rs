@@ -0,0 +1,40 @@
+use std::sync::atomic::{AtomicU8, Ordering};
+
+/// Amount of status output, messages go to stderr so that stdout only carries command results
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Verbosity {
+    Quiet,
+    Normal,
+    Verbose,
+}
+
+static VERBOSITY: AtomicU8 = AtomicU8::new(Verbosity::Normal as u8);
+
+pub fn set_verbosity(verbosity: Verbosity) {
+    VERBOSITY.store(verbosity as u8, Ordering::Relaxed);
+}
+
+pub fn enabled(verbosity: Verbosity) -> bool {
+    return VERBOSITY.load(Ordering::Relaxed) >= verbosity as u8;
+}
+
+/// Prints a status message unless `--quiet` is given.
+macro_rules! info {
+    ($($arg:tt)*) => {
+        if $crate::log::enabled($crate::log::Verbosity::Normal) {
+            eprintln!($($arg)*);
+        }
+    };
+}
+
+/// Prints a detail message only with `--verbose`.
+macro_rules! verbose {
+    ($($arg:tt)*) => {
+        if $crate::log::enabled($crate::log::Verbosity::Verbose) {
+            eprintln!($($arg)*);
+        }
+    };
+}
+
+pub(crate) use info;
+pub(crate) use verbose;
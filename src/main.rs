@@ -1,70 +1,874 @@
 use std::path::Path;
+use std::time::Duration;
 
-use clap::Parser;
+use clap::parser::ValueSource;
+use clap::{Args, CommandFactory, FromArgMatches, ValueEnum};
 
-use crate::args::{AlgorithmType, CLIArgs};
+use crate::adaptive_mosaic::AdaptiveMosaic;
+use crate::animation::{AnimatedMosaic, Animation};
+use crate::args::{
+    AlgorithmType, AlphaArgs, BatchArgs, BenchArgs, CLIArgs, Command, CompareArgs, ConfigArgs,
+    GenerateArgs, IndexArgs, InfoArgs, InputArgs, OutputFormat, OutputScale, PyramidFormat,
+    SweepArgs, TilingStrategy, VerifyArgs,
+};
+use crate::benchmark::BenchmarkResults;
+use crate::correctness::CorrectnessDiagnostics;
+use crate::exit_code::CommandError;
+use crate::geotiff::GeoReference;
 use crate::image_data::ImageData;
-use crate::mosaic_factory::{MosaicBuilder, MosaicFactory};
-use crate::parallel_mosaic::ParallelMosaic;
+use crate::info::ImageInfo;
+use crate::log::Verbosity;
+use crate::mosaic_factory::MosaicFactory;
+use crate::pyramid::TilePyramid;
+use crate::redaction::RedactionOptions;
+use crate::region_mosaic::{Region, RegionMosaic};
+use crate::report::{CorrectnessReport, Report};
+use crate::reveal::RevealMosaic;
 use crate::serial_mosaic::SerialMosaic;
-use crate::slow_parallel_mosaic::SlowParallelMosaic;
+use crate::streaming::{open_row_source, StreamingMosaic};
+use crate::synthetic::SyntheticImage;
+use crate::tile_index::TileIndex;
+use crate::video::{open_frame_reader, open_frame_writer, VideoMosaic};
 
+mod adaptive_mosaic;
+mod alpha;
+mod animation;
 mod args;
+mod batch;
+mod benchmark;
+mod config;
+mod correctness;
+mod detail_map;
+mod exit_code;
+mod geotiff;
+mod hdr_mosaic;
 mod image_data;
+mod image_io;
+mod info;
+mod log;
 mod mosaic_factory;
 mod parallel_mosaic;
+mod pyramid;
+mod redaction;
+mod region_mosaic;
+mod report;
+mod reveal;
 mod serial_mosaic;
 mod slow_parallel_mosaic;
+mod streaming;
+mod sweep;
+mod synthetic;
+#[cfg(test)]
+mod temp_dir;
+mod tiff_reader;
+mod tile_index;
+mod video;
 
 fn main() {
-    let cli_args: CLIArgs = CLIArgs::parse();
-    let input_image_path = Path::new(&cli_args.input_image_path);
-    if !input_image_path.exists() || !input_image_path.is_file() {
-        panic!("Input image does not exist: {}", input_image_path.display());
+    let cli_args = parse_cli_args();
+    log::set_verbosity(match (cli_args.quiet, cli_args.verbose) {
+        (true, _) => Verbosity::Quiet,
+        (_, true) => Verbosity::Verbose,
+        _ => Verbosity::Normal,
+    });
+    if let Err(e) = run_command(&cli_args) {
+        eprintln!("Error: {}", e.message);
+        std::process::exit(e.exit_code);
+    }
+}
+
+fn run_command(cli_args: &CLIArgs) -> Result<(), CommandError> {
+    let check_failed = match &cli_args.command {
+        Command::Generate(generate_args) => run_generate(generate_args).map(|_| false),
+        Command::Bench(bench_args) => run_bench(bench_args).map(|_| false),
+        Command::Verify(verify_args) => run_verify(verify_args).map(|is_correct| !is_correct),
+        Command::Index(index_args) => run_index(index_args).map(|_| false),
+        Command::Info(info_args) => run_info(info_args).map(|_| false),
+        Command::Compare(compare_args) => compare_reports(compare_args),
+        Command::Sweep(sweep_args) => run_sweep_workflow(sweep_args).map(|_| false),
+        Command::Batch(batch_args) => run_batch_workflow(batch_args),
+    }?;
+    if check_failed {
+        std::process::exit(exit_code::CHECK_FAILED);
+    }
+    return Ok(());
+}
+
+/// Parses the command line with settings of config files as defaults, exits on invalid arguments and after
+/// printing the effective settings.
+fn parse_cli_args() -> CLIArgs {
+    let exit_with_usage_error = |message: &str| -> ! {
+        eprintln!("Error: {}", message);
+        std::process::exit(exit_code::USAGE);
+    };
+
+    let command = ConfigArgs::augment_args(CLIArgs::command());
+    let config_args = command
+        .clone()
+        .ignore_errors(true)
+        .try_get_matches()
+        .ok()
+        .and_then(|matches| ConfigArgs::from_arg_matches(&matches).ok());
+    let (config_path, preset, print_config) = match &config_args {
+        Some(config_args) => (
+            config_args.config.as_deref(),
+            config_args.preset.as_deref(),
+            config_args.print_config,
+        ),
+        None => (None, None, false),
+    };
+    let settings = config::load_config_files(config_path)
+        .and_then(|config_files| config::merge_settings(&config_files, preset))
+        .unwrap_or_else(|e| exit_with_usage_error(&e));
+    let command =
+        config::apply_settings(command, &settings).unwrap_or_else(|e| exit_with_usage_error(&e));
+
+    if print_config {
+        // required arguments may be missing when only inspecting settings
+        let matches = command.clone().ignore_errors(true).get_matches();
+        print!(
+            "{}",
+            config::render_effective_settings(&command, &matches, &settings)
+        );
+        std::process::exit(0);
+    }
+
+    let matches = match command.try_get_matches() {
+        Ok(matches) => matches,
+        Err(e) => {
+            let _ = e.print();
+            std::process::exit(match e.use_stderr() {
+                true => exit_code::USAGE,
+                false => 0,
+            });
+        }
+    };
+    let mut cli_args = CLIArgs::from_arg_matches(&matches)
+        .unwrap_or_else(|e| exit_with_usage_error(&e.to_string()));
+    // a verbosity flag given on the command line replaces the opposite one set in config files
+    let is_explicit = |id: &str| {
+        let subcommand_matches = matches.subcommand().map(|(_, matches)| matches);
+        return [Some(&matches), subcommand_matches]
+            .into_iter()
+            .flatten()
+            .any(|matches| matches.value_source(id) == Some(ValueSource::CommandLine));
+    };
+    if is_explicit("verbose") {
+        cli_args.quiet = false;
+    } else if is_explicit("quiet") {
+        cli_args.verbose = false;
+    }
+    // tone mapping set in config files applies only to HDR inputs
+    if let (Command::Generate(generate_args), Some((_, generate_matches))) =
+        (&cli_args.command, matches.subcommand())
+    {
+        if generate_matches.value_source("tone_mapping") == Some(ValueSource::CommandLine)
+            && !hdr_mosaic::is_hdr_path(&generate_args.input.input_image_path)
+        {
+            exit_with_usage_error("--tone-mapping requires Radiance HDR or OpenEXR input");
+        }
+    }
+    return cli_args;
+}
+
+fn check_input_exists(input_image_path: &Path) -> Result<(), CommandError> {
+    let is_synthetic = SyntheticImage::is_synthetic_path(input_image_path);
+    let is_standard_input = image_io::is_standard_stream(input_image_path);
+    if !is_synthetic
+        && !is_standard_input
+        && (!input_image_path.exists() || !input_image_path.is_file())
+    {
+        return Err(format!("Input image does not exist: {}", input_image_path.display()).into());
+    }
+    return Ok(());
+}
+
+fn load_error(input_image_path: &Path, e: impl std::fmt::Display) -> String {
+    return format!("Error loading image {}: {}", input_image_path.display(), e);
+}
+
+fn create_mosaic_factory(
+    input_args: &InputArgs,
+    alpha_args: &AlphaArgs,
+) -> Result<MosaicFactory, CommandError> {
+    let input_image_path = Path::new(&input_args.input_image_path);
+    check_input_exists(input_image_path)?;
+    let mosaic_builder = input_args.algorithm_type.mosaic_builder();
+
+    let mosaic_factory = match input_args.tiff_page {
+        // the page is decoded by rows but the mosaic needs all of it, only streaming mode stays within a band
+        Some(tiff_page) => {
+            let image_data = open_row_source(input_image_path, tiff_page)
+                .and_then(|mut row_source| {
+                    return ImageData::from_row_source(
+                        row_source.as_mut(),
+                        input_args.tile_side_length,
+                    );
+                })
+                .map_err(|e| load_error(input_image_path, e))?;
+            MosaicFactory::from_image_data(image_data, mosaic_builder, input_args.tile_side_length)
+        }
+        None => MosaicFactory::new(
+            input_image_path,
+            mosaic_builder,
+            input_args.tile_side_length,
+        )
+        .map_err(|e| load_error(input_image_path, e))?,
+    };
+    return Ok(mosaic_factory.with_alpha_options(alpha_args.alpha_options()));
+}
+
+/// Fails when the output goes to stdout in a mode which writes files by path.
+fn check_file_output(generate_args: &GenerateArgs, mode: &str) -> Result<(), CommandError> {
+    if image_io::is_standard_stream(&generate_args.output_image_path) {
+        return Err(format!("{} cannot be written to standard output", mode).into());
+    }
+    return Ok(());
+}
+
+fn run_generate(generate_args: &GenerateArgs) -> Result<(), CommandError> {
+    let input_args = &generate_args.input;
+    if generate_args.video {
+        return run_video_workflow(generate_args);
+    }
+
+    if image_io::is_standard_stream(&generate_args.output_image_path)
+        && generate_args.output.output_format.is_none()
+    {
+        return Err("Writing to standard output requires --output-format".into());
     }
 
-    let mosaic_builder: Box<dyn MosaicBuilder> = match cli_args.algorithm_type {
-        AlgorithmType::Serial => Box::new(SerialMosaic),
-        AlgorithmType::Parallel => Box::new(ParallelMosaic),
-        AlgorithmType::SlowParallel => Box::new(SlowParallelMosaic),
+    let input_image_path = Path::new(&input_args.input_image_path);
+    check_input_exists(input_image_path)?;
+    let is_synthetic = SyntheticImage::is_synthetic_path(input_image_path);
+    let is_standard_input = image_io::is_standard_stream(input_image_path);
+
+    if generate_args.streaming {
+        check_file_output(generate_args, "Streaming mosaics")?;
+        let streaming_mosaic = StreamingMosaic::new(
+            input_args.algorithm_type.mosaic_builder(),
+            input_args.tile_side_length,
+        )
+        .with_alpha_options(generate_args.alpha.alpha_options())
+        .with_output_options(generate_args.output.output_options())
+        .with_tiff_page(input_args.tiff_page.unwrap_or(0));
+        return run_streaming_workflow(&streaming_mosaic, input_image_path, generate_args);
+    }
+
+    if generate_args.reveal {
+        check_file_output(generate_args, "Reveal animations")?;
+        let image_data = ImageData::from_path(input_image_path, 1)
+            .map_err(|e| load_error(input_image_path, e))?;
+        let reveal_mosaic =
+            RevealMosaic::new(image_data).with_alpha_options(generate_args.alpha.alpha_options());
+        return run_reveal_workflow(&reveal_mosaic, generate_args);
+    }
+
+    if hdr_mosaic::is_hdr_path(input_image_path) {
+        if has_regions(generate_args) {
+            return Err(CommandError::usage(
+                "Regions and redaction are not available for HDR input",
+            ));
+        }
+        if generate_args.tiling != TilingStrategy::Uniform
+            || generate_args.tile_size_mask.is_some()
+            || generate_args.pyramid.is_some()
+        {
+            return Err(CommandError::usage(
+                "Variable tile size and pyramids are not available for HDR input",
+            ));
+        }
+        let mosaic_factory = MosaicFactory::new_hdr(
+            input_image_path,
+            input_args.algorithm_type.mosaic_builder(),
+            input_args.tile_side_length,
+        )
+        .map_err(|e| load_error(input_image_path, e))?
+        .with_alpha_options(generate_args.alpha.alpha_options())
+        .with_output_options(generate_args.output.output_options());
+        return run_hdr_workflow(&mosaic_factory, generate_args);
+    }
+
+    let animation = match is_synthetic || is_standard_input {
+        true => None,
+        false => Animation::load(input_image_path)
+            .map_err(|e| format!("Error loading animation: {}", e))?,
     };
+    if let Some(animation) = animation {
+        let animated_mosaic = AnimatedMosaic::new(
+            animation,
+            input_args.algorithm_type.clone(),
+            input_args.tile_side_length,
+        )
+        .with_alpha_options(generate_args.alpha.alpha_options())
+        .with_output_options(generate_args.output.output_options())
+        .with_output_size(generate_args.output_scale, generate_args.output_size);
+        return run_animated_workflow(&animated_mosaic, generate_args);
+    }
 
-    let mosaic_factory = MosaicFactory::new(
-        Path::new(input_image_path),
-        mosaic_builder,
-        cli_args.tile_side_length,
+    let regions = collect_regions(generate_args)?;
+    if !regions.is_empty() {
+        let redaction = generate_args.redact.then_some(RedactionOptions {
+            noise: generate_args.redact_noise,
+            tile_jitter: generate_args.redact_tile_jitter,
+            seed: generate_args.redact_seed,
+        });
+        let region_mosaic =
+            RegionMosaic::new(input_image_path, input_args.tile_side_length, regions)
+                .map_err(|e| load_error(input_image_path, e))?
+                .with_redaction(redaction)
+                .with_alpha_options(generate_args.alpha.alpha_options())
+                .with_output_options(generate_args.output.output_options());
+        return run_region_workflow(&region_mosaic, generate_args);
+    }
+
+    let is_adaptive =
+        generate_args.tiling != TilingStrategy::Uniform || generate_args.tile_size_mask.is_some();
+    if is_adaptive && generate_args.min_tile_side_length > input_args.tile_side_length {
+        return Err(CommandError::usage(format!(
+            "Minimum tile side length must not exceed the tile side length of {}",
+            input_args.tile_side_length
+        )));
+    }
+    let adaptive_mosaic = match (generate_args.tiling, &generate_args.tile_size_mask) {
+        (TilingStrategy::Uniform | TilingStrategy::Mask, Some(tile_size_mask)) => {
+            Some(AdaptiveMosaic::from_mask(
+                input_image_path,
+                tile_size_mask,
+                input_args.algorithm_type.mosaic_builder(),
+                input_args.tile_side_length,
+                generate_args.min_tile_side_length,
+            ))
+        }
+        (TilingStrategy::Mask, None) => {
+            return Err(CommandError::usage("Mask tiling requires --tile-size-mask"))
+        }
+        (TilingStrategy::Edges, _) => Some(AdaptiveMosaic::from_edges(
+            input_image_path,
+            input_args.algorithm_type.mosaic_builder(),
+            input_args.tile_side_length,
+            generate_args.min_tile_side_length,
+        )),
+        (TilingStrategy::Uniform, None) => None,
+    };
+    if let Some(adaptive_mosaic) = adaptive_mosaic {
+        let adaptive_mosaic = adaptive_mosaic
+            .map_err(|e| load_error(input_image_path, e))?
+            .with_alpha_options(generate_args.alpha.alpha_options())
+            .with_output_options(generate_args.output.output_options());
+        return run_adaptive_workflow(&adaptive_mosaic, generate_args);
+    }
+
+    let mosaic_factory = create_mosaic_factory(input_args, &generate_args.alpha)?
+        .with_output_options(generate_args.output.output_options());
+    return run_workflow(&mosaic_factory, generate_args);
+}
+
+fn run_workflow(
+    mosaic_factory: &MosaicFactory,
+    generate_args: &GenerateArgs,
+) -> Result<(), CommandError> {
+    let path = &generate_args.output_image_path;
+    if let Some(pyramid_format) = generate_args.pyramid {
+        check_file_output(generate_args, "Tile pyramids")?;
+        let tile_pyramid = TilePyramid::new(mosaic_factory, generate_args.pyramid_tile_size);
+        match pyramid_format {
+            PyramidFormat::Dzi => tile_pyramid.save_dzi(path),
+            PyramidFormat::Iiif => tile_pyramid.save_iiif(path, generate_args.iiif_id.as_deref()),
+        }
+        .map_err(|e| format!("Failed to save mosaic pyramid at {}: {}", path, e))?;
+        log::info!(
+            "Successfully generated and saved mosaic pyramid at: {}",
+            path
+        );
+        return Ok(());
+    }
+
+    let input_path = &generate_args.input.input_image_path;
+    // an explicit output format other than TIFF takes precedence over the extension
+    let reads_georeferencing = GeoReference::is_tiff_path(path)
+        && matches!(
+            generate_args.output.output_format,
+            None | Some(OutputFormat::Tiff)
+        );
+    let geo_reference = match reads_georeferencing {
+        true => GeoReference::read(input_path, generate_args.input.tiff_page.unwrap_or(0))
+            .map_err(|e| format!("Failed to read georeferencing of {}: {}", input_path, e))?,
+        false => None,
+    };
+    let output_size = generate_args.output_size.or(generate_args
+        .output_scale
+        .map(|output_scale| mosaic_factory.output_size(output_scale)));
+
+    if let Some(geo_reference) = geo_reference {
+        let (width, height) = output_size.unwrap_or(mosaic_factory.output_size(OutputScale::Full));
+        let img = mosaic_factory.generate_mosaic_with_size(width, height);
+        let geo_reference = geo_reference.resampled(mosaic_factory.tile_side_length, width, height);
+        geo_reference
+            .save_tiff(path, mosaic_factory.image_data.color, &img)
+            .map_err(|e| format!("Failed to save GeoTIFF at {}: {}", path, e))?;
+        log::info!(
+            "Successfully generated and saved georeferenced mosaic at: {}",
+            path
+        );
+        return Ok(());
+    }
+
+    match output_size {
+        Some((width, height)) => {
+            mosaic_factory
+                .generate_and_save_mosaic_with_size(path, width, height)
+                .map_err(|e| format!("Failed to save mosaic at {}: {}", path, e))?;
+            log::info!(
+                "Successfully generated and saved {}x{} mosaic at: {}",
+                width,
+                height,
+                path
+            );
+        }
+        None => {
+            mosaic_factory
+                .generate_and_save_mosaic(path)
+                .map_err(|e| format!("Failed to save mosaic at {}: {}", path, e))?;
+            log::info!("Successfully generated and saved mosaic at: {}", path);
+        }
+    }
+    return Ok(());
+}
+
+fn run_bench(bench_args: &BenchArgs) -> Result<(), CommandError> {
+    let mosaic_factory = create_mosaic_factory(&bench_args.input, &bench_args.alpha)?;
+    let correctness_args = &bench_args.correctness;
+    log::info!("Checking algorithm correctness and benchmarking...\n");
+    let results: Vec<(CorrectnessDiagnostics, BenchmarkResults)> =
+        match bench_args.compare_algorithms {
+            true => AlgorithmType::value_variants()
+                .iter()
+                .map(|algorithm_type| {
+                    let mosaic_builder = algorithm_type.mosaic_builder();
+                    return (
+                        mosaic_factory.check_builder_correctness(
+                            mosaic_builder.as_ref(),
+                            correctness_args.correctness_mode,
+                            correctness_args.max_mismatches,
+                        ),
+                        mosaic_factory.benchmark_builder(
+                            mosaic_builder.as_ref(),
+                            bench_args.warmup_runs,
+                            bench_args.runs,
+                        ),
+                    );
+                })
+                .collect(),
+            false => vec![(
+                mosaic_factory.check_correctness(
+                    correctness_args.correctness_mode,
+                    correctness_args.max_mismatches,
+                ),
+                mosaic_factory.benchmark(bench_args.warmup_runs, bench_args.runs),
+            )],
+        };
+
+    for (correctness_diagnostics, benchmark_results) in &results {
+        println!("{}", benchmark_results);
+        println!("{}", correctness_diagnostics);
+        println!();
+    }
+
+    let results: Vec<(CorrectnessReport, BenchmarkResults)> = results
+        .into_iter()
+        .map(|(correctness_diagnostics, benchmark_results)| {
+            ((&correctness_diagnostics).into(), benchmark_results)
+        })
+        .collect();
+
+    if bench_args.compare_algorithms {
+        let benchmark_results: Vec<BenchmarkResults> =
+            results.iter().map(|(_, result)| result.clone()).collect();
+        println!("{}", benchmark::comparison_table(&benchmark_results));
+    }
+
+    if let Some(report_format) = bench_args.report {
+        let report_path = match &bench_args.report_path {
+            Some(report_path) => report_path.clone(),
+            None => format!("report.{}", report_format.extension()),
+        };
+        let report = Report::new(
+            &bench_args.input.input_image_path,
+            &mosaic_factory,
+            &results,
+        );
+        report
+            .save(&report_path, report_format)
+            .map_err(|e| format!("Failed to save benchmark report at {}: {}", report_path, e))?;
+        log::info!("Benchmark report saved at: {}", report_path);
+    }
+    return Ok(());
+}
+
+/// Prints correctness diagnostics and returns whether every checked algorithm matches the serial one.
+fn run_verify(verify_args: &VerifyArgs) -> Result<bool, CommandError> {
+    let mosaic_factory = create_mosaic_factory(&verify_args.input, &verify_args.alpha)?;
+    let correctness_args = &verify_args.correctness;
+    let results: Vec<CorrectnessDiagnostics> = match verify_args.all_algorithms {
+        true => AlgorithmType::value_variants()
+            .iter()
+            .map(|algorithm_type| {
+                return mosaic_factory.check_builder_correctness(
+                    algorithm_type.mosaic_builder().as_ref(),
+                    correctness_args.correctness_mode,
+                    correctness_args.max_mismatches,
+                );
+            })
+            .collect(),
+        false => vec![mosaic_factory.check_correctness(
+            correctness_args.correctness_mode,
+            correctness_args.max_mismatches,
+        )],
+    };
+
+    for correctness_diagnostics in &results {
+        println!("{}", correctness_diagnostics);
+        println!();
+    }
+
+    if let Some(diff_image_path) = &verify_args.diff_image_path {
+        for correctness_diagnostics in &results {
+            let path = match verify_args.all_algorithms {
+                true => {
+                    builder_diff_image_path(diff_image_path, &correctness_diagnostics.builder_name)
+                }
+                false => diff_image_path.clone(),
+            };
+            let diff_image = correctness_diagnostics.diff_image(&mosaic_factory);
+            MosaicFactory::save_buffer(
+                &path,
+                &diff_image,
+                mosaic_factory.image_data.width,
+                mosaic_factory.image_data.height,
+                image::ColorType::Rgb8,
+            )
+            .map_err(|e| format!("Failed to save correctness diff image at {}: {}", path, e))?;
+            log::info!("Correctness diff image saved at: {}", path);
+        }
+    }
+
+    return Ok(results
+        .iter()
+        .all(|correctness_diagnostics| correctness_diagnostics.is_correct()));
+}
+
+fn run_index(index_args: &IndexArgs) -> Result<(), CommandError> {
+    let mosaic_factory = create_mosaic_factory(&index_args.input, &index_args.alpha)?;
+    let tile_index = TileIndex::new(&index_args.input.input_image_path, &mosaic_factory);
+    let path = &index_args.output_index_path;
+    tile_index
+        .save(path)
+        .map_err(|e| format!("Failed to save tile index at {}: {}", path, e))?;
+    log::info!(
+        "Tile index of {}x{} tiles saved at: {}",
+        tile_index.tiles_x,
+        tile_index.tiles_y,
+        path
     );
+    return Ok(());
+}
 
-    run_workflow(&mosaic_factory, &cli_args);
+fn run_info(info_args: &InfoArgs) -> Result<(), CommandError> {
+    let input_image_path = Path::new(&info_args.input_image_path);
+    check_input_exists(input_image_path)?;
+    let image_info = ImageInfo::read(input_image_path, info_args.tiff_page)
+        .map_err(|e| format!("Error reading image {}: {}", input_image_path.display(), e))?;
+    let ((tiles_x, tiles_y), (cropped_x, cropped_y)) =
+        image_info.tile_grid(info_args.tile_side_length);
+    println!("{}", image_info);
+    println!(
+        "Tiles: {}x{} of {} pixels, {}x{} pixels cropped",
+        tiles_x, tiles_y, info_args.tile_side_length, cropped_x, cropped_y
+    );
+    return Ok(());
 }
 
-fn run_workflow(mosaic_factory: &MosaicFactory, cli_args: &CLIArgs) {
-    if let Some(benchmark_runs) = cli_args.benchmark_runs {
-        println!("Checking algorithm correctness...");
-        let correctness_results = mosaic_factory.check_correctness();
-        println!("Stage 1 incorrect values: {:?}", correctness_results.0);
-        println!("Stage 2 incorrect values: {:?}", correctness_results.1);
-        println!("Stage 3 incorrect values: {:?}", correctness_results.2);
+/// Inserts the builder name before the extension of the diff image path.
+fn builder_diff_image_path(diff_image_path: &str, builder_name: &str) -> String {
+    let path = Path::new(diff_image_path);
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let file_name = match path.extension() {
+        Some(extension) => format!("{}-{}.{}", stem, builder_name, extension.to_string_lossy()),
+        None => format!("{}-{}", stem, builder_name),
+    };
+    return path
+        .with_file_name(file_name)
+        .to_string_lossy()
+        .into_owned();
+}
+
+fn run_streaming_workflow(
+    streaming_mosaic: &StreamingMosaic,
+    input_image_path: &Path,
+    generate_args: &GenerateArgs,
+) -> Result<(), CommandError> {
+    let path = &generate_args.output_image_path;
+    streaming_mosaic
+        .generate_and_save_mosaic(input_image_path, path)
+        .map_err(|e| format!("Failed to save mosaic at {}: {}", path, e))?;
+    log::info!("Successfully generated and saved mosaic at: {}", path);
+    return Ok(());
+}
+
+fn run_animated_workflow(
+    animated_mosaic: &AnimatedMosaic,
+    generate_args: &GenerateArgs,
+) -> Result<(), CommandError> {
+    if has_regions(generate_args)
+        || generate_args.tile_size_mask.is_some()
+        || generate_args.tiling != TilingStrategy::Uniform
+        || generate_args.pyramid.is_some()
+    {
+        return Err(CommandError::usage(
+            "Regions, variable tile size and pyramids are not available for animated input",
+        ));
+    }
+    check_file_output(generate_args, "Animated mosaics")?;
+
+    let path = &generate_args.output_image_path;
+    animated_mosaic
+        .generate_and_save_mosaic(path)
+        .map_err(|e| format!("Failed to save animated mosaic at {}: {}", path, e))?;
+    log::info!(
+        "Successfully generated and saved {} frame animated mosaic at: {}",
+        animated_mosaic.animation.frames.len(),
+        path
+    );
+    return Ok(());
+}
+
+fn run_video_workflow(generate_args: &GenerateArgs) -> Result<(), CommandError> {
+    let input_args = &generate_args.input;
+    let input_path = &input_args.input_image_path;
+    let output_path = &generate_args.output_image_path;
+    let video_mosaic = VideoMosaic::new(
+        input_args.algorithm_type.clone(),
+        input_args.tile_side_length,
+    )
+    .with_alpha_options(generate_args.alpha.alpha_options())
+    .with_output_size(generate_args.output_scale, generate_args.output_size)
+    .with_temporal_smoothing(generate_args.temporal_smoothing);
+
+    let mut frame_reader = open_frame_reader(input_path)
+        .map_err(|e| format!("Failed to open video input {}: {}", input_path, e))?;
+    let mut frame_writer = open_frame_writer(output_path, frame_reader.y4m_header())
+        .map_err(|e| format!("Failed to open video output {}: {}", output_path, e))?;
+    let frame_count = video_mosaic
+        .process(frame_reader.as_mut(), frame_writer.as_mut())
+        .map_err(|e| format!("Failed to process video frames: {}", e))?;
+    log::info!(
+        "Successfully generated and saved {} mosaic frames at: {}",
+        frame_count,
+        output_path
+    );
+    return Ok(());
+}
+
+fn run_reveal_workflow(
+    reveal_mosaic: &RevealMosaic,
+    generate_args: &GenerateArgs,
+) -> Result<(), CommandError> {
+    let tile_sizes = RevealMosaic::tile_sizes(
+        generate_args.input.tile_side_length,
+        generate_args.reveal_to,
+        generate_args.reveal_frames,
+    );
+    log::verbose!("Reveal tile sizes: {:?}", tile_sizes);
+    let animation = reveal_mosaic.generate_animation(
+        &tile_sizes,
+        generate_args.reveal_frame_delay,
+        generate_args.reveal_hold,
+    );
+    let path = &generate_args.output_image_path;
+    animation
+        .save(path, &generate_args.output.output_options())
+        .map_err(|e| format!("Failed to save reveal animation at {}: {}", path, e))?;
+    log::info!(
+        "Successfully generated and saved reveal animation at: {}",
+        path
+    );
+    return Ok(());
+}
+
+fn run_hdr_workflow(
+    mosaic_factory: &MosaicFactory<f32>,
+    generate_args: &GenerateArgs,
+) -> Result<(), CommandError> {
+    let path = &generate_args.output_image_path;
+    let (width, height) = generate_args.output_size.unwrap_or(
+        mosaic_factory.output_size(generate_args.output_scale.unwrap_or(OutputScale::Full)),
+    );
+    mosaic_factory
+        .generate_and_save_hdr_mosaic(path, width, height, generate_args.tone_mapping)
+        .map_err(|e| format!("Failed to save mosaic at {}: {}", path, e))?;
+    log::info!("Successfully generated and saved mosaic at: {}", path);
+    return Ok(());
+}
+
+fn run_sweep_workflow(sweep_args: &SweepArgs) -> Result<(), CommandError> {
+    let input_image_path = Path::new(&sweep_args.input_image_path);
+    let sweep_config = sweep_args.sweep_config();
+    let chart_path = &sweep_args.chart_path;
+    check_input_exists(input_image_path)?;
+    let img =
+        ImageData::load_image(input_image_path).map_err(|e| load_error(input_image_path, e))?;
+    let points = sweep::run_sweep(&img, &sweep_config);
+    println!();
+    println!("{}", sweep::sweep_table(&points));
+
+    if let Some(parent) = Path::new(chart_path).parent() {
+        std::fs::create_dir_all(parent).map_err(|e| {
+            format!(
+                "Failed to create directory for sweep chart {}: {}",
+                chart_path, e
+            )
+        })?;
+    }
+    std::fs::write(chart_path, sweep::sweep_chart(&points))
+        .map_err(|e| format!("Failed to save sweep chart at {}: {}", chart_path, e))?;
+    log::info!("Sweep chart saved at: {}", chart_path);
+    return Ok(());
+}
+
+/// Returns whether any image failed.
+fn run_batch_workflow(batch_args: &BatchArgs) -> Result<bool, CommandError> {
+    let batch_inputs = batch::collect_inputs(&batch_args.inputs)
+        .map_err(|e| format!("Error collecting batch inputs: {}", e))?;
+    if batch_inputs.is_empty() {
+        return Err("No images found in batch inputs".into());
+    }
+    let summary = batch::run_batch(&batch_args.batch_config(), &batch_inputs);
+    println!();
+    println!(
+        "Batch finished: {} succeeded, {} failed, {} skipped",
+        summary.succeeded,
+        summary.failures.len(),
+        summary.skipped
+    );
+    for (path, e) in &summary.failures {
+        println!("  {}: {}", path.display(), e);
+    }
+    return Ok(!summary.failures.is_empty());
+}
+
+/// Checks whether regions or redaction are requested, without reading region files.
+fn has_regions(generate_args: &GenerateArgs) -> bool {
+    return !generate_args.region.is_empty()
+        || !generate_args.region_polygon.is_empty()
+        || generate_args.regions_file.is_some()
+        || generate_args.region_mask.is_some()
+        || generate_args.redact;
+}
+
+fn collect_regions(generate_args: &GenerateArgs) -> Result<Vec<Region>, CommandError> {
+    let mut regions: Vec<Region> = generate_args.region.clone();
+    regions.extend(generate_args.region_polygon.iter().cloned());
+    if let Some(regions_file) = &generate_args.regions_file {
+        let file_regions = Region::from_json_file(regions_file)
+            .map_err(|e| format!("Error reading regions file {}: {}", regions_file, e))?;
+        regions.extend(file_regions);
+    }
+    if let Some(region_mask) = &generate_args.region_mask {
+        let mask_region = Region::from_mask_image(region_mask)
+            .map_err(|e| format!("Error loading region mask {}: {}", region_mask, e))?;
+        regions.push(mask_region);
+    }
+    if generate_args.redact && regions.is_empty() {
+        regions.push(Region::Rectangle {
+            x: 0,
+            y: 0,
+            width: u32::MAX,
+            height: u32::MAX,
+        });
+    }
+    return Ok(regions);
+}
+
+fn run_region_workflow(
+    region_mosaic: &RegionMosaic,
+    generate_args: &GenerateArgs,
+) -> Result<(), CommandError> {
+    let path = &generate_args.output_image_path;
+    region_mosaic
+        .generate_and_save_mosaic(path)
+        .map_err(|e| format!("Failed to save mosaic at {}: {}", path, e))?;
+    log::info!("Successfully generated and saved mosaic at: {}", path);
+    return Ok(());
+}
+
+fn run_adaptive_workflow(
+    adaptive_mosaic: &AdaptiveMosaic,
+    generate_args: &GenerateArgs,
+) -> Result<(), CommandError> {
+    let path = &generate_args.output_image_path;
+    adaptive_mosaic
+        .generate_and_save_mosaic(path)
+        .map_err(|e| format!("Failed to save mosaic at {}: {}", path, e))?;
+    log::info!("Successfully generated and saved mosaic at: {}", path);
+    return Ok(());
+}
+
+/// Prints median stage time changes, missing builders and correctness changes and returns whether any of them is a
+/// regression.
+fn compare_reports(compare_args: &CompareArgs) -> Result<bool, CommandError> {
+    let threshold = compare_args.threshold;
+    let read_report = |path: &str| {
+        return Report::from_json_file(path)
+            .map_err(|e| format!("Error reading report {}: {}", path, e));
+    };
+    let baseline = read_report(&compare_args.baseline_report_path)?;
+    let candidate = read_report(&compare_args.candidate_report_path)?;
+    let comparison = report::compare_reports(&baseline, &candidate)
+        .map_err(|e| format!("Cannot compare reports: {}", e))?;
+    if comparison.stages.is_empty() && comparison.missing_builders.is_empty() {
+        println!("No common builder stages found in reports");
+        return Ok(true);
+    }
+
+    println!(
+        "{:<16} {:<8} {:>14} {:>14} {:>10}",
+        "builder", "stage", "baseline", "candidate", "change"
+    );
+    for stage in &comparison.stages {
         println!(
-            "Global average incorrect values: {:?}",
-            correctness_results.3
+            "{:<16} {:<8} {:>14} {:>14} {:>9.2}%{}",
+            stage.builder,
+            stage.stage,
+            format!("{:.2?}", Duration::from_nanos(stage.baseline_ns as u64)),
+            format!("{:.2?}", Duration::from_nanos(stage.candidate_ns as u64)),
+            stage.change,
+            if stage.is_regression(threshold) {
+                " REGRESSION"
+            } else {
+                ""
+            }
         );
-        println!("\nBenchmarking...");
-        let benchmark_results = mosaic_factory.benchmark(benchmark_runs);
-        println!("Stage 1 time: {:?}", benchmark_results.0);
-        println!("Stage 2 time: {:?}", benchmark_results.1);
-        println!("Stage 3 time: {:?}", benchmark_results.2);
+    }
+    for builder in &comparison.missing_builders {
+        println!("{:<16} missing from candidate REGRESSION", builder);
+    }
+    for correctness in &comparison.correctness {
         println!(
-            "Total time: {:?}",
-            benchmark_results.0 + benchmark_results.1 + benchmark_results.2
+            "{:<16} incorrect values: stage 1: {} -> {}, stage 2: {} -> {}, stage 3: {} -> {}, global average: {} -> {}, \
+             max absolute error: {} -> {} REGRESSION",
+            correctness.builder,
+            correctness.baseline.stage_1,
+            correctness.candidate.stage_1,
+            correctness.baseline.stage_2,
+            correctness.candidate.stage_2,
+            correctness.baseline.stage_3,
+            correctness.candidate.stage_3,
+            correctness.baseline.global_average,
+            correctness.candidate.global_average,
+            correctness.baseline.max_absolute_error,
+            correctness.candidate.max_absolute_error
         );
     }
-
-    match &cli_args.output_image_path {
-        Some(path) => match mosaic_factory.generate_and_save_mosaic(path) {
-            Ok(_) => println!("Successfully generated and saved mosaic at: {}", path),
-            Err(_) => panic!("Failed to save mosaic at: {}", path),
-        },
-        None => println!("Result discarded, no output path provided"),
-    }
+    return Ok(comparison.is_regression(threshold));
 }
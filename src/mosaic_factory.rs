@@ -1,27 +1,42 @@
-use crate::{ImageData, SerialMosaic};
-use image::{ImageFormat, ImageResult};
-use std::io;
+use crate::alpha::{self, AlphaOptions};
+use crate::args::{CorrectnessMode, OutputScale};
+use crate::benchmark::{BenchmarkResults, StageStatistics};
+use crate::correctness::{self, CorrectnessDiagnostics};
+use crate::image_data::{ImageData, Sample};
+use crate::image_io::{self, OutputOptions};
+use crate::log;
+use image::ImageResult;
+use rayon::prelude::*;
 use std::path::Path;
-use std::time::{Duration, Instant};
+use std::time::Instant;
+
+pub trait MosaicBuilder<S: Sample = u8>: Sync {
+    /// Name identifying the builder in benchmark results.
+    fn name(&self) -> &'static str;
 
-pub trait MosaicBuilder: Sync {
     /// Adds together channels in pixels belonging to the same tile, each channel is summed to a separate value.
-    fn sum_tile_channels(&self, mosaic_factory: &MosaicFactory) -> Vec<u32>;
+    fn sum_tile_channels(&self, mosaic_factory: &MosaicFactory<S>) -> Vec<S::Sum>;
 
     /// Calculates the average of each of the channels in a tile. Also calculates global image average.
-    fn calc_tile_average(&self, mosaic_factory: &MosaicFactory, _: &[u32]) -> (Vec<u8>, Vec<u8>);
+    fn calc_tile_average(
+        &self,
+        mosaic_factory: &MosaicFactory<S>,
+        _: &[S::Sum],
+    ) -> (Vec<S>, Vec<S>);
 
     /// Creates a mosaic from the tile averages.
-    fn create_mosaic(&self, mosaic_factory: &MosaicFactory, _: &[u8]) -> Vec<u8>;
+    fn create_mosaic(&self, mosaic_factory: &MosaicFactory<S>, _: &[S]) -> Vec<S>;
 }
 
-pub struct MosaicFactory {
+pub struct MosaicFactory<S: Sample = u8> {
     pub tile_side_length: u32,
     pub tile_pixels: u32,
     pub tiles_x: u32,
     pub tiles_y: u32,
-    pub image_data: ImageData,
-    pub mosaic_builder: Box<dyn MosaicBuilder>,
+    pub image_data: ImageData<S>,
+    pub mosaic_builder: Box<dyn MosaicBuilder<S>>,
+    pub alpha_options: AlphaOptions,
+    pub output_options: OutputOptions,
 }
 
 impl MosaicFactory {
@@ -29,8 +44,23 @@ impl MosaicFactory {
         input_image_path: P,
         mosaic_builder: Box<dyn MosaicBuilder>,
         tile_side_length: u32,
-    ) -> MosaicFactory {
-        let image_data = ImageData::from_path(input_image_path, tile_side_length);
+    ) -> ImageResult<MosaicFactory> {
+        let image_data = ImageData::from_path(input_image_path, tile_side_length)?;
+        return Ok(MosaicFactory::from_image_data(
+            image_data,
+            mosaic_builder,
+            tile_side_length,
+        ));
+    }
+}
+
+impl<S: Sample> MosaicFactory<S> {
+    /// Creates factory from image data already cropped to a multiple of the tile side length.
+    pub fn from_image_data(
+        image_data: ImageData<S>,
+        mosaic_builder: Box<dyn MosaicBuilder<S>>,
+        tile_side_length: u32,
+    ) -> MosaicFactory<S> {
         return MosaicFactory {
             tile_side_length,
             tile_pixels: tile_side_length * tile_side_length,
@@ -38,71 +68,105 @@ impl MosaicFactory {
             tiles_y: image_data.height / tile_side_length,
             image_data,
             mosaic_builder,
+            alpha_options: AlphaOptions::default(),
+            output_options: OutputOptions::default(),
         };
     }
 
-    pub fn generate_mosaic(&self) -> Vec<u8> {
+    pub fn with_alpha_options(mut self, alpha_options: AlphaOptions) -> MosaicFactory<S> {
+        self.alpha_options = alpha_options;
+        return self;
+    }
+
+    pub fn with_output_options(mut self, output_options: OutputOptions) -> MosaicFactory<S> {
+        self.output_options = output_options;
+        return self;
+    }
+
+    /// Tile averages and the global average with the alpha mode applied.
+    pub fn tile_averages(&self) -> (Vec<S>, Vec<S>) {
         let tile_sum = self.mosaic_builder.sum_tile_channels(self);
-        let average_results = self.mosaic_builder.calc_tile_average(self, &tile_sum);
-        println!("Image global average: {:?}", average_results.1);
+        let mut average_results = self.mosaic_builder.calc_tile_average(self, &tile_sum);
+        if alpha::has_alpha(&self.image_data) {
+            let channels = self.image_data.channels;
+            alpha::resolve_alpha(&mut average_results.0, channels, &self.alpha_options);
+            alpha::resolve_alpha(&mut average_results.1, channels, &self.alpha_options);
+        }
+        log::verbose!("Image global average: {:?}", average_results.1);
+        return average_results;
+    }
+
+    pub fn generate_mosaic(&self) -> Vec<S> {
+        let average_results = self.tile_averages();
         return self.mosaic_builder.create_mosaic(self, &average_results.0);
     }
 
-    pub fn benchmark(&self, benchmark_runs: u32) -> (Duration, Duration, Duration) {
-        let mut sum_tile_channels_time = Duration::new(0, 0);
-        let mut calc_tile_average_time = Duration::new(0, 0);
-        let mut create_mosaic_time = Duration::new(0, 0);
+    pub fn benchmark(&self, warmup_runs: u32, benchmark_runs: u32) -> BenchmarkResults {
+        return self.benchmark_builder(self.mosaic_builder.as_ref(), warmup_runs, benchmark_runs);
+    }
+
+    /// Benchmarks any builder on the image of this factory, warm-up runs are not measured.
+    pub fn benchmark_builder(
+        &self,
+        mosaic_builder: &dyn MosaicBuilder<S>,
+        warmup_runs: u32,
+        benchmark_runs: u32,
+    ) -> BenchmarkResults {
+        if benchmark_runs == 0 {
+            panic!("Number of benchmark runs must be greater than 0");
+        }
+
+        for _ in 0..warmup_runs {
+            let tile_sum = mosaic_builder.sum_tile_channels(self);
+            let tile_average = mosaic_builder.calc_tile_average(self, &tile_sum);
+            mosaic_builder.create_mosaic(self, &tile_average.0);
+        }
+
+        let mut sum_tile_channels_samples = Vec::with_capacity(benchmark_runs as usize);
+        let mut calc_tile_average_samples = Vec::with_capacity(benchmark_runs as usize);
+        let mut create_mosaic_samples = Vec::with_capacity(benchmark_runs as usize);
+        let mut total_samples = Vec::with_capacity(benchmark_runs as usize);
 
         for _ in 0..benchmark_runs {
             let start = Instant::now();
-            let tile_sum = self.mosaic_builder.sum_tile_channels(self);
-            sum_tile_channels_time += start.elapsed();
+            let tile_sum = mosaic_builder.sum_tile_channels(self);
+            let sum_tile_channels_time = start.elapsed();
 
             let start = Instant::now();
-            let tile_average = self.mosaic_builder.calc_tile_average(self, &tile_sum);
-            calc_tile_average_time += start.elapsed();
+            let tile_average = mosaic_builder.calc_tile_average(self, &tile_sum);
+            let calc_tile_average_time = start.elapsed();
 
             let start = Instant::now();
-            self.mosaic_builder.create_mosaic(self, &tile_average.0);
-            create_mosaic_time += start.elapsed();
+            mosaic_builder.create_mosaic(self, &tile_average.0);
+            let create_mosaic_time = start.elapsed();
+
+            sum_tile_channels_samples.push(sum_tile_channels_time);
+            calc_tile_average_samples.push(calc_tile_average_time);
+            create_mosaic_samples.push(create_mosaic_time);
+            total_samples
+                .push(sum_tile_channels_time + calc_tile_average_time + create_mosaic_time);
         }
 
-        return (
-            sum_tile_channels_time / benchmark_runs,
-            calc_tile_average_time / benchmark_runs,
-            create_mosaic_time / benchmark_runs,
-        );
+        return BenchmarkResults {
+            builder_name: mosaic_builder.name().to_string(),
+            warmup_runs,
+            megapixels: (self.image_data.width * self.image_data.height) as f64 / 1_000_000.0,
+            sum_tile_channels: StageStatistics::from_samples(sum_tile_channels_samples),
+            calc_tile_average: StageStatistics::from_samples(calc_tile_average_samples),
+            create_mosaic: StageStatistics::from_samples(create_mosaic_samples),
+            total: StageStatistics::from_samples(total_samples),
+        };
     }
 
-    fn prepare_file<P: AsRef<Path>>(file_name: &P) -> io::Result<()> {
-        let path = Path::new(file_name.as_ref());
-        let prefix = path
-            .parent()
-            .ok_or_else(|| io::Error::from(io::ErrorKind::NotFound))?;
-        return std::fs::create_dir_all(prefix);
-    }
-
-    pub fn save_mosaic<P: AsRef<Path>>(&self, output_img_path: &P, img: &[u8]) -> ImageResult<()> {
-        Self::prepare_file(output_img_path)?;
-
-        let extension = output_img_path.as_ref().extension();
-        let format = extension.and_then(ImageFormat::from_extension);
-        match format {
-            Some(format) => {
-                return image::save_buffer_with_format(
-                    output_img_path.as_ref(),
-                    img,
-                    self.tiles_x * self.tile_side_length,
-                    self.tiles_y * self.tile_side_length,
-                    self.image_data.color,
-                    format,
-                );
-            }
-            None => panic!(
-                "Missing or incorrect extension in output image path: {}",
-                output_img_path.as_ref().display()
-            ),
-        }
+    pub fn save_mosaic<P: AsRef<Path>>(&self, output_img_path: &P, img: &[S]) -> ImageResult<()> {
+        return image_io::save_buffer(
+            output_img_path,
+            &S::as_bytes(img),
+            self.tiles_x * self.tile_side_length,
+            self.tiles_y * self.tile_side_length,
+            self.image_data.color,
+            &self.output_options,
+        );
     }
 
     pub fn generate_and_save_mosaic<P: AsRef<Path>>(&self, output_img_path: &P) -> ImageResult<()> {
@@ -110,27 +174,315 @@ impl MosaicFactory {
         return self.save_mosaic(&output_img_path, &img);
     }
 
-    pub fn check_correctness(&self) -> (usize, usize, usize, usize) {
-        let serial_stage_1 = SerialMosaic.sum_tile_channels(self);
-        let serial_stage_2 = SerialMosaic.calc_tile_average(self, &serial_stage_1);
-        let serial_stage_3 = SerialMosaic.create_mosaic(self, &serial_stage_2.0);
-        let new_stage_1 = self.mosaic_builder.sum_tile_channels(self);
-        let new_stage_2 = self.mosaic_builder.calc_tile_average(self, &serial_stage_1);
-        let new_stage_3 = self.mosaic_builder.create_mosaic(self, &serial_stage_2.0);
+    pub fn output_size(&self, output_scale: OutputScale) -> (u32, u32) {
+        return match output_scale {
+            OutputScale::Full => (
+                self.tiles_x * self.tile_side_length,
+                self.tiles_y * self.tile_side_length,
+            ),
+            OutputScale::Tile => (self.tiles_x, self.tiles_y),
+        };
+    }
+
+    /// Renders tile averages at any size, every output pixel takes the average of the tile under its centre.
+    pub fn render_tile_grid(&self, tile_averages: &[S], width: u32, height: u32) -> Vec<S> {
+        let channels = self.image_data.channels as usize;
+        let tile_columns: Vec<usize> = (0..width)
+            .map(|x| ((2 * x as u64 + 1) * self.tiles_x as u64 / (2 * width as u64)) as usize)
+            .collect();
+        let mut img = vec![S::default(); width as usize * height as usize * channels];
+        img.par_chunks_mut(width as usize * channels)
+            .enumerate()
+            .for_each(|(y, row)| {
+                let tile_y =
+                    ((2 * y as u64 + 1) * self.tiles_y as u64 / (2 * height as u64)) as usize;
+                let tile_row = tile_y * self.tiles_x as usize;
+                for (pixel, tile_x) in row.chunks_exact_mut(channels).zip(&tile_columns) {
+                    let tile = (tile_row + tile_x) * channels;
+                    pixel.copy_from_slice(&tile_averages[tile..tile + channels]);
+                }
+            });
+        return img;
+    }
+
+    /// Generates the mosaic at any size, the input size is rendered by the mosaic builder and other sizes
+    /// rescale the grid of tile averages.
+    pub fn generate_mosaic_with_size(&self, width: u32, height: u32) -> Vec<S> {
+        if (width, height) == self.output_size(OutputScale::Full) {
+            return self.generate_mosaic();
+        }
+        let (tile_averages, _) = self.tile_averages();
+        return self.render_tile_grid(&tile_averages, width, height);
+    }
+
+    pub fn generate_and_save_mosaic_with_size<P: AsRef<Path>>(
+        &self,
+        output_img_path: &P,
+        width: u32,
+        height: u32,
+    ) -> ImageResult<()> {
+        let img = self.generate_mosaic_with_size(width, height);
+        return image_io::save_buffer(
+            output_img_path,
+            &S::as_bytes(&img),
+            width,
+            height,
+            self.image_data.color,
+            &self.output_options,
+        );
+    }
+}
+
+impl MosaicFactory {
+    /// Saves raw image buffer in the format matching the output path extension with default encoder settings.
+    pub fn save_buffer<P: AsRef<Path>>(
+        output_img_path: &P,
+        img: &[u8],
+        width: u32,
+        height: u32,
+        color: image::ColorType,
+    ) -> ImageResult<()> {
+        return image_io::save_buffer(
+            output_img_path,
+            img,
+            width,
+            height,
+            color,
+            &OutputOptions::default(),
+        );
+    }
+
+    pub fn check_correctness(
+        &self,
+        mode: CorrectnessMode,
+        max_mismatches: usize,
+    ) -> CorrectnessDiagnostics {
+        return self.check_builder_correctness(self.mosaic_builder.as_ref(), mode, max_mismatches);
+    }
+
+    /// Compares stages of any builder with the serial implementation.
+    pub fn check_builder_correctness(
+        &self,
+        mosaic_builder: &dyn MosaicBuilder,
+        mode: CorrectnessMode,
+        max_mismatches: usize,
+    ) -> CorrectnessDiagnostics {
+        return correctness::check_builder(self, mosaic_builder, mode, max_mismatches);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    use super::*;
+    use crate::args::AlgorithmType;
+    use crate::synthetic::{ChannelLayout, Pattern, SyntheticImage};
+    use crate::SerialMosaic;
+    use clap::ValueEnum;
 
-        fn calc_diff<U: PartialEq<W>, W: PartialEq<U>>(vec_1: Vec<U>, vec_2: Vec<W>) -> usize {
-            return vec_1.iter().zip(&vec_2).filter(|(a, b)| a != b).count();
+    const CASES: u64 = 48;
+
+    /// Noise image of random size, channel layout and tile side length with a description of the case.
+    fn random_mosaic_factory(
+        rng: &mut StdRng,
+        algorithm_type: &AlgorithmType,
+    ) -> (MosaicFactory, String) {
+        let layout = [
+            ChannelLayout::L,
+            ChannelLayout::La,
+            ChannelLayout::Rgb,
+            ChannelLayout::Rgba,
+        ][rng.gen_range(0..4)];
+        let width = rng.gen_range(1..=160);
+        let height = rng.gen_range(1..=160);
+        let tile_side_length = rng.gen_range(1..=width.min(height).min(48));
+        let image_seed = rng.gen();
+        let img = SyntheticImage::new(Pattern::Noise, width, height, layout)
+            .with_seed(image_seed)
+            .generate();
+        let description = format!(
+            "{:?} builder, {:?} noise {}x{} with image seed {}, tile side length {}",
+            algorithm_type, layout, width, height, image_seed, tile_side_length
+        );
+        let mosaic_factory = MosaicFactory::from_image_data(
+            ImageData::new(&img, tile_side_length),
+            algorithm_type.mosaic_builder(),
+            tile_side_length,
+        );
+        return (mosaic_factory, description);
+    }
+
+    /// Runs `test` on random cases for every builder and prints the seed and parameters of a failing case. Set
+    /// `MOSAIC_TEST_SEED` to run only the case of that seed.
+    fn for_random_cases(test: impl Fn(u64, &MosaicFactory)) {
+        let seeds = match std::env::var("MOSAIC_TEST_SEED") {
+            Ok(seed) => {
+                let seed = seed.parse().expect("MOSAIC_TEST_SEED must be a number");
+                seed..seed + 1
+            }
+            Err(_) => 0..CASES,
+        };
+        for case in seeds {
+            for algorithm_type in AlgorithmType::value_variants() {
+                let mut rng = StdRng::seed_from_u64(case);
+                let (mosaic_factory, description) = random_mosaic_factory(&mut rng, algorithm_type);
+                let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+                    test(case, &mosaic_factory);
+                }));
+                if let Err(panic) = result {
+                    eprintln!(
+                        "Failing case with seed {}: {}, rerun with MOSAIC_TEST_SEED={}",
+                        case, description, case
+                    );
+                    std::panic::resume_unwind(panic);
+                }
+            }
         }
-        let stage_1_diff = calc_diff(new_stage_1, serial_stage_1);
-        let stage_2_diff = calc_diff(new_stage_2.0, serial_stage_2.0);
-        let global_average_diff = calc_diff(new_stage_2.1, serial_stage_2.1);
-        let stage_3_diff = calc_diff(new_stage_3, serial_stage_3);
-
-        return (
-            stage_1_diff,
-            stage_2_diff,
-            stage_3_diff,
-            global_average_diff,
+    }
+
+    #[test]
+    fn builders_match_serial_in_every_stage() {
+        for_random_cases(|case, mosaic_factory| {
+            for mode in CorrectnessMode::value_variants() {
+                let diagnostics = mosaic_factory.check_correctness(*mode, 5);
+                assert!(diagnostics.is_correct(), "case {}: {}", case, diagnostics);
+            }
+        });
+    }
+
+    #[test]
+    fn tile_averages_are_bounded_by_tile_sums() {
+        for_random_cases(|case, mosaic_factory| {
+            let builder = mosaic_factory.mosaic_builder.as_ref();
+            let tile_sum = builder.sum_tile_channels(mosaic_factory);
+            let (tile_average, global_average) =
+                builder.calc_tile_average(mosaic_factory, &tile_sum);
+            assert_eq!(tile_sum.len(), tile_average.len(), "case {}", case);
+            let channels = mosaic_factory.image_data.channels as usize;
+            let has_alpha = alpha::has_alpha(&mosaic_factory.image_data);
+            let weight = |channel: usize, sums: &[u64], pixels: u64| {
+                return match has_alpha && channel != channels - 1 {
+                    true => sums[channels - 1],
+                    false => pixels,
+                };
+            };
+            for (sums, averages) in tile_sum
+                .chunks_exact(channels)
+                .zip(tile_average.chunks_exact(channels))
+            {
+                for (channel, (&sum, &average)) in sums.iter().zip(averages).enumerate() {
+                    let divisor = weight(channel, sums, mosaic_factory.tile_pixels as u64);
+                    let lower = average as u64 * divisor;
+                    assert!(
+                        lower <= sum && sum < lower + divisor.max(1),
+                        "case {}",
+                        case
+                    );
+                }
+            }
+
+            for (channel, &average) in global_average.iter().enumerate() {
+                let weighted_tiles = tile_average
+                    .chunks_exact(channels)
+                    .filter(|tile| !has_alpha || channel == channels - 1 || tile[channels - 1] > 0);
+                let channel_averages = weighted_tiles.map(|tile| tile[channel]);
+                let min = channel_averages.clone().min().unwrap_or(0);
+                let max = channel_averages.max().unwrap_or(0);
+                assert!(
+                    min <= average && average <= max,
+                    "case {} channel {}",
+                    case,
+                    channel
+                );
+            }
+        });
+    }
+
+    #[test]
+    fn mosaic_is_uniform_within_tiles() {
+        for_random_cases(|case, mosaic_factory| {
+            let mosaic = mosaic_factory.generate_mosaic();
+            let channels = mosaic_factory.image_data.channels as usize;
+            let width = mosaic_factory.image_data.width;
+            assert_eq!(
+                mosaic.len(),
+                mosaic_factory.image_data.data.len(),
+                "case {}",
+                case
+            );
+            for (index, pixel) in mosaic.chunks_exact(channels).enumerate() {
+                let (x, y) = (index as u32 % width, index as u32 / width);
+                let origin_x = x - x % mosaic_factory.tile_side_length;
+                let origin_y = y - y % mosaic_factory.tile_side_length;
+                let origin = ((origin_y * width + origin_x) as usize) * channels;
+                assert_eq!(
+                    pixel,
+                    &mosaic[origin..origin + channels],
+                    "case {} pixel ({}, {})",
+                    case,
+                    x,
+                    y
+                );
+            }
+        });
+    }
+
+    #[test]
+    fn tile_grid_rendering_matches_mosaic_at_every_scale() {
+        for_random_cases(|case, mosaic_factory| {
+            let (tile_averages, _) = mosaic_factory.tile_averages();
+            let (full_width, full_height) = mosaic_factory.output_size(OutputScale::Full);
+            assert!(
+                mosaic_factory.render_tile_grid(&tile_averages, full_width, full_height)
+                    == mosaic_factory.generate_mosaic(),
+                "case {} full scale",
+                case
+            );
+            let (tiles_x, tiles_y) = mosaic_factory.output_size(OutputScale::Tile);
+            assert!(
+                mosaic_factory.render_tile_grid(&tile_averages, tiles_x, tiles_y) == tile_averages,
+                "case {} tile scale",
+                case
+            );
+
+            // doubling the tile grid repeats every tile average in a 2x2 block
+            let channels = mosaic_factory.image_data.channels as usize;
+            let doubled = mosaic_factory.render_tile_grid(&tile_averages, tiles_x * 2, tiles_y * 2);
+            for (index, pixel) in doubled.chunks_exact(channels).enumerate() {
+                let (x, y) = (index as u32 % (tiles_x * 2), index as u32 / (tiles_x * 2));
+                let tile = ((y / 2 * tiles_x + x / 2) as usize) * channels;
+                assert_eq!(
+                    pixel,
+                    &tile_averages[tile..tile + channels],
+                    "case {}",
+                    case
+                );
+            }
+        });
+    }
+
+    #[test]
+    fn golden_tile_averages() {
+        let img = SyntheticImage::new(Pattern::Gradient, 8, 4, ChannelLayout::Rgb).generate();
+        let mosaic_factory =
+            MosaicFactory::from_image_data(ImageData::new(&img, 4), Box::new(SerialMosaic), 4);
+        let tile_sum = SerialMosaic.sum_tile_channels(&mosaic_factory);
+        let (tile_average, global_average) =
+            SerialMosaic.calc_tile_average(&mosaic_factory, &tile_sum);
+        assert_eq!(tile_average, vec![54, 127, 76, 200, 127, 178]);
+        assert_eq!(global_average, vec![127, 127, 127]);
+
+        let checkerboard = SyntheticImage::new(Pattern::Checkerboard, 12, 8, ChannelLayout::La)
+            .with_cell_side_length(2)
+            .generate();
+        let mosaic_factory = MosaicFactory::from_image_data(
+            ImageData::new(&checkerboard, 4),
+            Box::new(SerialMosaic),
+            4,
         );
+        // Half of the pixels are (255, 255) and half (0, 128), colour is 255 * 255 / 383 truncated.
+        assert_eq!(mosaic_factory.generate_mosaic(), [169, 191].repeat(12 * 8));
     }
 }
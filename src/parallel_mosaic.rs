@@ -1,5 +1,7 @@
 use rayon::prelude::*;
 
+use crate::alpha;
+use crate::image_data::Sample;
 use crate::mosaic_factory::MosaicBuilder;
 use crate::MosaicFactory;
 
@@ -7,11 +9,16 @@ use crate::MosaicFactory;
 #[derive(Clone, Copy, Debug)]
 pub struct ParallelMosaic;
 
-impl MosaicBuilder for ParallelMosaic {
-    fn sum_tile_channels(&self, mosaic_factory: &MosaicFactory) -> Vec<u32> {
-        let size = ((mosaic_factory.tiles_x * mosaic_factory.tiles_y)
-            * mosaic_factory.image_data.channels as u32) as usize;
-        let mut tile_sum: Vec<u32> = vec![0; size];
+impl<S: Sample> MosaicBuilder<S> for ParallelMosaic {
+    fn name(&self) -> &'static str {
+        return "parallel";
+    }
+
+    fn sum_tile_channels(&self, mosaic_factory: &MosaicFactory<S>) -> Vec<S::Sum> {
+        let channels = mosaic_factory.image_data.channels as usize;
+        let size = ((mosaic_factory.tiles_x * mosaic_factory.tiles_y) as usize) * channels;
+        let mut tile_sum: Vec<S::Sum> = vec![S::Sum::default(); size];
+        let has_alpha = alpha::has_alpha(&mosaic_factory.image_data);
 
         tile_sum
             .par_iter_mut()
@@ -28,13 +35,14 @@ impl MosaicBuilder for ParallelMosaic {
                         + tile_x * mosaic_factory.tile_side_length)
                         * mosaic_factory.image_data.channels as u32) as usize;
 
-                let mut sum: u32 = 0;
+                let mut sum = S::Sum::default();
                 for pixel_y in 0..mosaic_factory.tile_side_length {
                     for pixel_x in 0..mosaic_factory.tile_side_length {
                         let pixel_index = (pixel_y * mosaic_factory.image_data.width + pixel_x)
                             * mosaic_factory.image_data.channels as u32;
-                        let index = tile_pixel_start + pixel_index as usize + channel as usize;
-                        sum += mosaic_factory.image_data.data[index] as u32;
+                        let index = tile_pixel_start + pixel_index as usize;
+                        let pixel = &mosaic_factory.image_data.data[index..index + channels];
+                        sum += alpha::weighted_sample(pixel, channel as usize, has_alpha);
                     }
                 }
 
@@ -46,51 +54,48 @@ impl MosaicBuilder for ParallelMosaic {
 
     fn calc_tile_average(
         &self,
-        mosaic_factory: &MosaicFactory,
-        tile_sum: &[u32],
-    ) -> (Vec<u8>, Vec<u8>) {
-        let mut global_sum: Vec<u128> = vec![0; mosaic_factory.image_data.channels as usize];
-        let mut tile_average: Vec<u8> = vec![0; tile_sum.len()];
-
-        let calc_tile_avg = || {
-            tile_average
-                .par_iter_mut()
-                .enumerate()
-                .for_each(|(index, tile_channel)| {
-                    *tile_channel = (tile_sum[index] / mosaic_factory.tile_pixels) as u8;
-                });
-        };
-
-        let calc_global_avg = || {
-            global_sum
-                .par_iter_mut()
-                .enumerate()
-                .for_each(|(index, global_sum_channel)| {
-                    for tile in 0..((mosaic_factory.tiles_y * mosaic_factory.tiles_x) as usize) {
-                        let channel = index;
-                        let tile_sum_channel =
-                            tile_sum[tile * mosaic_factory.image_data.channels as usize + channel];
-                        let tile_average_channel =
-                            (tile_sum_channel / mosaic_factory.tile_pixels) as u8;
-                        *global_sum_channel += tile_average_channel as u128;
-                    }
-                });
-        };
-
-        rayon::join(calc_tile_avg, calc_global_avg);
+        mosaic_factory: &MosaicFactory<S>,
+        tile_sum: &[S::Sum],
+    ) -> (Vec<S>, Vec<S>) {
+        let channels = mosaic_factory.image_data.channels as usize;
+        let has_alpha = alpha::has_alpha(&mosaic_factory.image_data);
+        let mut tile_average: Vec<S> = vec![S::default(); tile_sum.len()];
+
+        tile_average
+            .par_chunks_mut(channels)
+            .zip(tile_sum.par_chunks(channels))
+            .for_each(|(tile_average_channels, tile_sum_channels)| {
+                for (channel, average) in tile_average_channels.iter_mut().enumerate() {
+                    *average = alpha::channel_average(
+                        tile_sum_channels,
+                        channel,
+                        mosaic_factory.tile_pixels as u64,
+                        has_alpha,
+                    );
+                }
+            });
 
-        global_sum.iter_mut().for_each(|channel| {
-            *channel /= (mosaic_factory.tiles_x * mosaic_factory.tiles_y) as u128;
-        });
-        let global_average: Vec<u8> = global_sum.into_iter().map(|x| x as u8).collect();
+        let global_sum: Vec<S::Sum> = (0..channels)
+            .into_par_iter()
+            .map(|channel| {
+                return tile_average
+                    .chunks(channels)
+                    .map(|tile| alpha::weighted_sample(tile, channel, has_alpha))
+                    .sum();
+            })
+            .collect();
+        let tiles = (mosaic_factory.tiles_x * mosaic_factory.tiles_y) as u64;
+        let global_average: Vec<S> = (0..channels)
+            .map(|channel| alpha::channel_average(&global_sum, channel, tiles, has_alpha))
+            .collect();
         return (tile_average, global_average);
     }
 
-    fn create_mosaic(&self, mosaic_factory: &MosaicFactory, tile_average: &[u8]) -> Vec<u8> {
+    fn create_mosaic(&self, mosaic_factory: &MosaicFactory<S>, tile_average: &[S]) -> Vec<S> {
         let size = (mosaic_factory.image_data.width
             * mosaic_factory.image_data.height
             * mosaic_factory.image_data.channels as u32) as usize;
-        let mut mosaic: Vec<u8> = vec![0; size];
+        let mut mosaic: Vec<S> = vec![S::default(); size];
 
         mosaic
             .par_chunks_mut(
@@ -0,0 +1,386 @@
+use std::path::{Path, PathBuf};
+
+use image::{ColorType, ImageResult};
+use rayon::prelude::*;
+
+use crate::image_io::{self, OutputOptions};
+use crate::MosaicFactory;
+
+/// Zoomable tile pyramid of a mosaic. Pyramid levels are rendered from the tile averages by weighting each
+/// tile with its overlap of the level pixel, so neither the full raster nor any downscaled level is kept in memory.
+pub struct TilePyramid {
+    pub width: u32,
+    pub height: u32,
+    pub tile_side_length: u32,
+    pub tiles_x: u32,
+    pub channels: u8,
+    pub color: ColorType,
+    pub tile_averages: Vec<u8>,
+    /// Side length of the pyramid image tiles
+    pub pyramid_tile_size: u32,
+    /// Encoder settings of the pyramid image tiles, the tile format always follows the pyramid format
+    pub output_options: OutputOptions,
+}
+
+/// Pyramid image tile as `(scale, x, y, width, height)` in level pixels
+type PyramidTile = (u64, u32, u32, u32, u32);
+
+impl TilePyramid {
+    pub fn new(mosaic_factory: &MosaicFactory, pyramid_tile_size: u32) -> TilePyramid {
+        if pyramid_tile_size == 0 {
+            panic!("Pyramid tile size must be greater than 0");
+        }
+        return TilePyramid {
+            width: mosaic_factory.image_data.width,
+            height: mosaic_factory.image_data.height,
+            tile_side_length: mosaic_factory.tile_side_length,
+            tiles_x: mosaic_factory.tiles_x,
+            channels: mosaic_factory.image_data.channels,
+            color: mosaic_factory.image_data.color,
+            tile_averages: mosaic_factory.tile_averages().0,
+            pyramid_tile_size,
+            output_options: OutputOptions {
+                format: None,
+                ..mosaic_factory.output_options
+            },
+        };
+    }
+
+    /// Size of the image downscaled by the scale factor, partial pixels at the edges are kept.
+    pub fn level_size(&self, scale: u64) -> (u32, u32) {
+        return (
+            (self.width as u64).div_ceil(scale) as u32,
+            (self.height as u64).div_ceil(scale) as u32,
+        );
+    }
+
+    /// Mosaic tiles overlapping each level pixel in `start..start + length` as `(tile, overlap)`.
+    fn overlaps(&self, scale: u64, start: u32, length: u32, limit: u32) -> Vec<Vec<(usize, u64)>> {
+        let tile_side_length = self.tile_side_length as u64;
+        return (start..start + length)
+            .map(|level_pixel| {
+                let pixel_start = level_pixel as u64 * scale;
+                let pixel_end = ((level_pixel as u64 + 1) * scale).min(limit as u64);
+                let first_tile = pixel_start / tile_side_length;
+                let last_tile = (pixel_end - 1) / tile_side_length;
+                return (first_tile..=last_tile)
+                    .map(|tile| {
+                        let tile_start = (tile * tile_side_length).max(pixel_start);
+                        let tile_end = ((tile + 1) * tile_side_length).min(pixel_end);
+                        return (tile as usize, tile_end - tile_start);
+                    })
+                    .collect();
+            })
+            .collect();
+    }
+
+    /// Renders a region of the mosaic downscaled by the scale factor with a box filter.
+    pub fn render_region(&self, scale: u64, x: u32, y: u32, width: u32, height: u32) -> Vec<u8> {
+        let channels = self.channels as usize;
+        let columns = self.overlaps(scale, x, width, self.width);
+        let rows = self.overlaps(scale, y, height, self.height);
+        let mut region = Vec::with_capacity(width as usize * height as usize * channels);
+        let mut sum = vec![0u64; channels];
+
+        for row in &rows {
+            let row_area: u64 = row.iter().map(|(_, overlap)| overlap).sum();
+            for column in &columns {
+                let column_area: u64 = column.iter().map(|(_, overlap)| overlap).sum();
+                sum.fill(0);
+                for &(tile_y, overlap_y) in row {
+                    for &(tile_x, overlap_x) in column {
+                        let index = (tile_y * self.tiles_x as usize + tile_x) * channels;
+                        let weight = overlap_x * overlap_y;
+                        for (channel, channel_sum) in sum.iter_mut().enumerate() {
+                            *channel_sum += self.tile_averages[index + channel] as u64 * weight;
+                        }
+                    }
+                }
+                let area = row_area * column_area;
+                region.extend(
+                    sum.iter()
+                        .map(|channel_sum| ((channel_sum + area / 2) / area) as u8),
+                );
+            }
+        }
+        return region;
+    }
+
+    /// Splits a level into pyramid tiles in row-major order.
+    fn level_tiles(&self, scale: u64) -> Vec<PyramidTile> {
+        let (level_width, level_height) = self.level_size(scale);
+        let tile_size = self.pyramid_tile_size;
+        let mut tiles = Vec::new();
+        for y in (0..level_height).step_by(tile_size as usize) {
+            for x in (0..level_width).step_by(tile_size as usize) {
+                let width = tile_size.min(level_width - x);
+                let height = tile_size.min(level_height - y);
+                tiles.push((scale, x, y, width, height));
+            }
+        }
+        return tiles;
+    }
+
+    /// Writes a Deep Zoom image, `path` with the `.dzi` extension gets the XML manifest and the directory
+    /// next to it with the `_files` suffix gets PNG tiles of every level.
+    pub fn save_dzi<P: AsRef<Path>>(&self, path: P) -> ImageResult<()> {
+        let manifest_path = path.as_ref().with_extension("dzi");
+        let stem = manifest_path
+            .file_stem()
+            .unwrap_or_default()
+            .to_string_lossy();
+        let files_path = manifest_path.with_file_name(format!("{}_files", stem));
+
+        let mut max_level = 0;
+        while (1u64 << max_level) < self.width.max(self.height) as u64 {
+            max_level += 1;
+        }
+        let tiles: Vec<(u32, PyramidTile)> = (0..=max_level)
+            .flat_map(|level| {
+                let scale = 1u64 << (max_level - level);
+                return self
+                    .level_tiles(scale)
+                    .into_iter()
+                    .map(move |tile| (level, tile));
+            })
+            .collect();
+        tiles
+            .par_iter()
+            .try_for_each(|&(level, (scale, x, y, width, height))| {
+                let tile_path = files_path.join(level.to_string()).join(format!(
+                    "{}_{}.png",
+                    x / self.pyramid_tile_size,
+                    y / self.pyramid_tile_size
+                ));
+                let region = self.render_region(scale, x, y, width, height);
+                return image_io::save_buffer(
+                    &tile_path,
+                    &region,
+                    width,
+                    height,
+                    self.color,
+                    &self.output_options,
+                );
+            })?;
+
+        let manifest = format!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+             <Image xmlns=\"http://schemas.microsoft.com/deepzoom/2008\" Format=\"png\" Overlap=\"0\" TileSize=\"{}\">\n  \
+             <Size Width=\"{}\" Height=\"{}\"/>\n\
+             </Image>\n",
+            self.pyramid_tile_size, self.width, self.height
+        );
+        std::fs::write(&manifest_path, manifest)?;
+        return Ok(());
+    }
+
+    /// Writes a IIIF Image API 3.0 level 0 static tile directory with `info.json`. JPEG tiles are stored
+    /// under `{x},{y},{w},{h}/{w},{h}/0/default.jpg`, alpha is dropped as JPEG does not support it.
+    pub fn save_iiif<P: AsRef<Path>>(&self, directory: P, id: Option<&str>) -> ImageResult<()> {
+        let directory = directory.as_ref();
+        let mut scale_factors = vec![1u64];
+        loop {
+            let (level_width, level_height) = self.level_size(*scale_factors.last().unwrap());
+            if level_width.max(level_height) <= self.pyramid_tile_size {
+                break;
+            }
+            scale_factors.push(scale_factors.last().unwrap() * 2);
+        }
+
+        let tiles: Vec<(PathBuf, PyramidTile)> = scale_factors
+            .iter()
+            .flat_map(|&scale| self.level_tiles(scale))
+            .map(|(scale, x, y, width, height)| {
+                let region_x = x as u64 * scale;
+                let region_y = y as u64 * scale;
+                let region_width = (width as u64 * scale).min(self.width as u64 - region_x);
+                let region_height = (height as u64 * scale).min(self.height as u64 - region_y);
+                let tile_path = directory
+                    .join(format!(
+                        "{},{},{},{}",
+                        region_x, region_y, region_width, region_height
+                    ))
+                    .join(format!("{},{}", width, height))
+                    .join("0")
+                    .join("default.jpg");
+                return (tile_path, (scale, x, y, width, height));
+            })
+            .collect();
+        // Sizes small enough to fit a single tile are also published as full region images
+        let sizes: Vec<(u64, u32, u32)> = scale_factors
+            .iter()
+            .map(|&scale| {
+                let (width, height) = self.level_size(scale);
+                return (scale, width, height);
+            })
+            .filter(|&(_, width, height)| width.max(height) <= self.pyramid_tile_size)
+            .collect();
+        let full_images = sizes.iter().map(|&(scale, width, height)| {
+            let tile_path = directory
+                .join("full")
+                .join(format!("{},{}", width, height))
+                .join("0")
+                .join("default.jpg");
+            return (tile_path, (scale, 0, 0, width, height));
+        });
+        let tiles: Vec<(PathBuf, PyramidTile)> = tiles.into_iter().chain(full_images).collect();
+
+        tiles
+            .par_iter()
+            .try_for_each(|(tile_path, (scale, x, y, width, height))| {
+                let region = self.render_region(*scale, *x, *y, *width, *height);
+                let (region, color) = self.without_alpha(region);
+                return image_io::save_buffer(
+                    tile_path,
+                    &region,
+                    *width,
+                    *height,
+                    color,
+                    &self.output_options,
+                );
+            })?;
+
+        let id = match id {
+            Some(id) => id.to_string(),
+            None => directory
+                .file_name()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .into_owned(),
+        };
+        let info = serde_json::json!({
+            "@context": "http://iiif.io/api/image/3/context.json",
+            "id": id,
+            "type": "ImageService3",
+            "protocol": "http://iiif.io/api/image",
+            "profile": "level0",
+            "width": self.width,
+            "height": self.height,
+            "sizes": sizes
+                .iter()
+                .map(|(_, width, height)| serde_json::json!({ "width": width, "height": height }))
+                .collect::<Vec<_>>(),
+            "tiles": [{
+                "width": self.pyramid_tile_size,
+                "height": self.pyramid_tile_size,
+                "scaleFactors": scale_factors,
+            }],
+        });
+        std::fs::create_dir_all(directory)?;
+        std::fs::write(
+            directory.join("info.json"),
+            serde_json::to_string_pretty(&info).unwrap() + "\n",
+        )?;
+        return Ok(());
+    }
+
+    fn without_alpha(&self, region: Vec<u8>) -> (Vec<u8>, ColorType) {
+        return match self.color {
+            ColorType::La8 => (region.into_iter().step_by(2).collect(), ColorType::L8),
+            ColorType::Rgba8 => (
+                region
+                    .chunks_exact(4)
+                    .flat_map(|pixel| [pixel[0], pixel[1], pixel[2]])
+                    .collect(),
+                ColorType::Rgb8,
+            ),
+            color => (region, color),
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::synthetic::{ChannelLayout, Pattern, SyntheticImage};
+    use crate::temp_dir::TempDir;
+    use crate::{ImageData, SerialMosaic};
+
+    fn mosaic_factory() -> MosaicFactory {
+        let img = SyntheticImage::new(Pattern::Noise, 70, 45, ChannelLayout::Rgb)
+            .with_seed(3)
+            .generate();
+        return MosaicFactory::from_image_data(ImageData::new(&img, 7), Box::new(SerialMosaic), 7);
+    }
+
+    /// Box filter downscale of the full mosaic raster used as reference.
+    fn downscale(mosaic: &[u8], width: u32, height: u32, scale: u32) -> Vec<u8> {
+        let mut downscaled = Vec::new();
+        for y in (0..height).step_by(scale as usize) {
+            for x in (0..width).step_by(scale as usize) {
+                let mut sum = [0u32; 3];
+                let mut area = 0;
+                for pixel_y in y..(y + scale).min(height) {
+                    for pixel_x in x..(x + scale).min(width) {
+                        let index = ((pixel_y * width + pixel_x) * 3) as usize;
+                        (0..3).for_each(|channel| sum[channel] += mosaic[index + channel] as u32);
+                        area += 1;
+                    }
+                }
+                downscaled.extend(sum.map(|channel_sum| ((channel_sum + area / 2) / area) as u8));
+            }
+        }
+        return downscaled;
+    }
+
+    #[test]
+    fn levels_match_downscaled_mosaic() {
+        let mosaic_factory = mosaic_factory();
+        let mosaic = mosaic_factory.generate_mosaic();
+        let tile_pyramid = TilePyramid::new(&mosaic_factory, 16);
+        let (width, height) = (tile_pyramid.width, tile_pyramid.height);
+        for scale in [1, 2, 4, 8, 16, 64] {
+            let (level_width, level_height) = tile_pyramid.level_size(scale);
+            assert_eq!(
+                tile_pyramid.render_region(scale, 0, 0, level_width, level_height),
+                downscale(&mosaic, width, height, scale as u32),
+                "scale {}",
+                scale
+            );
+        }
+    }
+
+    #[test]
+    fn regions_match_whole_level() {
+        let tile_pyramid = TilePyramid::new(&mosaic_factory(), 16);
+        let (level_width, level_height) = tile_pyramid.level_size(2);
+        let level = tile_pyramid.render_region(2, 0, 0, level_width, level_height);
+        let region = tile_pyramid.render_region(2, 16, 8, 10, 5);
+        for row in 0..5 {
+            let start = (((8 + row) * level_width + 16) * 3) as usize;
+            assert_eq!(
+                &region[row as usize * 30..(row as usize + 1) * 30],
+                &level[start..start + 30]
+            );
+        }
+    }
+
+    #[test]
+    fn tiles_use_output_options() {
+        let directory = TempDir::new("pyramid");
+        let palette_options = OutputOptions {
+            png_palette: true,
+            ..OutputOptions::default()
+        };
+        TilePyramid::new(&mosaic_factory().with_output_options(palette_options), 16)
+            .save_dzi(directory.join("mosaic.dzi"))
+            .unwrap();
+        let tile = std::fs::read(directory.join("mosaic_files/7/0_0.png")).unwrap();
+        let reader = png::Decoder::new(tile.as_slice()).read_info().unwrap();
+        assert_eq!(reader.info().color_type, png::ColorType::Indexed);
+
+        let iiif_size = |jpeg_quality| {
+            let output_options = OutputOptions {
+                jpeg_quality,
+                ..OutputOptions::default()
+            };
+            let iiif_directory = directory.join(format!("iiif_{}", jpeg_quality));
+            TilePyramid::new(&mosaic_factory().with_output_options(output_options), 16)
+                .save_iiif(&iiif_directory, None)
+                .unwrap();
+            let tile_path = iiif_directory.join("0,0,16,16/16,16/0/default.jpg");
+            return std::fs::metadata(tile_path).unwrap().len();
+        };
+        assert!(iiif_size(10) < iiif_size(100));
+    }
+}
@@ -0,0 +1,169 @@
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+/// Randomisation used by the hardened redaction mode.
+///
+/// Plain pixelation publishes exact tile averages on a grid anchored at a known position, so an attacker
+/// holding a set of candidate contents (names, digits, faces) can pixelate each candidate the same way
+/// and pick the one matching the redacted output. Hardened mode shifts the grid origin by a random offset
+/// and optionally jitters row and column sizes, which defeats attacks assuming the default grid, and adds
+/// uniform noise to every tile average, which limits attacks that recover the grid from visible tile edges.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RedactionOptions {
+    /// Maximum absolute noise added to each colour channel of a tile average
+    pub noise: u8,
+    /// Maximum number of pixels randomly added to the size of each tile row and column
+    pub tile_jitter: u32,
+    /// Seed for reproducible output, random output is derived from system entropy when empty
+    pub seed: Option<u64>,
+}
+
+impl RedactionOptions {
+    pub fn rng(&self) -> StdRng {
+        return match self.seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_entropy(),
+        };
+    }
+}
+
+/// Splits `min..max` into tiles as `(start, length)` clipped to `0..limit`. With redaction enabled
+/// the first tile starts at a random offset before `min` and tile lengths are jittered.
+pub fn grid_lines(
+    min: u32,
+    max: u32,
+    limit: u32,
+    tile_side_length: u32,
+    redaction: Option<(&RedactionOptions, &mut StdRng)>,
+) -> Vec<(u32, u32)> {
+    let mut lines = Vec::new();
+    let mut start = min as i64;
+    let mut rng = None;
+    let mut tile_jitter = 0;
+    if let Some((options, redaction_rng)) = redaction {
+        start -= redaction_rng.gen_range(0..tile_side_length) as i64;
+        tile_jitter = options.tile_jitter;
+        rng = Some(redaction_rng);
+    }
+
+    while start < max as i64 {
+        let jitter = match rng.as_mut() {
+            Some(rng) if tile_jitter > 0 => rng.gen_range(0..=tile_jitter),
+            _ => 0,
+        };
+        let end = start + (tile_side_length + jitter) as i64;
+        let clipped_start = start.max(0) as u32;
+        let clipped_end = end.min(limit as i64) as u32;
+        if clipped_start < clipped_end {
+            lines.push((clipped_start, clipped_end - clipped_start));
+        }
+        start = end;
+    }
+    return lines;
+}
+
+/// Adds uniform noise to colour channels of a tile average, alpha is left unchanged.
+pub fn add_noise(average: &mut [u8], has_alpha: bool, noise: u8, rng: &mut StdRng) {
+    if noise == 0 {
+        return;
+    }
+    let colour_channels = average.len() - has_alpha as usize;
+    for value in &mut average[..colour_channels] {
+        let offset = rng.gen_range(-(noise as i32)..=noise as i32);
+        *value = (*value as i32 + offset).clamp(0, 255) as u8;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::region_mosaic::{Region, RegionMosaic};
+    use crate::ImageData;
+
+    const SIDE: u32 = 32;
+    const TILE: u32 = 8;
+    const CANDIDATES: u64 = 16;
+
+    /// Secret content the attacker knows to be one of a small set of candidates.
+    fn candidate(seed: u64) -> ImageData {
+        let mut rng = StdRng::seed_from_u64(seed);
+        return ImageData {
+            width: SIDE,
+            height: SIDE,
+            channels: 1,
+            data: (0..SIDE * SIDE).map(|_| rng.gen()).collect(),
+            color: image::ColorType::L8,
+        };
+    }
+
+    fn pixelate(image_data: ImageData, redaction: Option<RedactionOptions>) -> Vec<u8> {
+        let whole_image = Region::Rectangle {
+            x: 0,
+            y: 0,
+            width: SIDE,
+            height: SIDE,
+        };
+        let mut region_mosaic = RegionMosaic::from_image_data(image_data, TILE, vec![whole_image]);
+        region_mosaic.redaction = redaction;
+        return region_mosaic.generate_mosaic();
+    }
+
+    /// Brute-force attack: pixelate every candidate with plain settings and pick the closest output.
+    fn reconstruct(redacted: &[u8]) -> u64 {
+        let distance = |seed: &u64| -> u64 {
+            let rendered = pixelate(candidate(*seed), None);
+            return rendered
+                .iter()
+                .zip(redacted)
+                .map(|(&a, &b)| (a as i64 - b as i64).pow(2) as u64)
+                .sum();
+        };
+        return (0..CANDIDATES).min_by_key(distance).unwrap();
+    }
+
+    fn attack_success_rate(redaction: Option<RedactionOptions>) -> f64 {
+        let successes = (0..CANDIDATES)
+            .filter(|&secret| {
+                let redaction = redaction.map(|options| RedactionOptions {
+                    seed: Some(1000 + secret),
+                    ..options
+                });
+                return reconstruct(&pixelate(candidate(secret), redaction)) == secret;
+            })
+            .count();
+        return successes as f64 / CANDIDATES as f64;
+    }
+
+    #[test]
+    fn brute_force_attack_recovers_plain_pixelation() {
+        assert_eq!(attack_success_rate(None), 1.0);
+    }
+
+    #[test]
+    fn brute_force_attack_fails_against_hardened_redaction() {
+        let options = RedactionOptions {
+            noise: 64,
+            tile_jitter: TILE / 2,
+            seed: None,
+        };
+        assert!(attack_success_rate(Some(options)) <= 0.25);
+    }
+
+    #[test]
+    fn grid_lines_cover_range() {
+        let mut rng = StdRng::seed_from_u64(7);
+        let options = RedactionOptions {
+            noise: 0,
+            tile_jitter: 5,
+            seed: None,
+        };
+        let lines = grid_lines(10, 90, 100, 16, Some((&options, &mut rng)));
+        assert!(lines[0].0 <= 10);
+        assert!(lines
+            .windows(2)
+            .all(|pair| pair[0].0 + pair[0].1 == pair[1].0));
+        let last = lines.last().unwrap();
+        assert!(last.0 + last.1 >= 90);
+        assert!(lines.iter().all(|&(_, length)| length <= 21));
+    }
+}
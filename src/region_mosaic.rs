@@ -0,0 +1,466 @@
+use std::path::Path;
+
+use image::{GenericImageView, ImageResult};
+use rand::rngs::StdRng;
+use rayon::prelude::*;
+use serde::Deserialize;
+
+use crate::alpha::{self, AlphaOptions};
+use crate::image_io::{self, OutputOptions};
+use crate::log;
+use crate::redaction::{self, RedactionOptions};
+use crate::ImageData;
+
+/// Area of the image that gets pixelated, everything outside of regions is left untouched
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum Region {
+    Rectangle {
+        x: u32,
+        y: u32,
+        width: u32,
+        height: u32,
+    },
+    Polygon {
+        points: Vec<(f64, f64)>,
+    },
+    #[serde(skip)]
+    Mask {
+        width: u32,
+        height: u32,
+        data: Vec<bool>,
+    },
+}
+
+impl Region {
+    /// Parses rectangle in `x,y,width,height` format.
+    pub fn parse_rectangle(value: &str) -> Result<Region, String> {
+        let values = parse_numbers::<u32>(value)?;
+        if values.len() != 4 {
+            return Err(format!(
+                "expected rectangle in x,y,width,height format, got: {}",
+                value
+            ));
+        }
+        return Ok(Region::Rectangle {
+            x: values[0],
+            y: values[1],
+            width: values[2],
+            height: values[3],
+        });
+    }
+
+    /// Parses polygon in `x1,y1,x2,y2,x3,y3,...` format.
+    pub fn parse_polygon(value: &str) -> Result<Region, String> {
+        let values = parse_numbers::<f64>(value)?;
+        if values.len() < 6 || values.len() % 2 != 0 {
+            return Err(format!(
+                "expected at least three x,y points in polygon, got: {}",
+                value
+            ));
+        }
+        let points = values.chunks_exact(2).map(|point| (point[0], point[1]));
+        return Ok(Region::Polygon {
+            points: points.collect(),
+        });
+    }
+
+    /// Loads a list of rectangles and polygons from a JSON file.
+    pub fn from_json_file<P: AsRef<Path>>(path: P) -> std::io::Result<Vec<Region>> {
+        let file = std::fs::File::open(path)?;
+        return Ok(serde_json::from_reader(std::io::BufReader::new(file))?);
+    }
+
+    /// Loads a mask image, pixels brighter than mid grey belong to the region.
+    pub fn from_mask_image<P: AsRef<Path>>(path: P) -> ImageResult<Region> {
+        let mask = ImageData::load_image(path.as_ref())?.into_luma8();
+        let (width, height) = mask.dimensions();
+        return Ok(Region::Mask {
+            width,
+            height,
+            data: mask
+                .into_raw()
+                .into_iter()
+                .map(|luma| luma >= 128)
+                .collect(),
+        });
+    }
+
+    /// Returns the region bounding box as `(min_x, min_y, max_x, max_y)` clamped to the image.
+    fn bounding_box(&self, width: u32, height: u32) -> Option<(u32, u32, u32, u32)> {
+        let (min_x, min_y, max_x, max_y) = match self {
+            Region::Rectangle {
+                x,
+                y,
+                width: region_width,
+                height: region_height,
+            } => (
+                *x as f64,
+                *y as f64,
+                x.saturating_add(*region_width) as f64,
+                y.saturating_add(*region_height) as f64,
+            ),
+            Region::Polygon { points } => points.iter().fold(
+                (f64::MAX, f64::MAX, f64::MIN, f64::MIN),
+                |(min_x, min_y, max_x, max_y), &(x, y)| {
+                    (min_x.min(x), min_y.min(y), max_x.max(x), max_y.max(y))
+                },
+            ),
+            Region::Mask {
+                width: mask_width,
+                data,
+                ..
+            } => data
+                .iter()
+                .enumerate()
+                .filter(|(_, &inside)| inside)
+                .map(|(index, _)| {
+                    let x = (index as u32 % mask_width) as f64;
+                    let y = (index as u32 / mask_width) as f64;
+                    return (x, y, x + 1.0, y + 1.0);
+                })
+                .fold((f64::MAX, f64::MAX, f64::MIN, f64::MIN), |acc, pixel| {
+                    (
+                        acc.0.min(pixel.0),
+                        acc.1.min(pixel.1),
+                        acc.2.max(pixel.2),
+                        acc.3.max(pixel.3),
+                    )
+                }),
+        };
+
+        let min_x = min_x.floor().max(0.0) as u32;
+        let min_y = min_y.floor().max(0.0) as u32;
+        let max_x = (max_x.ceil().max(0.0) as u32).min(width);
+        let max_y = (max_y.ceil().max(0.0) as u32).min(height);
+        if min_x >= max_x || min_y >= max_y {
+            return None;
+        }
+        return Some((min_x, min_y, max_x, max_y));
+    }
+
+    /// Checks whether the centre of a pixel lies inside the region.
+    fn contains(&self, x: u32, y: u32) -> bool {
+        match self {
+            Region::Rectangle {
+                x: region_x,
+                y: region_y,
+                width,
+                height,
+            } => {
+                return x >= *region_x
+                    && y >= *region_y
+                    && x - region_x < *width
+                    && y - region_y < *height;
+            }
+            Region::Polygon { points } => {
+                let (px, py) = (x as f64 + 0.5, y as f64 + 0.5);
+                let mut inside = false;
+                let mut previous = points[points.len() - 1];
+                for &current in points {
+                    let crosses = (current.1 > py) != (previous.1 > py);
+                    if crosses {
+                        let intersection_x = current.0
+                            + (py - current.1) * (previous.0 - current.0)
+                                / (previous.1 - current.1);
+                        if px < intersection_x {
+                            inside = !inside;
+                        }
+                    }
+                    previous = current;
+                }
+                return inside;
+            }
+            Region::Mask {
+                width,
+                height,
+                data,
+            } => {
+                return x < *width && y < *height && data[(y * width + x) as usize];
+            }
+        }
+    }
+}
+
+fn parse_numbers<T: std::str::FromStr>(value: &str) -> Result<Vec<T>, String> {
+    return value
+        .split(',')
+        .map(|number| {
+            number
+                .trim()
+                .parse::<T>()
+                .map_err(|_| format!("invalid number '{}' in: {}", number, value))
+        })
+        .collect();
+}
+
+/// Tile of a region grid as `(x, y, width, height)`
+type RegionTile = (u32, u32, u32, u32);
+
+/// Pixelates only the tiles covering given regions, with the tile grid anchored to each region
+pub struct RegionMosaic {
+    pub tile_side_length: u32,
+    pub image_data: ImageData,
+    pub regions: Vec<Region>,
+    pub redaction: Option<RedactionOptions>,
+    pub alpha_options: AlphaOptions,
+    pub output_options: OutputOptions,
+}
+
+impl RegionMosaic {
+    pub fn new<P: AsRef<Path>>(
+        input_image_path: P,
+        tile_side_length: u32,
+        regions: Vec<Region>,
+    ) -> ImageResult<RegionMosaic> {
+        let img = ImageData::load_image(input_image_path.as_ref())?;
+        let image_data = ImageData::new(&img, 1);
+        for region in &regions {
+            if let Region::Mask { width, height, .. } = region {
+                if (*width, *height) != img.dimensions() {
+                    return Err(image_io::parameter_error(format!(
+                        "region mask size {}x{} does not match image size {}x{}",
+                        width,
+                        height,
+                        img.width(),
+                        img.height()
+                    )));
+                }
+            }
+        }
+        return Ok(RegionMosaic::from_image_data(
+            image_data,
+            tile_side_length,
+            regions,
+        ));
+    }
+
+    pub fn from_image_data(
+        image_data: ImageData,
+        tile_side_length: u32,
+        regions: Vec<Region>,
+    ) -> RegionMosaic {
+        return RegionMosaic {
+            tile_side_length,
+            image_data,
+            regions,
+            redaction: None,
+            alpha_options: AlphaOptions::default(),
+            output_options: OutputOptions::default(),
+        };
+    }
+
+    pub fn with_redaction(mut self, redaction: Option<RedactionOptions>) -> RegionMosaic {
+        self.redaction = redaction;
+        return self;
+    }
+
+    pub fn with_alpha_options(mut self, alpha_options: AlphaOptions) -> RegionMosaic {
+        self.alpha_options = alpha_options;
+        return self;
+    }
+
+    pub fn with_output_options(mut self, output_options: OutputOptions) -> RegionMosaic {
+        self.output_options = output_options;
+        return self;
+    }
+
+    /// Lists tiles of a region grid intersecting the region, tiles are clipped to the image.
+    fn region_tiles(&self, region: &Region, rng: Option<&mut StdRng>) -> Vec<RegionTile> {
+        let (width, height) = (self.image_data.width, self.image_data.height);
+        let Some((min_x, min_y, max_x, max_y)) = region.bounding_box(width, height) else {
+            return Vec::new();
+        };
+
+        let mut redaction = self.redaction.as_ref().zip(rng);
+        let mut grid_lines = |min: u32, max: u32, limit: u32| {
+            let redaction = redaction
+                .as_mut()
+                .map(|(options, rng)| (*options, &mut **rng));
+            return redaction::grid_lines(min, max, limit, self.tile_side_length, redaction);
+        };
+        let columns = grid_lines(min_x, max_x, width);
+        let rows = grid_lines(min_y, max_y, height);
+
+        let mut tiles = Vec::new();
+        for &(tile_y, tile_height) in &rows {
+            for &(tile_x, tile_width) in &columns {
+                let intersects = (tile_y..tile_y + tile_height)
+                    .any(|y| (tile_x..tile_x + tile_width).any(|x| region.contains(x, y)));
+                if intersects {
+                    tiles.push((tile_x, tile_y, tile_width, tile_height));
+                }
+            }
+        }
+        return tiles;
+    }
+
+    pub fn generate_mosaic(&self) -> Vec<u8> {
+        let mut mosaic = self.image_data.data.clone();
+        let has_alpha = alpha::has_alpha(&self.image_data);
+        let mut rng = self.redaction.map(|options| options.rng());
+
+        for region in &self.regions {
+            let mut tile_averages: Vec<(RegionTile, Vec<u8>)> = self
+                .region_tiles(region, rng.as_mut())
+                .into_par_iter()
+                .map(|(x, y, width, height)| {
+                    let mut average = self.image_data.area_average(x, y, width, height);
+                    if has_alpha {
+                        alpha::resolve_alpha(
+                            &mut average,
+                            self.image_data.channels,
+                            &self.alpha_options,
+                        );
+                    }
+                    return ((x, y, width, height), average);
+                })
+                .collect();
+            if let (Some(options), Some(rng)) = (self.redaction, rng.as_mut()) {
+                for (_, average) in &mut tile_averages {
+                    redaction::add_noise(average, has_alpha, options.noise, rng);
+                }
+            }
+            log::verbose!("Region tiles pixelated: {}", tile_averages.len());
+
+            for ((x, y, width, height), average) in tile_averages {
+                self.image_data
+                    .fill_area(&mut mosaic, x, y, width, height, &average);
+            }
+        }
+
+        return mosaic;
+    }
+
+    pub fn save_mosaic<P: AsRef<Path>>(&self, output_img_path: &P, img: &[u8]) -> ImageResult<()> {
+        return image_io::save_buffer(
+            output_img_path,
+            img,
+            self.image_data.width,
+            self.image_data.height,
+            self.image_data.color,
+            &self.output_options,
+        );
+    }
+
+    pub fn generate_and_save_mosaic<P: AsRef<Path>>(&self, output_img_path: &P) -> ImageResult<()> {
+        let img = self.generate_mosaic();
+        return self.save_mosaic(output_img_path, &img);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::synthetic::{ChannelLayout, Pattern, SyntheticImage};
+
+    fn noise_region_mosaic(tile_side_length: u32, regions: Vec<Region>) -> RegionMosaic {
+        let img = SyntheticImage::new(Pattern::Noise, 16, 16, ChannelLayout::Rgb)
+            .with_seed(3)
+            .generate();
+        return RegionMosaic::from_image_data(ImageData::new(&img, 1), tile_side_length, regions);
+    }
+
+    fn rectangle(x: u32, y: u32, width: u32, height: u32) -> Region {
+        return Region::Rectangle {
+            x,
+            y,
+            width,
+            height,
+        };
+    }
+
+    /// Pixel of a buffer laid out like the 16x16 RGB test image.
+    fn pixel(img: &[u8], x: u32, y: u32) -> &[u8] {
+        let index = ((y * 16 + x) * 3) as usize;
+        return &img[index..index + 3];
+    }
+
+    /// Checks that every pixel of a tile holds the tile average of the original image.
+    fn assert_tile_filled(region_mosaic: &RegionMosaic, mosaic: &[u8], tile: RegionTile) {
+        let (x, y, width, height) = tile;
+        let average = region_mosaic.image_data.area_average(x, y, width, height);
+        for pixel_y in y..y + height {
+            for pixel_x in x..x + width {
+                assert_eq!(
+                    pixel(mosaic, pixel_x, pixel_y),
+                    average,
+                    "pixel ({}, {})",
+                    pixel_x,
+                    pixel_y
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn regions_are_clipped_to_image() {
+        let region_mosaic = noise_region_mosaic(4, vec![rectangle(12, 10, 20, 20)]);
+        assert_eq!(
+            region_mosaic.region_tiles(&region_mosaic.regions[0], None),
+            vec![(12, 10, 4, 4), (12, 14, 4, 2)]
+        );
+
+        let mosaic = region_mosaic.generate_mosaic();
+        assert_tile_filled(&region_mosaic, &mosaic, (12, 10, 4, 4));
+        assert_tile_filled(&region_mosaic, &mosaic, (12, 14, 4, 2));
+        let original = &region_mosaic.image_data.data;
+        for (x, y) in [(11, 10), (12, 9), (0, 0), (15, 9)] {
+            assert_eq!(pixel(&mosaic, x, y), pixel(original, x, y));
+        }
+
+        let polygon = Region::Polygon {
+            points: vec![(-8.0, -8.0), (3.0, -8.0), (3.0, 3.0), (-8.0, 3.0)],
+        };
+        let region_mosaic = noise_region_mosaic(4, vec![polygon]);
+        // the grid starts at the clipped region, tiles extend past the region but not past the image
+        assert_eq!(
+            region_mosaic.region_tiles(&region_mosaic.regions[0], None),
+            vec![(0, 0, 4, 4)]
+        );
+
+        let outside = noise_region_mosaic(4, vec![rectangle(16, 0, 4, 4)]);
+        assert_eq!(outside.generate_mosaic(), outside.image_data.data);
+    }
+
+    #[test]
+    fn overlapping_regions_are_pixelated_in_order() {
+        let region_mosaic =
+            noise_region_mosaic(4, vec![rectangle(0, 0, 8, 8), rectangle(6, 6, 8, 8)]);
+        let mosaic = region_mosaic.generate_mosaic();
+
+        // the later region is averaged from the input image and overwrites the overlap
+        assert_tile_filled(&region_mosaic, &mosaic, (6, 6, 4, 4));
+        assert_tile_filled(&region_mosaic, &mosaic, (10, 10, 4, 4));
+        assert_tile_filled(&region_mosaic, &mosaic, (0, 0, 4, 4));
+        for (x, y) in [(4, 4), (5, 7), (7, 5)] {
+            let average = region_mosaic.image_data.area_average(4, 4, 4, 4);
+            assert_eq!(pixel(&mosaic, x, y), average, "pixel ({}, {})", x, y);
+        }
+        assert_eq!(
+            pixel(&mosaic, 15, 15),
+            pixel(&region_mosaic.image_data.data, 15, 15)
+        );
+    }
+
+    #[test]
+    fn regions_smaller_than_a_tile_get_one_tile() {
+        let region_mosaic = noise_region_mosaic(8, vec![rectangle(5, 5, 2, 2)]);
+        assert_eq!(
+            region_mosaic.region_tiles(&region_mosaic.regions[0], None),
+            vec![(5, 5, 8, 8)]
+        );
+        let mosaic = region_mosaic.generate_mosaic();
+        assert_tile_filled(&region_mosaic, &mosaic, (5, 5, 8, 8));
+        assert_eq!(
+            pixel(&mosaic, 4, 4),
+            pixel(&region_mosaic.image_data.data, 4, 4)
+        );
+
+        let corner = noise_region_mosaic(8, vec![rectangle(14, 14, 1, 1)]);
+        assert_eq!(
+            corner.region_tiles(&corner.regions[0], None),
+            vec![(14, 14, 2, 2)]
+        );
+    }
+}
@@ -0,0 +1,581 @@
+use std::fmt::Write as _;
+use std::path::Path;
+
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+
+use crate::args::ReportFormat;
+use crate::benchmark::{BenchmarkResults, StageStatistics};
+use crate::correctness::CorrectnessDiagnostics;
+use crate::MosaicFactory;
+
+/// Machine-readable summary of a benchmark invocation
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Report {
+    pub image: ImageReport,
+    pub tile_side_length: u32,
+    pub threads: usize,
+    pub host: HostReport,
+    pub builders: Vec<BuilderReport>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ImageReport {
+    pub path: String,
+    pub width: u32,
+    pub height: u32,
+    pub channels: u8,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct HostReport {
+    pub os: String,
+    pub arch: String,
+    pub cpus: usize,
+}
+
+impl HostReport {
+    pub fn current() -> HostReport {
+        return HostReport {
+            os: std::env::consts::OS.to_string(),
+            arch: std::env::consts::ARCH.to_string(),
+            cpus: std::thread::available_parallelism().map_or(1, |cpus| cpus.get()),
+        };
+    }
+}
+
+/// Number of values differing from the serial implementation in each stage
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CorrectnessReport {
+    #[serde(default)]
+    pub mode: String,
+    pub stage_1: usize,
+    pub stage_2: usize,
+    pub stage_3: usize,
+    pub global_average: usize,
+    #[serde(default)]
+    pub max_absolute_error: u64,
+}
+
+impl From<&CorrectnessDiagnostics> for CorrectnessReport {
+    fn from(correctness_diagnostics: &CorrectnessDiagnostics) -> CorrectnessReport {
+        return CorrectnessReport {
+            mode: correctness_diagnostics
+                .mode
+                .to_possible_value()
+                .unwrap()
+                .get_name()
+                .to_string(),
+            stage_1: correctness_diagnostics.sum_tile_channels.mismatches,
+            stage_2: correctness_diagnostics.calc_tile_average.mismatches,
+            stage_3: correctness_diagnostics.create_mosaic.mismatches,
+            global_average: correctness_diagnostics.global_average.mismatches,
+            max_absolute_error: correctness_diagnostics.max_absolute_error(),
+        };
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct BuilderReport {
+    pub builder: String,
+    pub warmup_runs: u32,
+    pub runs: usize,
+    pub megapixels_per_second: f64,
+    pub correctness: CorrectnessReport,
+    pub stages: Vec<StageReport>,
+}
+
+/// Stage statistics in nanoseconds
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct StageReport {
+    pub stage: String,
+    pub mean_ns: u128,
+    pub median_ns: u128,
+    pub p95_ns: u128,
+    pub min_ns: u128,
+    pub max_ns: u128,
+    pub std_dev_ns: u128,
+    pub outliers: usize,
+}
+
+impl StageReport {
+    fn new(stage: &str, statistics: &StageStatistics) -> StageReport {
+        return StageReport {
+            stage: stage.to_string(),
+            mean_ns: statistics.mean.as_nanos(),
+            median_ns: statistics.median.as_nanos(),
+            p95_ns: statistics.p95.as_nanos(),
+            min_ns: statistics.min.as_nanos(),
+            max_ns: statistics.max.as_nanos(),
+            std_dev_ns: statistics.std_dev.as_nanos(),
+            outliers: statistics.outliers,
+        };
+    }
+}
+
+impl Report {
+    pub fn new(
+        input_image_path: &str,
+        mosaic_factory: &MosaicFactory,
+        results: &[(CorrectnessReport, BenchmarkResults)],
+    ) -> Report {
+        let builders = results
+            .iter()
+            .map(|(correctness, benchmark_results)| BuilderReport {
+                builder: benchmark_results.builder_name.clone(),
+                warmup_runs: benchmark_results.warmup_runs,
+                runs: benchmark_results.total.samples.len(),
+                megapixels_per_second: benchmark_results.throughput(),
+                correctness: correctness.clone(),
+                stages: benchmark_results
+                    .stages()
+                    .iter()
+                    .map(|(stage, statistics)| StageReport::new(stage, statistics))
+                    .collect(),
+            })
+            .collect();
+
+        return Report {
+            image: ImageReport {
+                path: input_image_path.to_string(),
+                width: mosaic_factory.image_data.width,
+                height: mosaic_factory.image_data.height,
+                channels: mosaic_factory.image_data.channels,
+            },
+            tile_side_length: mosaic_factory.tile_side_length,
+            threads: rayon::current_num_threads(),
+            host: HostReport::current(),
+            builders,
+        };
+    }
+
+    pub fn from_json_file<P: AsRef<Path>>(path: P) -> std::io::Result<Report> {
+        let file = std::fs::File::open(path)?;
+        return Ok(serde_json::from_reader(std::io::BufReader::new(file))?);
+    }
+
+    pub fn render(&self, format: ReportFormat) -> String {
+        return match format {
+            ReportFormat::Json => serde_json::to_string_pretty(self).unwrap() + "\n",
+            ReportFormat::Csv => self.to_csv(),
+            ReportFormat::Markdown => self.to_markdown(),
+        };
+    }
+
+    /// One row per builder and stage, run metadata is repeated on every row.
+    fn to_csv(&self) -> String {
+        let mut csv = String::from(
+            "builder,stage,mean_ns,median_ns,p95_ns,min_ns,max_ns,std_dev_ns,outliers,runs,warmup_runs,\
+             megapixels_per_second,incorrect_stage_1,incorrect_stage_2,incorrect_stage_3,\
+             incorrect_global_average,max_absolute_error,image_width,image_height,channels,tile_side_length,threads,os,arch,cpus\n",
+        );
+        for builder in &self.builders {
+            for stage in &builder.stages {
+                writeln!(
+                    csv,
+                    "{},{},{},{},{},{},{},{},{},{},{},{:.4},{},{},{},{},{},{},{},{},{},{},{},{},{}",
+                    builder.builder,
+                    stage.stage,
+                    stage.mean_ns,
+                    stage.median_ns,
+                    stage.p95_ns,
+                    stage.min_ns,
+                    stage.max_ns,
+                    stage.std_dev_ns,
+                    stage.outliers,
+                    builder.runs,
+                    builder.warmup_runs,
+                    builder.megapixels_per_second,
+                    builder.correctness.stage_1,
+                    builder.correctness.stage_2,
+                    builder.correctness.stage_3,
+                    builder.correctness.global_average,
+                    builder.correctness.max_absolute_error,
+                    self.image.width,
+                    self.image.height,
+                    self.image.channels,
+                    self.tile_side_length,
+                    self.threads,
+                    self.host.os,
+                    self.host.arch,
+                    self.host.cpus
+                )
+                .unwrap();
+            }
+        }
+        return csv;
+    }
+
+    fn to_markdown(&self) -> String {
+        let mut markdown = String::from("# Mosaic benchmark report\n\n");
+        writeln!(
+            markdown,
+            "- Image: `{}` ({}x{}, {} channels)\n- Tile side length: {}\n- Threads: {}\n- Host: {} {} ({} CPUs)\n",
+            self.image.path,
+            self.image.width,
+            self.image.height,
+            self.image.channels,
+            self.tile_side_length,
+            self.threads,
+            self.host.os,
+            self.host.arch,
+            self.host.cpus
+        )
+        .unwrap();
+
+        for builder in &self.builders {
+            writeln!(
+                markdown,
+                "## {}\n\n{} runs, {} warm-up, {:.2} MP/s\n\nIncorrect values ({}): stage 1: {}, stage 2: {}, stage 3: {}, global average: {}, max absolute error: {}\n",
+                builder.builder,
+                builder.runs,
+                builder.warmup_runs,
+                builder.megapixels_per_second,
+                builder.correctness.mode,
+                builder.correctness.stage_1,
+                builder.correctness.stage_2,
+                builder.correctness.stage_3,
+                builder.correctness.global_average,
+                builder.correctness.max_absolute_error
+            )
+            .unwrap();
+            markdown += "| Stage | Mean (ns) | Median (ns) | p95 (ns) | Min (ns) | Max (ns) | Std dev (ns) | Outliers |\n";
+            markdown += "|---|---:|---:|---:|---:|---:|---:|---:|\n";
+            for stage in &builder.stages {
+                writeln!(
+                    markdown,
+                    "| {} | {} | {} | {} | {} | {} | {} | {} |",
+                    stage.stage,
+                    stage.mean_ns,
+                    stage.median_ns,
+                    stage.p95_ns,
+                    stage.min_ns,
+                    stage.max_ns,
+                    stage.std_dev_ns,
+                    stage.outliers
+                )
+                .unwrap();
+            }
+            markdown += "\n";
+        }
+        return markdown;
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P, format: ReportFormat) -> std::io::Result<()> {
+        if let Some(parent) = path.as_ref().parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        return std::fs::write(path, self.render(format));
+    }
+}
+
+/// Change of the median time of a stage between two reports
+#[derive(Clone, Debug, PartialEq)]
+pub struct StageComparison {
+    pub builder: String,
+    pub stage: String,
+    pub baseline_ns: u128,
+    pub candidate_ns: u128,
+    /// Relative change in percent, positive values are slowdowns
+    pub change: f64,
+}
+
+impl StageComparison {
+    pub fn is_regression(&self, threshold: f64) -> bool {
+        return self.change > threshold;
+    }
+}
+
+/// Correctness results of a builder which got worse between two reports
+#[derive(Clone, Debug, PartialEq)]
+pub struct CorrectnessComparison {
+    pub builder: String,
+    pub baseline: CorrectnessReport,
+    pub candidate: CorrectnessReport,
+}
+
+/// Differences between a baseline and a candidate report of the same benchmark setup
+#[derive(Clone, Debug, PartialEq)]
+pub struct ReportComparison {
+    pub stages: Vec<StageComparison>,
+    /// Builders of the baseline which the candidate report does not contain
+    pub missing_builders: Vec<String>,
+    pub correctness: Vec<CorrectnessComparison>,
+}
+
+impl ReportComparison {
+    /// Whether a stage slowed down by more than `threshold` percent, a builder is missing or got less correct.
+    pub fn is_regression(&self, threshold: f64) -> bool {
+        return !self.missing_builders.is_empty()
+            || !self.correctness.is_empty()
+            || self
+                .stages
+                .iter()
+                .any(|comparison| comparison.is_regression(threshold));
+    }
+}
+
+impl CorrectnessReport {
+    /// Whether any stage has more incorrect values or the maximum error grew compared to `baseline`.
+    fn is_worse_than(&self, baseline: &CorrectnessReport) -> bool {
+        return self.stage_1 > baseline.stage_1
+            || self.stage_2 > baseline.stage_2
+            || self.stage_3 > baseline.stage_3
+            || self.global_average > baseline.global_average
+            || self.max_absolute_error > baseline.max_absolute_error;
+    }
+}
+
+/// Checks that both reports benchmarked images of the same size and channels with the same tile size and thread
+/// count, the image paths may differ between machines.
+fn check_comparable(baseline: &Report, candidate: &Report) -> Result<(), String> {
+    let (baseline_image, candidate_image) = (&baseline.image, &candidate.image);
+    if (
+        baseline_image.width,
+        baseline_image.height,
+        baseline_image.channels,
+    ) != (
+        candidate_image.width,
+        candidate_image.height,
+        candidate_image.channels,
+    ) {
+        return Err(format!(
+            "reports benchmark different images: {} ({}x{}, {} channels) and {} ({}x{}, {} channels)",
+            baseline.image.path,
+            baseline.image.width,
+            baseline.image.height,
+            baseline.image.channels,
+            candidate.image.path,
+            candidate.image.width,
+            candidate.image.height,
+            candidate.image.channels
+        ));
+    }
+    if baseline.tile_side_length != candidate.tile_side_length {
+        return Err(format!(
+            "reports use different tile side lengths: {} and {}",
+            baseline.tile_side_length, candidate.tile_side_length
+        ));
+    }
+    if baseline.threads != candidate.threads {
+        return Err(format!(
+            "reports use different thread counts: {} and {}",
+            baseline.threads, candidate.threads
+        ));
+    }
+    return Ok(());
+}
+
+/// Compares median stage times and correctness of builders present in both reports. Fails when the reports were
+/// not created with the same image size, channels, tile side length and thread count.
+pub fn compare_reports(baseline: &Report, candidate: &Report) -> Result<ReportComparison, String> {
+    check_comparable(baseline, candidate)?;
+    let mut comparison = ReportComparison {
+        stages: Vec::new(),
+        missing_builders: Vec::new(),
+        correctness: Vec::new(),
+    };
+    for baseline_builder in &baseline.builders {
+        let candidate_builder = candidate
+            .builders
+            .iter()
+            .find(|builder| builder.builder == baseline_builder.builder);
+        let Some(candidate_builder) = candidate_builder else {
+            comparison
+                .missing_builders
+                .push(baseline_builder.builder.clone());
+            continue;
+        };
+        if candidate_builder
+            .correctness
+            .is_worse_than(&baseline_builder.correctness)
+        {
+            comparison.correctness.push(CorrectnessComparison {
+                builder: candidate_builder.builder.clone(),
+                baseline: baseline_builder.correctness.clone(),
+                candidate: candidate_builder.correctness.clone(),
+            });
+        }
+        for candidate_stage in &candidate_builder.stages {
+            let baseline_stage = baseline_builder
+                .stages
+                .iter()
+                .find(|stage| stage.stage == candidate_stage.stage);
+            if let Some(baseline_stage) = baseline_stage {
+                let change = match baseline_stage.median_ns {
+                    0 => 0.0,
+                    baseline_ns => {
+                        (candidate_stage.median_ns as f64 - baseline_ns as f64) / baseline_ns as f64
+                            * 100.0
+                    }
+                };
+                comparison.stages.push(StageComparison {
+                    builder: candidate_builder.builder.clone(),
+                    stage: candidate_stage.stage.clone(),
+                    baseline_ns: baseline_stage.median_ns,
+                    candidate_ns: candidate_stage.median_ns,
+                    change,
+                });
+            }
+        }
+    }
+    return Ok(comparison);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn correctness(stage_1: usize, max_absolute_error: u64) -> CorrectnessReport {
+        return CorrectnessReport {
+            mode: "per-stage".to_string(),
+            stage_1,
+            stage_2: 0,
+            stage_3: 0,
+            global_average: 0,
+            max_absolute_error,
+        };
+    }
+
+    fn builder(name: &str, median_ns: u128, correctness: CorrectnessReport) -> BuilderReport {
+        return BuilderReport {
+            builder: name.to_string(),
+            warmup_runs: 1,
+            runs: 3,
+            megapixels_per_second: 12.5,
+            correctness,
+            stages: vec![StageReport {
+                stage: "total".to_string(),
+                mean_ns: median_ns,
+                median_ns,
+                p95_ns: median_ns,
+                min_ns: median_ns,
+                max_ns: median_ns,
+                std_dev_ns: 0,
+                outliers: 0,
+            }],
+        };
+    }
+
+    fn report(builders: Vec<BuilderReport>) -> Report {
+        return Report {
+            image: ImageReport {
+                path: "image.png".to_string(),
+                width: 64,
+                height: 48,
+                channels: 3,
+            },
+            tile_side_length: 16,
+            threads: 4,
+            host: HostReport {
+                os: "linux".to_string(),
+                arch: "x86_64".to_string(),
+                cpus: 4,
+            },
+            builders,
+        };
+    }
+
+    #[test]
+    fn stage_changes_are_compared_to_threshold() {
+        let baseline = report(vec![builder("serial", 1000, correctness(0, 0))]);
+        let candidate = report(vec![builder("serial", 1100, correctness(0, 0))]);
+        let comparison = compare_reports(&baseline, &candidate).unwrap();
+        assert_eq!(comparison.stages.len(), 1);
+        assert!((comparison.stages[0].change - 10.0).abs() < 1e-9);
+        assert!(comparison.is_regression(5.0));
+        assert!(!comparison.is_regression(10.0));
+        assert!(!comparison.is_regression(20.0));
+
+        let faster = report(vec![builder("serial", 500, correctness(0, 0))]);
+        assert!(!compare_reports(&baseline, &faster)
+            .unwrap()
+            .is_regression(0.0));
+    }
+
+    #[test]
+    fn missing_builders_are_regressions() {
+        let baseline = report(vec![
+            builder("serial", 1000, correctness(0, 0)),
+            builder("parallel", 1000, correctness(0, 0)),
+        ]);
+        let candidate = report(vec![builder("serial", 1000, correctness(0, 0))]);
+        let comparison = compare_reports(&baseline, &candidate).unwrap();
+        assert_eq!(comparison.missing_builders, vec!["parallel".to_string()]);
+        assert!(comparison.is_regression(100.0));
+
+        let comparison = compare_reports(&candidate, &baseline).unwrap();
+        assert!(comparison.missing_builders.is_empty());
+        assert!(!comparison.is_regression(100.0));
+    }
+
+    #[test]
+    fn correctness_changes_are_regressions() {
+        let baseline = report(vec![builder("parallel", 1000, correctness(2, 1))]);
+        let worse = report(vec![builder("parallel", 1000, correctness(3, 1))]);
+        let comparison = compare_reports(&baseline, &worse).unwrap();
+        assert_eq!(comparison.correctness.len(), 1);
+        assert_eq!(comparison.correctness[0].candidate.stage_1, 3);
+        assert!(comparison.is_regression(100.0));
+
+        let larger_error = report(vec![builder("parallel", 1000, correctness(2, 4))]);
+        assert!(compare_reports(&baseline, &larger_error)
+            .unwrap()
+            .is_regression(100.0));
+
+        let better = report(vec![builder("parallel", 1000, correctness(0, 0))]);
+        assert!(!compare_reports(&baseline, &better)
+            .unwrap()
+            .is_regression(100.0));
+    }
+
+    #[test]
+    fn different_setups_are_not_compared() {
+        let baseline = report(vec![builder("serial", 1000, correctness(0, 0))]);
+        let mut candidates = vec![baseline.clone(); 4];
+        candidates[0].image.channels = 4;
+        candidates[1].image.width = 32;
+        candidates[2].tile_side_length = 8;
+        candidates[3].threads = 1;
+        for (candidate, message) in candidates.iter().zip([
+            "different images",
+            "different images",
+            "different tile side lengths",
+            "different thread counts",
+        ]) {
+            let error = compare_reports(&baseline, candidate).unwrap_err();
+            assert!(error.contains(message), "{}", error);
+        }
+
+        let mut moved = baseline.clone();
+        moved.image.path = "/home/ci/images/image.png".to_string();
+        assert!(compare_reports(&baseline, &moved).is_ok());
+    }
+
+    #[test]
+    fn json_round_trips() {
+        let report = report(vec![builder("serial", 1000, correctness(1, 2))]);
+        let json = report.render(ReportFormat::Json);
+        assert!(json.ends_with("}\n"));
+        assert_eq!(serde_json::from_str::<Report>(&json).unwrap(), report);
+    }
+
+    #[test]
+    fn markdown_lists_metadata_correctness_and_stages() {
+        let markdown =
+            report(vec![builder("serial", 1000, correctness(1, 2))]).render(ReportFormat::Markdown);
+        assert!(markdown.starts_with("# Mosaic benchmark report\n\n"));
+        assert!(
+            markdown.contains(
+                "- Image: `image.png` (64x48, 3 channels)\n- Tile side length: 16\n- Threads: 4\n"
+            ),
+            "{}",
+            markdown
+        );
+        assert!(markdown.contains("## serial\n\n3 runs, 1 warm-up, 12.50 MP/s\n"));
+        assert!(markdown.contains(
+            "Incorrect values (per-stage): stage 1: 1, stage 2: 0, stage 3: 0, global average: 0, max absolute error: 2\n"
+        ));
+        assert!(markdown.contains("| total | 1000 | 1000 | 1000 | 1000 | 1000 | 0 | 0 |\n"));
+    }
+}
@@ -0,0 +1,242 @@
+use image::{Delay, Frame};
+use rayon::prelude::*;
+
+use crate::alpha::{self, AlphaOptions};
+use crate::animation::Animation;
+use crate::image_data::ImageData;
+
+/// Renders mosaics of one image at any tile size from a single summed-area table, each tile size costs one lookup
+/// per tile instead of another pass over the pixels. Tiles are centred like the crop of regular mosaics, partial
+/// tiles at the edges keep every rendering at the input size.
+pub struct RevealMosaic {
+    pub width: u32,
+    pub height: u32,
+    pub channels: u8,
+    pub color: image::ColorType,
+    /// Alpha weighted channel sums of all pixels above and to the left of each position, with a leading row and
+    /// column of zeros
+    summed_area: Vec<u64>,
+    pub alpha_options: AlphaOptions,
+}
+
+impl RevealMosaic {
+    pub fn new(image_data: ImageData) -> RevealMosaic {
+        let has_alpha = alpha::has_alpha(&image_data);
+        let channels = image_data.channels as usize;
+        let (width, height) = (image_data.width as usize, image_data.height as usize);
+        let stride = (width + 1) * channels;
+        let mut summed_area: Vec<u64> = vec![0; stride * (height + 1)];
+
+        let mut row_sum = vec![0u64; channels];
+        for y in 0..height {
+            row_sum.fill(0);
+            let (previous_rows, current_rows) = summed_area.split_at_mut((y + 1) * stride);
+            let above = &previous_rows[y * stride..];
+            let current = &mut current_rows[..stride];
+            for x in 0..width {
+                let pixel = &image_data.data[(y * width + x) * channels..][..channels];
+                for (channel, sum) in row_sum.iter_mut().enumerate() {
+                    *sum += alpha::weighted_sample(pixel, channel, has_alpha);
+                    let index = (x + 1) * channels + channel;
+                    current[index] = above[index] + *sum;
+                }
+            }
+        }
+
+        return RevealMosaic {
+            width: image_data.width,
+            height: image_data.height,
+            channels: image_data.channels,
+            color: image_data.color,
+            summed_area,
+            alpha_options: AlphaOptions::default(),
+        };
+    }
+
+    pub fn with_alpha_options(mut self, alpha_options: AlphaOptions) -> RevealMosaic {
+        self.alpha_options = alpha_options;
+        return self;
+    }
+
+    /// Tile sizes from `from` to `to` spaced geometrically so that every step changes detail by the same ratio,
+    /// repeated sizes are skipped.
+    pub fn tile_sizes(from: u32, to: u32, frames: u32) -> Vec<u32> {
+        let mut tile_sizes: Vec<u32> = (0..frames)
+            .map(|frame| {
+                let progress = frame as f64 / (frames - 1).max(1) as f64;
+                let tile_side_length = from as f64 * (to as f64 / from as f64).powf(progress);
+                return (tile_side_length.round() as u32).max(1);
+            })
+            .collect();
+        tile_sizes.dedup();
+        return tile_sizes;
+    }
+
+    /// Tile boundaries along one axis, the remainder is split between partial tiles at both ends.
+    fn tile_edges(length: u32, tile_side_length: u32) -> Vec<u32> {
+        if length <= tile_side_length {
+            return vec![0, length];
+        }
+        let margin = (length % tile_side_length) / 2;
+        let mut edges = vec![0];
+        edges.extend(
+            (margin..length)
+                .step_by(tile_side_length as usize)
+                .filter(|edge| *edge > 0),
+        );
+        edges.push(length);
+        return edges;
+    }
+
+    fn area_sum(&self, x0: u32, y0: u32, x1: u32, y1: u32, channel: usize) -> u64 {
+        let channels = self.channels as usize;
+        let stride = (self.width as usize + 1) * channels;
+        let at = |x: u32, y: u32| {
+            self.summed_area[y as usize * stride + x as usize * channels + channel]
+        };
+        return at(x1, y1) + at(x0, y0) - at(x0, y1) - at(x1, y0);
+    }
+
+    /// Renders the mosaic at a tile size, tile size 1 reproduces opaque input.
+    pub fn render(&self, tile_side_length: u32) -> Vec<u8> {
+        let channels = self.channels as usize;
+        let edges_x = RevealMosaic::tile_edges(self.width, tile_side_length);
+        let edges_y = RevealMosaic::tile_edges(self.height, tile_side_length);
+        let tiles_x = edges_x.len() - 1;
+        let has_alpha = matches!(self.color, image::ColorType::La8 | image::ColorType::Rgba8);
+
+        let mut tile_averages: Vec<u8> = edges_y
+            .par_windows(2)
+            .flat_map_iter(|rows| {
+                return edges_x.windows(2).flat_map(move |columns| {
+                    let area = ((columns[1] - columns[0]) * (rows[1] - rows[0])) as u64;
+                    let sums: Vec<u64> = (0..channels)
+                        .map(|channel| {
+                            self.area_sum(columns[0], rows[0], columns[1], rows[1], channel)
+                        })
+                        .collect();
+                    return (0..channels).map(move |channel| {
+                        return alpha::channel_average(&sums, channel, area, has_alpha);
+                    });
+                });
+            })
+            .collect();
+        if has_alpha {
+            alpha::resolve_alpha(&mut tile_averages, self.channels, &self.alpha_options);
+        }
+
+        let mut tile_columns = Vec::with_capacity(self.width as usize);
+        for (tile, columns) in edges_x.windows(2).enumerate() {
+            tile_columns.extend(std::iter::repeat_n(
+                tile,
+                (columns[1] - columns[0]) as usize,
+            ));
+        }
+        let mut img = vec![0; self.width as usize * self.height as usize * channels];
+        img.par_chunks_mut(self.width as usize * channels)
+            .enumerate()
+            .for_each(|(y, row)| {
+                let tile_y = edges_y.partition_point(|edge| *edge <= y as u32) - 1;
+                for (pixel, tile_x) in row.chunks_exact_mut(channels).zip(&tile_columns) {
+                    let tile = (tile_y * tiles_x + tile_x) * channels;
+                    pixel.copy_from_slice(&tile_averages[tile..tile + channels]);
+                }
+            });
+        return img;
+    }
+
+    /// Animation with a frame per tile size, the last frame is shown for `hold_ms`.
+    pub fn generate_animation(
+        &self,
+        tile_sizes: &[u32],
+        frame_delay_ms: u32,
+        hold_ms: u32,
+    ) -> Animation {
+        let frames = tile_sizes
+            .par_iter()
+            .enumerate()
+            .map(|(index, tile_side_length)| {
+                let img = ImageData::image_from_buffer(
+                    self.render(*tile_side_length),
+                    self.width,
+                    self.height,
+                    self.color,
+                );
+                let delay_ms = match index + 1 == tile_sizes.len() {
+                    true => hold_ms,
+                    false => frame_delay_ms,
+                };
+                return Frame::from_parts(
+                    img.to_rgba8(),
+                    0,
+                    0,
+                    Delay::from_numer_denom_ms(delay_ms, 1),
+                );
+            })
+            .collect();
+        return Animation {
+            frames,
+            plays: None,
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::args::AlgorithmType;
+    use crate::mosaic_factory::MosaicFactory;
+    use crate::synthetic::{ChannelLayout, Pattern, SyntheticImage};
+
+    #[test]
+    fn renders_match_mosaics_and_original() {
+        for layout in [ChannelLayout::L, ChannelLayout::Rgb, ChannelLayout::Rgba] {
+            let img = SyntheticImage::new(Pattern::Noise, 48, 36, layout)
+                .with_seed(11)
+                .generate();
+            let reveal_mosaic = RevealMosaic::new(ImageData::new(&img, 1));
+            if layout != ChannelLayout::Rgba {
+                assert!(reveal_mosaic.render(1) == img.as_bytes(), "{:?}", layout);
+            }
+            // tile sizes dividing the image have no partial tiles
+            for tile_side_length in [4, 6, 12] {
+                let mosaic_factory = MosaicFactory::from_image_data(
+                    ImageData::new(&img, tile_side_length),
+                    AlgorithmType::Serial.mosaic_builder(),
+                    tile_side_length,
+                );
+                assert!(
+                    reveal_mosaic.render(tile_side_length) == mosaic_factory.generate_mosaic(),
+                    "{:?} tile side length {}",
+                    layout,
+                    tile_side_length
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn partial_tiles_are_centred() {
+        assert_eq!(RevealMosaic::tile_edges(10, 4), vec![0, 1, 5, 9, 10]);
+        assert_eq!(RevealMosaic::tile_edges(8, 4), vec![0, 4, 8]);
+        assert_eq!(RevealMosaic::tile_edges(3, 8), vec![0, 3]);
+
+        // a 3 pixel wide image with 2 pixel tiles gets a full tile and a partial tile
+        let img = SyntheticImage::new(Pattern::Gradient, 3, 1, ChannelLayout::L).generate();
+        let reveal_mosaic = RevealMosaic::new(ImageData::new(&img, 1));
+        let data = img.as_bytes();
+        let first = ((data[0] as u32 + data[1] as u32) / 2) as u8;
+        assert_eq!(reveal_mosaic.render(2), vec![first, first, data[2]]);
+    }
+
+    #[test]
+    fn tile_sizes_are_geometric() {
+        assert_eq!(
+            RevealMosaic::tile_sizes(64, 1, 7),
+            vec![64, 32, 16, 8, 4, 2, 1]
+        );
+        assert_eq!(RevealMosaic::tile_sizes(4, 16, 3), vec![4, 8, 16]);
+        assert_eq!(RevealMosaic::tile_sizes(2, 1, 5), vec![2, 1]);
+        assert_eq!(RevealMosaic::tile_sizes(8, 1, 1), vec![8]);
+    }
+}
@@ -1,3 +1,5 @@
+use crate::alpha;
+use crate::image_data::Sample;
 use crate::mosaic_factory::MosaicBuilder;
 use crate::MosaicFactory;
 
@@ -5,11 +7,16 @@ use crate::MosaicFactory;
 #[derive(Clone, Copy, Debug)]
 pub struct SerialMosaic;
 
-impl MosaicBuilder for SerialMosaic {
-    fn sum_tile_channels(&self, mosaic_factory: &MosaicFactory) -> Vec<u32> {
+impl<S: Sample> MosaicBuilder<S> for SerialMosaic {
+    fn name(&self) -> &'static str {
+        return "serial";
+    }
+
+    fn sum_tile_channels(&self, mosaic_factory: &MosaicFactory<S>) -> Vec<S::Sum> {
         let size = ((mosaic_factory.tiles_x * mosaic_factory.tiles_y)
             * mosaic_factory.image_data.channels as u32) as usize;
-        let mut tile_sum: Vec<u32> = vec![0; size];
+        let mut tile_sum: Vec<S::Sum> = vec![S::Sum::default(); size];
+        let has_alpha = alpha::has_alpha(&mosaic_factory.image_data);
 
         for tile_y in 0..mosaic_factory.tiles_y {
             for tile_x in 0..mosaic_factory.tiles_x {
@@ -22,10 +29,12 @@ impl MosaicBuilder for SerialMosaic {
                     for pixel_x in 0..mosaic_factory.tile_side_length {
                         let pixel_offset = (pixel_y * mosaic_factory.image_data.width + pixel_x)
                             * mosaic_factory.image_data.channels as u32;
+                        let index = (tile_index + pixel_offset) as usize;
+                        let pixel = &mosaic_factory.image_data.data
+                            [index..index + mosaic_factory.image_data.channels as usize];
                         for channel in 0..mosaic_factory.image_data.channels {
-                            let index = (tile_index + pixel_offset + channel as u32) as usize;
-                            let pixel = mosaic_factory.image_data.data[index];
-                            tile_sum[(tile_sum_index + channel as u32) as usize] += pixel as u32;
+                            tile_sum[(tile_sum_index + channel as u32) as usize] +=
+                                alpha::weighted_sample(pixel, channel as usize, has_alpha);
                         }
                     }
                 }
@@ -37,36 +46,43 @@ impl MosaicBuilder for SerialMosaic {
 
     fn calc_tile_average(
         &self,
-        mosaic_builder: &MosaicFactory,
-        tile_sum: &[u32],
-    ) -> (Vec<u8>, Vec<u8>) {
-        let mut global_sum: Vec<u128> = vec![0; mosaic_builder.image_data.channels as usize];
-        let mut tile_average: Vec<u8> = vec![0; tile_sum.len()];
+        mosaic_builder: &MosaicFactory<S>,
+        tile_sum: &[S::Sum],
+    ) -> (Vec<S>, Vec<S>) {
+        let channels = mosaic_builder.image_data.channels as usize;
+        let has_alpha = alpha::has_alpha(&mosaic_builder.image_data);
+        let mut global_sum: Vec<S::Sum> = vec![S::Sum::default(); channels];
+        let mut tile_average: Vec<S> = vec![S::default(); tile_sum.len()];
 
-        let len = (mosaic_builder.tiles_y
-            * mosaic_builder.tiles_x
-            * mosaic_builder.image_data.channels as u32) as usize;
-        for tile_channel in 0..len {
-            let channel = (tile_channel % mosaic_builder.image_data.channels as usize) as u8;
-            let tile_sum_channel = tile_sum[tile_channel];
-            let tile_average_channel = (tile_sum_channel / mosaic_builder.tile_pixels) as u8;
-            tile_average[tile_channel] = tile_average_channel;
-            global_sum[channel as usize] += tile_average_channel as u128;
+        let len = (mosaic_builder.tiles_y * mosaic_builder.tiles_x) as usize * channels;
+        for tile_channel in (0..len).step_by(channels) {
+            let tile_sum_channels = &tile_sum[tile_channel..tile_channel + channels];
+            for channel in 0..channels {
+                tile_average[tile_channel + channel] = alpha::channel_average(
+                    tile_sum_channels,
+                    channel,
+                    mosaic_builder.tile_pixels as u64,
+                    has_alpha,
+                );
+            }
+            let tile_average_channels = &tile_average[tile_channel..tile_channel + channels];
+            for (channel, sum) in global_sum.iter_mut().enumerate() {
+                *sum += alpha::weighted_sample(tile_average_channels, channel, has_alpha);
+            }
         }
 
-        for channel in 0..mosaic_builder.image_data.channels {
-            global_sum[channel as usize] /=
-                (mosaic_builder.tiles_x * mosaic_builder.tiles_y) as u128;
-        }
-        let global_average: Vec<u8> = global_sum.into_iter().map(|x| x as u8).collect();
+        let tiles = (mosaic_builder.tiles_x * mosaic_builder.tiles_y) as u64;
+        let global_average: Vec<S> = (0..channels)
+            .map(|channel| alpha::channel_average(&global_sum, channel, tiles, has_alpha))
+            .collect();
         return (tile_average, global_average);
     }
 
-    fn create_mosaic(&self, mosaic_builder: &MosaicFactory, tile_average: &[u8]) -> Vec<u8> {
+    fn create_mosaic(&self, mosaic_builder: &MosaicFactory<S>, tile_average: &[S]) -> Vec<S> {
         let size = (mosaic_builder.image_data.width
             * mosaic_builder.image_data.height
             * mosaic_builder.image_data.channels as u32) as usize;
-        let mut mosaic: Vec<u8> = vec![0; size];
+        let mut mosaic: Vec<S> = vec![S::default(); size];
 
         for tile_y in 0..mosaic_builder.tiles_y {
             for tile_x in 0..mosaic_builder.tiles_x {
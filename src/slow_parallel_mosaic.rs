@@ -2,6 +2,8 @@ use std::sync::Mutex;
 
 use rayon::prelude::*;
 
+use crate::alpha;
+use crate::image_data::Sample;
 use crate::mosaic_factory::MosaicBuilder;
 use crate::MosaicFactory;
 
@@ -9,11 +11,17 @@ use crate::MosaicFactory;
 #[derive(Clone, Copy, Debug)]
 pub struct SlowParallelMosaic;
 
-impl MosaicBuilder for SlowParallelMosaic {
-    fn sum_tile_channels(&self, mosaic_builder: &MosaicFactory) -> Vec<u32> {
+impl<S: Sample> MosaicBuilder<S> for SlowParallelMosaic {
+    fn name(&self) -> &'static str {
+        return "slow-parallel";
+    }
+
+    fn sum_tile_channels(&self, mosaic_builder: &MosaicFactory<S>) -> Vec<S::Sum> {
         let size = ((mosaic_builder.tiles_x * mosaic_builder.tiles_y)
             * mosaic_builder.image_data.channels as u32) as usize;
-        let tile_sum: Vec<Mutex<u32>> = (0..size).map(|_| Mutex::new(0)).collect();
+        let tile_sum: Vec<Mutex<S::Sum>> =
+            (0..size).map(|_| Mutex::new(S::Sum::default())).collect();
+        let has_alpha = alpha::has_alpha(&mosaic_builder.image_data);
 
         (0..mosaic_builder.tiles_y)
             .into_par_iter()
@@ -29,12 +37,14 @@ impl MosaicBuilder for SlowParallelMosaic {
                             let pixel_offset = (pixel_y * mosaic_builder.image_data.width
                                 + pixel_x)
                                 * mosaic_builder.image_data.channels as u32;
+                            let index = (tile_index + pixel_offset) as usize;
+                            let pixel = &mosaic_builder.image_data.data
+                                [index..index + mosaic_builder.image_data.channels as usize];
                             for channel in 0..mosaic_builder.image_data.channels {
-                                let pixel = mosaic_builder.image_data.data
-                                    [(tile_index + pixel_offset + channel as u32) as usize];
                                 *tile_sum[(tile_sum_index + channel as u32) as usize]
                                     .lock()
-                                    .unwrap() += pixel as u32;
+                                    .unwrap() +=
+                                    alpha::weighted_sample(pixel, channel as usize, has_alpha);
                             }
                         }
                     }
@@ -49,58 +59,68 @@ impl MosaicBuilder for SlowParallelMosaic {
 
     fn calc_tile_average(
         &self,
-        mosaic_builder: &MosaicFactory,
-        tile_sum: &[u32],
-    ) -> (Vec<u8>, Vec<u8>) {
-        let global_sum: Vec<Mutex<u128>> = (0..mosaic_builder.image_data.channels)
-            .map(|_| Mutex::new(0))
+        mosaic_builder: &MosaicFactory<S>,
+        tile_sum: &[S::Sum],
+    ) -> (Vec<S>, Vec<S>) {
+        let channels = mosaic_builder.image_data.channels as usize;
+        let has_alpha = alpha::has_alpha(&mosaic_builder.image_data);
+        let global_sum: Vec<Mutex<S::Sum>> = (0..channels)
+            .map(|_| Mutex::new(S::Sum::default()))
+            .collect();
+        let tile_average: Vec<Mutex<S>> = (0..tile_sum.len())
+            .map(|_| Mutex::new(S::default()))
             .collect();
-        let tile_average: Vec<Mutex<u8>> = (0..tile_sum.len()).map(|_| Mutex::new(0)).collect();
 
         (0..mosaic_builder.tiles_y)
             .into_par_iter()
             .for_each(|tile_y| {
                 for tile_x in 0..mosaic_builder.tiles_x {
-                    let tile_index = (tile_y * mosaic_builder.tiles_x + tile_x)
-                        * mosaic_builder.image_data.channels as u32;
-                    for channel in 0..mosaic_builder.image_data.channels {
-                        let tile_sum_channel = tile_sum[(tile_index + channel as u32) as usize];
-                        let tile_average_channel =
-                            (tile_sum_channel / mosaic_builder.tile_pixels) as u8;
+                    let tile_index = (tile_y * mosaic_builder.tiles_x + tile_x) as usize * channels;
+                    let tile_sum_channels = &tile_sum[tile_index..tile_index + channels];
+                    let tile_average_channels: Vec<S> = (0..channels)
+                        .map(|channel| {
+                            alpha::channel_average(
+                                tile_sum_channels,
+                                channel,
+                                mosaic_builder.tile_pixels as u64,
+                                has_alpha,
+                            )
+                        })
+                        .collect();
+                    for channel in 0..channels {
                         let update_tile_average = || {
-                            *tile_average[(tile_index + channel as u32) as usize]
-                                .lock()
-                                .unwrap() = tile_average_channel;
+                            *tile_average[tile_index + channel].lock().unwrap() =
+                                tile_average_channels[channel];
                         };
                         let update_global_sum = || {
-                            *global_sum[channel as usize].lock().unwrap() +=
-                                tile_average_channel as u128;
+                            *global_sum[channel].lock().unwrap() +=
+                                alpha::weighted_sample(&tile_average_channels, channel, has_alpha);
                         };
                         rayon::join(update_tile_average, update_global_sum);
                     }
                 }
             });
 
-        let mut global_average: Vec<_> = global_sum
+        let global_sum: Vec<S::Sum> = global_sum
             .into_iter()
             .map(|entry| entry.into_inner().unwrap())
             .collect();
-        global_average.iter_mut().for_each(|channel| {
-            *channel /= (mosaic_builder.tiles_x * mosaic_builder.tiles_y) as u128;
-        });
+        let tiles = (mosaic_builder.tiles_x * mosaic_builder.tiles_y) as u64;
+        let global_average: Vec<S> = (0..channels)
+            .map(|channel| alpha::channel_average(&global_sum, channel, tiles, has_alpha))
+            .collect();
         let tile_average = tile_average
             .into_iter()
             .map(|entry| entry.into_inner().unwrap())
             .collect();
-        let global_average: Vec<u8> = global_average.into_iter().map(|x| x as u8).collect();
         return (tile_average, global_average);
     }
 
-    fn create_mosaic(&self, mosaic_builder: &MosaicFactory, tile_average: &[u8]) -> Vec<u8> {
+    fn create_mosaic(&self, mosaic_builder: &MosaicFactory<S>, tile_average: &[S]) -> Vec<S> {
         let size = (mosaic_builder.image_data.width
             * mosaic_builder.image_data.height
             * mosaic_builder.image_data.channels as u32) as usize;
-        let mosaic: Mutex<Vec<u8>> = Mutex::new(vec![0; size]);
+        let mosaic: Mutex<Vec<S>> = Mutex::new(vec![S::default(); size]);
 
         (0..mosaic_builder.tiles_y)
             .into_par_iter()
@@ -0,0 +1,455 @@
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Write};
+use std::path::Path;
+
+use image::{ColorType, ImageFormat};
+use tiff::encoder::{colortype, TiffEncoder, TiffKind, TiffKindBig, TiffKindStandard};
+
+use crate::alpha::{self, AlphaOptions};
+use crate::image_io::{self, OutputOptions};
+use crate::log;
+use crate::mosaic_factory::MosaicBuilder;
+use crate::synthetic::SyntheticImage;
+use crate::tiff_reader::TiffRowSource;
+use crate::{ImageData, MosaicFactory};
+
+/// Decoder producing an 8-bit image one row at a time
+pub trait RowSource {
+    fn width(&self) -> u32;
+    fn height(&self) -> u32;
+    fn color(&self) -> ColorType;
+    /// Reads the next row into a buffer of `width * channels` bytes.
+    fn read_row(&mut self, row: &mut [u8]) -> io::Result<()>;
+}
+
+/// Opens a row source for PNG, TIFF or synthetic input, `tiff_page` selects the page of multi-page TIFF files.
+pub fn open_row_source<P: AsRef<Path>>(path: P, tiff_page: u32) -> io::Result<Box<dyn RowSource>> {
+    let path = path.as_ref();
+    if SyntheticImage::is_synthetic_path(path) {
+        return match SyntheticImage::parse(&path.to_string_lossy()) {
+            Ok(synthetic_image) => Ok(Box::new(SyntheticRowSource {
+                synthetic_image,
+                row_index: 0,
+            })),
+            Err(e) => Err(io::Error::new(io::ErrorKind::InvalidInput, e)),
+        };
+    }
+
+    let extension = path
+        .extension()
+        .map(|extension| extension.to_string_lossy().to_lowercase());
+    return match extension.as_deref() {
+        Some("png") => Ok(Box::new(PngRowSource::open(path)?)),
+        Some("tif" | "tiff") => Ok(Box::new(TiffRowSource::open(path, tiff_page)?)),
+        _ => Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            format!(
+                "reading by rows supports PNG and TIFF input, got: {}",
+                path.display()
+            ),
+        )),
+    };
+}
+
+struct PngRowSource {
+    reader: png::Reader<BufReader<File>>,
+    color: ColorType,
+}
+
+impl PngRowSource {
+    fn open(path: &Path) -> io::Result<PngRowSource> {
+        let mut decoder = png::Decoder::new(BufReader::new(File::open(path)?));
+        decoder.set_transformations(png::Transformations::normalize_to_color8());
+        let reader = decoder.read_info().map_err(io::Error::other)?;
+        if reader.info().interlaced {
+            return Err(io::Error::other("interlaced PNG cannot be streamed"));
+        }
+        let color = match reader.output_color_type().0 {
+            png::ColorType::Grayscale => ColorType::L8,
+            png::ColorType::GrayscaleAlpha => ColorType::La8,
+            png::ColorType::Rgb => ColorType::Rgb8,
+            png::ColorType::Rgba => ColorType::Rgba8,
+            png::ColorType::Indexed => unreachable!("palette is expanded by the decoder"),
+        };
+        return Ok(PngRowSource { reader, color });
+    }
+}
+
+impl RowSource for PngRowSource {
+    fn width(&self) -> u32 {
+        return self.reader.info().width;
+    }
+
+    fn height(&self) -> u32 {
+        return self.reader.info().height;
+    }
+
+    fn color(&self) -> ColorType {
+        return self.color;
+    }
+
+    fn read_row(&mut self, row: &mut [u8]) -> io::Result<()> {
+        match self.reader.next_row().map_err(io::Error::other)? {
+            Some(next_row) => row.copy_from_slice(next_row.data()),
+            None => return Err(io::Error::from(io::ErrorKind::UnexpectedEof)),
+        }
+        return Ok(());
+    }
+}
+
+struct SyntheticRowSource {
+    synthetic_image: SyntheticImage,
+    row_index: u32,
+}
+
+impl RowSource for SyntheticRowSource {
+    fn width(&self) -> u32 {
+        return self.synthetic_image.width;
+    }
+
+    fn height(&self) -> u32 {
+        return self.synthetic_image.height;
+    }
+
+    fn color(&self) -> ColorType {
+        return self.synthetic_image.color_type();
+    }
+
+    fn read_row(&mut self, row: &mut [u8]) -> io::Result<()> {
+        self.synthetic_image.fill_row(self.row_index, row);
+        self.row_index += 1;
+        return Ok(());
+    }
+}
+
+/// Mosaic pipeline processing one band of `tile_side_length` rows at a time. Memory use is proportional
+/// to a single band of the input and output instead of the whole image.
+pub struct StreamingMosaic {
+    pub tile_side_length: u32,
+    pub mosaic_builder: Box<dyn MosaicBuilder>,
+    pub alpha_options: AlphaOptions,
+    pub output_options: OutputOptions,
+    /// Page read from multi-page TIFF input
+    pub tiff_page: u32,
+}
+
+impl StreamingMosaic {
+    pub fn new(mosaic_builder: Box<dyn MosaicBuilder>, tile_side_length: u32) -> StreamingMosaic {
+        return StreamingMosaic {
+            tile_side_length,
+            mosaic_builder,
+            alpha_options: AlphaOptions::default(),
+            output_options: OutputOptions::default(),
+            tiff_page: 0,
+        };
+    }
+
+    pub fn with_tiff_page(mut self, tiff_page: u32) -> StreamingMosaic {
+        self.tiff_page = tiff_page;
+        return self;
+    }
+
+    pub fn with_alpha_options(mut self, alpha_options: AlphaOptions) -> StreamingMosaic {
+        self.alpha_options = alpha_options;
+        return self;
+    }
+
+    pub fn with_output_options(mut self, output_options: OutputOptions) -> StreamingMosaic {
+        self.output_options = output_options;
+        return self;
+    }
+
+    /// Size of the output after cropping the input to a multiple of the tile side length.
+    pub fn output_size(&self, width: u32, height: u32) -> (u32, u32) {
+        return (
+            (width / self.tile_side_length) * self.tile_side_length,
+            (height / self.tile_side_length) * self.tile_side_length,
+        );
+    }
+
+    /// Passes each output band to the sink and returns the global average. Output is identical to
+    /// `MosaicFactory` using the same builder on the whole image.
+    pub fn generate_mosaic(
+        &self,
+        row_source: &mut dyn RowSource,
+        sink: &mut dyn FnMut(&[u8]) -> io::Result<()>,
+    ) -> io::Result<Vec<u8>> {
+        let (width, height) = (row_source.width(), row_source.height());
+        let (output_width, output_height) = self.output_size(width, height);
+        if output_width == 0 || output_height == 0 {
+            return Err(io::Error::other("tile side length larger than the image"));
+        }
+        let color = row_source.color();
+        let channels = color.channel_count() as usize;
+        let margin_x = ((width - output_width) / 2) as usize * channels;
+        let margin_y = (height - output_height) / 2;
+
+        let band = ImageData {
+            width: output_width,
+            height: self.tile_side_length,
+            channels: channels as u8,
+            data: vec![0; output_width as usize * channels * self.tile_side_length as usize],
+            color,
+        };
+        let has_alpha = alpha::has_alpha(&band);
+        let mut mosaic_factory = MosaicFactory::from_image_data(
+            band,
+            Box::new(crate::SerialMosaic),
+            self.tile_side_length,
+        );
+        let mut row = vec![0; width as usize * channels];
+        for _ in 0..margin_y {
+            row_source.read_row(&mut row)?;
+        }
+
+        let row_length = output_width as usize * channels;
+        let mut global_sum: Vec<u64> = vec![0; channels];
+        for _ in 0..output_height / self.tile_side_length {
+            for band_row in mosaic_factory.image_data.data.chunks_exact_mut(row_length) {
+                row_source.read_row(&mut row)?;
+                band_row.copy_from_slice(&row[margin_x..margin_x + row_length]);
+            }
+
+            let tile_sum = self.mosaic_builder.sum_tile_channels(&mosaic_factory);
+            let (mut tile_average, _) = self
+                .mosaic_builder
+                .calc_tile_average(&mosaic_factory, &tile_sum);
+            for tile in tile_average.chunks_exact(channels) {
+                for (channel, sum) in global_sum.iter_mut().enumerate() {
+                    *sum += alpha::weighted_sample(tile, channel, has_alpha);
+                }
+            }
+            if has_alpha {
+                alpha::resolve_alpha(&mut tile_average, channels as u8, &self.alpha_options);
+            }
+            sink(
+                &self
+                    .mosaic_builder
+                    .create_mosaic(&mosaic_factory, &tile_average),
+            )?;
+        }
+
+        let tiles = (output_width / self.tile_side_length) as u64
+            * (output_height / self.tile_side_length) as u64;
+        let mut global_average: Vec<u8> = (0..channels)
+            .map(|channel| alpha::channel_average(&global_sum, channel, tiles, has_alpha))
+            .collect();
+        if has_alpha {
+            alpha::resolve_alpha(&mut global_average, channels as u8, &self.alpha_options);
+        }
+        return Ok(global_average);
+    }
+
+    /// Streams the input into a PNG or TIFF file in the format of the output options or the format matching the
+    /// output path extension. PNG output uses the compression and filter of the output options, a palette needs the
+    /// whole image and is not written.
+    pub fn generate_and_save_mosaic<I: AsRef<Path>, O: AsRef<Path>>(
+        &self,
+        input_image_path: I,
+        output_image_path: O,
+    ) -> io::Result<()> {
+        let output_image_path = output_image_path.as_ref();
+        let format = image_io::output_format(output_image_path, &self.output_options)
+            .map_err(io::Error::other)?;
+        if !matches!(format, ImageFormat::Png | ImageFormat::Tiff) {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                format!(
+                    "Streaming mode supports PNG and TIFF output, got: {:?}",
+                    format
+                ),
+            ));
+        }
+        let mut row_source = open_row_source(input_image_path, self.tiff_page)?;
+        if let Some(parent) = output_image_path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let (width, height) = self.output_size(row_source.width(), row_source.height());
+        let color = row_source.color();
+
+        let global_average = match format {
+            ImageFormat::Png => {
+                let file = BufWriter::new(File::create(output_image_path)?);
+                let mut encoder = png::Encoder::new(file, width, height);
+                image_io::configure_png_encoder(&mut encoder, &self.output_options);
+                if self.output_options.png_palette {
+                    log::info!(
+                        "Streaming mode cannot collect a palette, writing PNG without a palette"
+                    );
+                }
+                encoder.set_color(match color {
+                    ColorType::L8 => png::ColorType::Grayscale,
+                    ColorType::La8 => png::ColorType::GrayscaleAlpha,
+                    ColorType::Rgb8 => png::ColorType::Rgb,
+                    _ => png::ColorType::Rgba,
+                });
+                encoder.set_depth(png::BitDepth::Eight);
+                let mut writer = encoder.write_header().map_err(io::Error::other)?;
+                let mut stream_writer = writer.stream_writer().map_err(io::Error::other)?;
+                let global_average = self.generate_mosaic(row_source.as_mut(), &mut |band| {
+                    stream_writer.write_all(band)
+                })?;
+                stream_writer.finish().map_err(io::Error::other)?;
+                global_average
+            }
+            _ => {
+                let file = BufWriter::new(File::create(output_image_path)?);
+                let output_bytes = width as u64 * height as u64 * color.channel_count() as u64;
+                // Classic TIFF offsets are 32-bit, larger outputs need BigTIFF
+                match output_bytes < u32::MAX as u64 / 2 {
+                    true => self.save_tiff::<TiffKindStandard>(row_source.as_mut(), file, color)?,
+                    false => self.save_tiff::<TiffKindBig>(row_source.as_mut(), file, color)?,
+                }
+            }
+        };
+        log::verbose!("Image global average: {:?}", global_average);
+        return Ok(());
+    }
+
+    fn save_tiff<K: TiffKind>(
+        &self,
+        row_source: &mut dyn RowSource,
+        file: BufWriter<File>,
+        color: ColorType,
+    ) -> io::Result<Vec<u8>> {
+        let mut encoder =
+            TiffEncoder::new_generic(file).map_err(|e| io::Error::other(e.to_string()))?;
+        return match color {
+            ColorType::L8 => self.save_tiff_image::<K, colortype::Gray8>(row_source, &mut encoder),
+            ColorType::Rgb8 => self.save_tiff_image::<K, colortype::RGB8>(row_source, &mut encoder),
+            ColorType::Rgba8 => {
+                self.save_tiff_image::<K, colortype::RGBA8>(row_source, &mut encoder)
+            }
+            _ => Err(io::Error::other(format!(
+                "TIFF output does not support {:?} images",
+                color
+            ))),
+        };
+    }
+
+    fn save_tiff_image<K: TiffKind, C: colortype::ColorType<Inner = u8>>(
+        &self,
+        row_source: &mut dyn RowSource,
+        encoder: &mut TiffEncoder<BufWriter<File>, K>,
+    ) -> io::Result<Vec<u8>> {
+        let to_io_error = |e: tiff::TiffError| io::Error::other(e.to_string());
+        let (width, height) = self.output_size(row_source.width(), row_source.height());
+        let mut image = encoder.new_image::<C>(width, height).map_err(to_io_error)?;
+        image
+            .rows_per_strip(self.tile_side_length)
+            .map_err(to_io_error)?;
+        let global_average = self.generate_mosaic(row_source, &mut |band| {
+            return image
+                .write_strip(band)
+                .map_err(|e| io::Error::other(e.to_string()));
+        })?;
+        image.finish().map_err(to_io_error)?;
+        return Ok(global_average);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::args::AlgorithmType;
+    use crate::args::{OutputFormat, PngCompression, PngFilter};
+    use crate::synthetic::{ChannelLayout, Pattern};
+    use crate::temp_dir::TempDir;
+    use clap::ValueEnum;
+
+    #[test]
+    fn streaming_matches_whole_image_mosaic() {
+        for layout in [
+            ChannelLayout::L,
+            ChannelLayout::La,
+            ChannelLayout::Rgb,
+            ChannelLayout::Rgba,
+        ] {
+            for algorithm_type in AlgorithmType::value_variants() {
+                let synthetic_image =
+                    SyntheticImage::new(Pattern::Noise, 103, 61, layout).with_seed(5);
+                let mosaic_factory = MosaicFactory::from_image_data(
+                    ImageData::new(&synthetic_image.generate(), 9),
+                    algorithm_type.mosaic_builder(),
+                    9,
+                );
+
+                let streaming_mosaic = StreamingMosaic::new(algorithm_type.mosaic_builder(), 9);
+                let mut row_source = SyntheticRowSource {
+                    synthetic_image,
+                    row_index: 0,
+                };
+                let mut mosaic = Vec::new();
+                streaming_mosaic
+                    .generate_mosaic(&mut row_source, &mut |band| {
+                        mosaic.extend_from_slice(band);
+                        return Ok(());
+                    })
+                    .unwrap();
+                assert!(
+                    mosaic == mosaic_factory.generate_mosaic(),
+                    "{:?} {:?}",
+                    layout,
+                    algorithm_type
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn saved_files_match_whole_image_mosaic() {
+        let input = "synthetic:noise:67x45:layout=rgba:seed=3";
+        let img = SyntheticImage::parse(input).unwrap().generate();
+        let expected = MosaicFactory::from_image_data(
+            ImageData::new(&img, 8),
+            AlgorithmType::Serial.mosaic_builder(),
+            8,
+        )
+        .generate_mosaic();
+
+        let directory = TempDir::new("streaming");
+        let streaming_mosaic = StreamingMosaic::new(AlgorithmType::Parallel.mosaic_builder(), 8)
+            .with_output_options(OutputOptions {
+                png_compression: PngCompression::Best,
+                png_filter: PngFilter::Paeth,
+                png_palette: true,
+                ..OutputOptions::default()
+            });
+        for name in ["mosaic.png", "mosaic.tif"] {
+            let path = directory.join(name);
+            streaming_mosaic
+                .generate_and_save_mosaic(input, &path)
+                .unwrap();
+            let saved = image::open(&path).unwrap();
+            assert_eq!((saved.width(), saved.height()), (64, 40), "{}", name);
+            assert!(saved.as_bytes() == expected, "{}", name);
+        }
+    }
+
+    #[test]
+    fn output_format_overrides_extension() {
+        let input = "synthetic:gradient:32x32";
+        let directory = TempDir::new("streaming_format");
+        let path = directory.join("mosaic.out");
+        let streaming_mosaic = |format| {
+            return StreamingMosaic::new(AlgorithmType::Serial.mosaic_builder(), 8)
+                .with_output_options(OutputOptions {
+                    format: Some(format),
+                    ..OutputOptions::default()
+                });
+        };
+
+        streaming_mosaic(OutputFormat::Tiff)
+            .generate_and_save_mosaic(input, &path)
+            .unwrap();
+        let reader = image::io::Reader::open(&path).unwrap();
+        let format = reader.with_guessed_format().unwrap().format();
+        assert_eq!(format, Some(ImageFormat::Tiff));
+
+        let error = streaming_mosaic(OutputFormat::Jpeg)
+            .generate_and_save_mosaic(input, directory.join("mosaic.png"))
+            .unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::Unsupported);
+        assert!(!directory.join("mosaic.png").exists());
+    }
+}
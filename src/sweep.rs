@@ -0,0 +1,274 @@
+use std::fmt::Write as _;
+
+use image::imageops::FilterType;
+use image::DynamicImage;
+
+use crate::args::AlgorithmType;
+use crate::benchmark::BenchmarkResults;
+use crate::log;
+use crate::{ImageData, MosaicFactory};
+
+/// Matrix of parameters benchmarked by a sweep
+#[derive(Clone, Debug)]
+pub struct SweepConfig {
+    pub algorithm_types: Vec<AlgorithmType>,
+    pub tile_side_lengths: Vec<u32>,
+    pub thread_counts: Vec<usize>,
+    /// Image sizes as `(width, height)`, the source image is used as is when empty
+    pub image_sizes: Vec<(u32, u32)>,
+    pub warmup_runs: u32,
+    pub benchmark_runs: u32,
+}
+
+/// Benchmark results of a single combination of sweep parameters
+#[derive(Clone, Debug)]
+pub struct SweepPoint {
+    pub tile_side_length: u32,
+    pub threads: usize,
+    pub width: u32,
+    pub height: u32,
+    pub results: BenchmarkResults,
+}
+
+impl SweepPoint {
+    fn label(&self) -> String {
+        return format!(
+            "{} {}x{} tile {} threads {}",
+            self.results.builder_name, self.width, self.height, self.tile_side_length, self.threads
+        );
+    }
+}
+
+/// Benchmarks every combination of the sweep parameters on resized copies of the source image.
+pub fn run_sweep(img: &DynamicImage, config: &SweepConfig) -> Vec<SweepPoint> {
+    let images: Vec<DynamicImage> = match config.image_sizes.is_empty() {
+        true => vec![img.clone()],
+        false => config
+            .image_sizes
+            .iter()
+            .map(|&(width, height)| img.resize_exact(width, height, FilterType::Nearest))
+            .collect(),
+    };
+
+    let mut points = Vec::new();
+    for sized_img in &images {
+        for &tile_side_length in &config.tile_side_lengths {
+            let image_data = ImageData::new(sized_img, tile_side_length);
+            if image_data.width == 0 || image_data.height == 0 {
+                log::info!(
+                    "Skipping tile side length {} larger than {}x{} image",
+                    tile_side_length,
+                    sized_img.width(),
+                    sized_img.height()
+                );
+                continue;
+            }
+            let (width, height) = (image_data.width, image_data.height);
+            let mosaic_factory = MosaicFactory::from_image_data(
+                image_data,
+                AlgorithmType::Serial.mosaic_builder(),
+                tile_side_length,
+            );
+
+            for &threads in &config.thread_counts {
+                let thread_pool = match rayon::ThreadPoolBuilder::new().num_threads(threads).build()
+                {
+                    Ok(thread_pool) => thread_pool,
+                    Err(e) => panic!(
+                        "Failed to create thread pool with {} threads: {}",
+                        threads, e
+                    ),
+                };
+                for algorithm_type in &config.algorithm_types {
+                    let mosaic_builder = algorithm_type.mosaic_builder();
+                    let results = thread_pool.install(|| {
+                        return mosaic_factory.benchmark_builder(
+                            mosaic_builder.as_ref(),
+                            config.warmup_runs,
+                            config.benchmark_runs,
+                        );
+                    });
+                    let point = SweepPoint {
+                        tile_side_length,
+                        threads,
+                        width,
+                        height,
+                        results,
+                    };
+                    log::info!("Benchmarked {}", point.label());
+                    points.push(point);
+                }
+            }
+        }
+    }
+    return points;
+}
+
+/// Markdown table with median stage times of every sweep point.
+pub fn sweep_table(points: &[SweepPoint]) -> String {
+    let mut table = String::from(
+        "| Algorithm | Image | Tile | Threads | Stage 1 | Stage 2 | Stage 3 | Total | MP/s |\n\
+         |---|---|---:|---:|---:|---:|---:|---:|---:|\n",
+    );
+    for point in points {
+        let results = &point.results;
+        writeln!(
+            table,
+            "| {} | {}x{} | {} | {} | {:.2?} | {:.2?} | {:.2?} | {:.2?} | {:.2} |",
+            results.builder_name,
+            point.width,
+            point.height,
+            point.tile_side_length,
+            point.threads,
+            results.sum_tile_channels.median,
+            results.calc_tile_average.median,
+            results.create_mosaic.median,
+            results.total.median,
+            results.throughput()
+        )
+        .unwrap();
+    }
+    return table;
+}
+
+/// Horizontal stacked bar chart of median stage times, one bar per sweep point.
+pub fn sweep_chart(points: &[SweepPoint]) -> String {
+    const LABEL_WIDTH: f64 = 320.0;
+    const CHART_WIDTH: f64 = 560.0;
+    const BAR_HEIGHT: f64 = 18.0;
+    const ROW_HEIGHT: f64 = 26.0;
+    const TOP: f64 = 40.0;
+    const STAGES: [(&str, &str); 3] = [
+        ("Stage 1", "#4e79a7"),
+        ("Stage 2", "#f28e2b"),
+        ("Stage 3", "#59a14f"),
+    ];
+
+    let max_total = points
+        .iter()
+        .map(|point| {
+            let results = &point.results;
+            return (results.sum_tile_channels.median
+                + results.calc_tile_average.median
+                + results.create_mosaic.median)
+                .as_secs_f64();
+        })
+        .fold(0.0, f64::max);
+    let scale = if max_total > 0.0 {
+        CHART_WIDTH / max_total
+    } else {
+        0.0
+    };
+    let width = LABEL_WIDTH + CHART_WIDTH + 100.0;
+    let height = TOP + ROW_HEIGHT * points.len() as f64 + 20.0;
+
+    let mut svg = String::new();
+    writeln!(
+        svg,
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="{width}" height="{height}" font-family="sans-serif" font-size="12">"#
+    )
+    .unwrap();
+    writeln!(svg, r#"<rect width="100%" height="100%" fill="white"/>"#).unwrap();
+    for (index, (name, colour)) in STAGES.iter().enumerate() {
+        let x = LABEL_WIDTH + index as f64 * 90.0;
+        writeln!(
+            svg,
+            r#"<rect x="{x}" y="10" width="12" height="12" fill="{colour}"/><text x="{}" y="21">{name}</text>"#,
+            x + 16.0
+        )
+        .unwrap();
+    }
+
+    for (row, point) in points.iter().enumerate() {
+        let y = TOP + row as f64 * ROW_HEIGHT;
+        let results = &point.results;
+        writeln!(
+            svg,
+            r#"<text x="{}" y="{}" text-anchor="end">{}</text>"#,
+            LABEL_WIDTH - 8.0,
+            y + BAR_HEIGHT - 5.0,
+            point.label()
+        )
+        .unwrap();
+
+        let mut x = LABEL_WIDTH;
+        let stage_medians = [
+            results.sum_tile_channels.median,
+            results.calc_tile_average.median,
+            results.create_mosaic.median,
+        ];
+        for ((name, colour), median) in STAGES.iter().zip(stage_medians) {
+            let bar_width = median.as_secs_f64() * scale;
+            writeln!(
+                svg,
+                r#"<rect x="{x:.2}" y="{y}" width="{bar_width:.2}" height="{BAR_HEIGHT}" fill="{colour}"><title>{name}: {median:.2?}</title></rect>"#
+            )
+            .unwrap();
+            x += bar_width;
+        }
+        writeln!(
+            svg,
+            r#"<text x="{:.2}" y="{}">{:.2?}</text>"#,
+            x + 6.0,
+            y + BAR_HEIGHT - 5.0,
+            results.total.median
+        )
+        .unwrap();
+    }
+    svg += "</svg>\n";
+    return svg;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::synthetic::{ChannelLayout, Pattern, SyntheticImage};
+
+    #[test]
+    fn sweep_covers_every_combination() {
+        let img = SyntheticImage::new(Pattern::Noise, 64, 48, ChannelLayout::Rgb).generate();
+        let config = SweepConfig {
+            algorithm_types: vec![AlgorithmType::Serial, AlgorithmType::Parallel],
+            tile_side_lengths: vec![8, 40],
+            thread_counts: vec![1, 2],
+            image_sizes: vec![(32, 32), (64, 48)],
+            warmup_runs: 0,
+            benchmark_runs: 2,
+        };
+        let points = run_sweep(&img, &config);
+
+        // Tiles of 40 pixels do not fit into the 32x32 image and are skipped.
+        assert_eq!(points.len(), 3 * 2 * 2);
+        let combinations: Vec<(u32, u32, u32, usize, &str)> = points
+            .iter()
+            .map(|point| {
+                return (
+                    point.width,
+                    point.height,
+                    point.tile_side_length,
+                    point.threads,
+                    point.results.builder_name.as_str(),
+                );
+            })
+            .collect();
+        assert_eq!(
+            &combinations[..2],
+            &[(32, 32, 8, 1, "serial"), (32, 32, 8, 1, "parallel")]
+        );
+        assert!(combinations.contains(&(40, 40, 40, 2, "parallel")));
+        assert!(points
+            .iter()
+            .all(|point| point.results.total.samples.len() == 2));
+
+        let table = sweep_table(&points);
+        assert_eq!(table.lines().count(), 2 + points.len());
+        assert!(table.contains("| serial | 64x48 | 8 | 2 |"), "{}", table);
+        let chart = sweep_chart(&points);
+        assert!(chart.starts_with("<svg") && chart.trim_end().ends_with("</svg>"));
+        assert!(
+            chart.contains("serial 40x40 tile 40 threads 1"),
+            "{}",
+            chart
+        );
+    }
+}
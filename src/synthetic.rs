@@ -0,0 +1,305 @@
+use image::{DynamicImage, GrayAlphaImage, GrayImage, RgbImage, RgbaImage};
+use rayon::prelude::*;
+
+/// Prefix marking an input path as a synthetic image specification
+pub const SYNTHETIC_PREFIX: &str = "synthetic:";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Pattern {
+    /// Horizontal, vertical and diagonal gradients in consecutive colour channels
+    Gradient,
+    /// Alternating black and white square cells
+    Checkerboard,
+    /// Uniform per channel noise derived from the seed
+    Noise,
+    /// Single colour filling the whole image
+    Solid,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ChannelLayout {
+    L,
+    La,
+    Rgb,
+    Rgba,
+}
+
+impl ChannelLayout {
+    pub fn channels(&self) -> usize {
+        return match self {
+            ChannelLayout::L => 1,
+            ChannelLayout::La => 2,
+            ChannelLayout::Rgb => 3,
+            ChannelLayout::Rgba => 4,
+        };
+    }
+
+    fn has_alpha(&self) -> bool {
+        return matches!(self, ChannelLayout::La | ChannelLayout::Rgba);
+    }
+}
+
+/// Deterministic in-memory image, identical for identical parameters regardless of thread count
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SyntheticImage {
+    pub pattern: Pattern,
+    pub width: u32,
+    pub height: u32,
+    pub layout: ChannelLayout,
+    pub seed: u64,
+    /// Side length of checkerboard cells
+    pub cell_side_length: u32,
+    /// Colour of solid images
+    pub color: [u8; 3],
+    /// Alpha of solid images
+    pub alpha: u8,
+}
+
+impl SyntheticImage {
+    pub fn new(pattern: Pattern, width: u32, height: u32, layout: ChannelLayout) -> SyntheticImage {
+        return SyntheticImage {
+            pattern,
+            width,
+            height,
+            layout,
+            seed: 0,
+            cell_side_length: 16,
+            color: [128, 128, 128],
+            alpha: 255,
+        };
+    }
+
+    pub fn with_seed(mut self, seed: u64) -> SyntheticImage {
+        self.seed = seed;
+        return self;
+    }
+
+    pub fn with_cell_side_length(mut self, cell_side_length: u32) -> SyntheticImage {
+        self.cell_side_length = cell_side_length;
+        return self;
+    }
+
+    pub fn with_color(mut self, color: [u8; 3], alpha: u8) -> SyntheticImage {
+        self.color = color;
+        self.alpha = alpha;
+        return self;
+    }
+
+    pub fn is_synthetic_path<P: AsRef<std::path::Path>>(path: P) -> bool {
+        return path
+            .as_ref()
+            .to_str()
+            .is_some_and(|path| path.starts_with(SYNTHETIC_PREFIX));
+    }
+
+    /// Parses `synthetic:PATTERN:WIDTHxHEIGHT` followed by optional `:key=value` parameters:
+    /// `layout` (l, la, rgb, rgba), `seed`, `cell`, `color` (rrggbb) and `alpha`.
+    pub fn parse(spec: &str) -> Result<SyntheticImage, String> {
+        let Some(spec) = spec.strip_prefix(SYNTHETIC_PREFIX) else {
+            return Err(format!(
+                "expected {} prefix, got: {}",
+                SYNTHETIC_PREFIX, spec
+            ));
+        };
+        let mut parts = spec.split(':');
+        let pattern = match parts.next().unwrap_or_default() {
+            "gradient" => Pattern::Gradient,
+            "checkerboard" => Pattern::Checkerboard,
+            "noise" => Pattern::Noise,
+            "solid" => Pattern::Solid,
+            pattern => return Err(format!("unknown synthetic pattern: {}", pattern)),
+        };
+        let size = parts.next().unwrap_or_default();
+        let (width, height) = match size.split_once('x').map(|(w, h)| (w.parse(), h.parse())) {
+            Some((Ok(width), Ok(height))) if width > 0 && height > 0 => (width, height),
+            _ => {
+                return Err(format!(
+                    "expected size in WIDTHxHEIGHT format, got: {}",
+                    size
+                ))
+            }
+        };
+
+        let mut synthetic_image = SyntheticImage::new(pattern, width, height, ChannelLayout::Rgb);
+        for parameter in parts {
+            let Some((key, value)) = parameter.split_once('=') else {
+                return Err(format!("expected key=value parameter, got: {}", parameter));
+            };
+            let invalid = || format!("invalid value of synthetic parameter {}: {}", key, value);
+            match key {
+                "layout" => {
+                    synthetic_image.layout = match value {
+                        "l" => ChannelLayout::L,
+                        "la" => ChannelLayout::La,
+                        "rgb" => ChannelLayout::Rgb,
+                        "rgba" => ChannelLayout::Rgba,
+                        _ => return Err(invalid()),
+                    }
+                }
+                "seed" => {
+                    let seed = value.parse().map_err(|_| invalid())?;
+                    synthetic_image = synthetic_image.with_seed(seed);
+                }
+                "cell" => {
+                    let cell_side_length = match value.parse() {
+                        Ok(0) | Err(_) => return Err(invalid()),
+                        Ok(cell_side_length) => cell_side_length,
+                    };
+                    synthetic_image = synthetic_image.with_cell_side_length(cell_side_length);
+                }
+                "color" => {
+                    let color = crate::args::parse_hex_color(value)?;
+                    let alpha = synthetic_image.alpha;
+                    synthetic_image = synthetic_image.with_color(color, alpha);
+                }
+                "alpha" => synthetic_image.alpha = value.parse().map_err(|_| invalid())?,
+                _ => return Err(format!("unknown synthetic parameter: {}", key)),
+            }
+        }
+        return Ok(synthetic_image);
+    }
+
+    /// Generates the image, rows are filled in parallel.
+    pub fn generate(&self) -> DynamicImage {
+        let channels = self.layout.channels();
+        let row_length = self.width as usize * channels;
+        let mut data = vec![0; row_length * self.height as usize];
+        data.par_chunks_mut(row_length)
+            .enumerate()
+            .for_each(|(y, row)| self.fill_row(y as u32, row));
+
+        let (width, height) = (self.width, self.height);
+        return match self.layout {
+            ChannelLayout::L => {
+                DynamicImage::ImageLuma8(GrayImage::from_raw(width, height, data).unwrap())
+            }
+            ChannelLayout::La => {
+                DynamicImage::ImageLumaA8(GrayAlphaImage::from_raw(width, height, data).unwrap())
+            }
+            ChannelLayout::Rgb => {
+                DynamicImage::ImageRgb8(RgbImage::from_raw(width, height, data).unwrap())
+            }
+            ChannelLayout::Rgba => {
+                DynamicImage::ImageRgba8(RgbaImage::from_raw(width, height, data).unwrap())
+            }
+        };
+    }
+
+    /// Fills a single row of the image, the row has to hold `width` pixels of the channel layout.
+    pub fn fill_row(&self, y: u32, row: &mut [u8]) {
+        let channels = self.layout.channels();
+        for (x, pixel) in row.chunks_exact_mut(channels).enumerate() {
+            self.fill_pixel(x as u32, y, pixel);
+        }
+    }
+
+    pub fn color_type(&self) -> image::ColorType {
+        return match self.layout {
+            ChannelLayout::L => image::ColorType::L8,
+            ChannelLayout::La => image::ColorType::La8,
+            ChannelLayout::Rgb => image::ColorType::Rgb8,
+            ChannelLayout::Rgba => image::ColorType::Rgba8,
+        };
+    }
+
+    fn fill_pixel(&self, x: u32, y: u32, pixel: &mut [u8]) {
+        let channels = pixel.len() as u64;
+        let color_channels = match self.layout.has_alpha() {
+            true => pixel.len() - 1,
+            false => pixel.len(),
+        };
+        let scale = |value: u64, max: u64| (value * 255 / max.max(1)) as u8;
+        let (width, height) = (self.width as u64, self.height as u64);
+        let (x, y) = (x as u64, y as u64);
+
+        for (channel, value) in pixel.iter_mut().enumerate() {
+            let is_alpha = channel == color_channels;
+            *value = match self.pattern {
+                Pattern::Gradient if is_alpha => 255 - scale(x, width - 1),
+                Pattern::Gradient => match (color_channels, channel) {
+                    (1, _) | (_, 2) => scale(x + y, width + height - 2),
+                    (_, 0) => scale(x, width - 1),
+                    _ => scale(y, height - 1),
+                },
+                Pattern::Checkerboard => {
+                    let cell = self.cell_side_length as u64;
+                    match ((x / cell + y / cell).is_multiple_of(2), is_alpha) {
+                        (true, _) => 255,
+                        (false, true) => 128,
+                        (false, false) => 0,
+                    }
+                }
+                Pattern::Noise => {
+                    let index = (y * width + x) * channels + channel as u64;
+                    (splitmix64(self.seed ^ index) >> 56) as u8
+                }
+                Pattern::Solid if is_alpha => self.alpha,
+                Pattern::Solid if color_channels == 1 => {
+                    let [r, g, b] = self.color.map(|c| c as u32);
+                    ((r * 299 + g * 587 + b * 114) / 1000) as u8
+                }
+                Pattern::Solid => self.color[channel],
+            };
+        }
+    }
+}
+
+/// Stateless hash used instead of a sequential generator so noise does not depend on thread scheduling.
+fn splitmix64(value: u64) -> u64 {
+    let mut z = value.wrapping_add(0x9e3779b97f4a7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    return z ^ (z >> 31);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::args::AlgorithmType;
+    use crate::{ImageData, MosaicFactory};
+
+    #[test]
+    fn generation_is_deterministic() {
+        let noise = SyntheticImage::parse("synthetic:noise:67x45:layout=rgba:seed=7").unwrap();
+        assert_eq!(noise.generate(), noise.generate());
+        assert_ne!(noise.generate(), noise.clone().with_seed(8).generate());
+    }
+
+    #[test]
+    fn layouts_match_channel_counts() {
+        for layout in [
+            ChannelLayout::L,
+            ChannelLayout::La,
+            ChannelLayout::Rgb,
+            ChannelLayout::Rgba,
+        ] {
+            let img = SyntheticImage::new(Pattern::Gradient, 31, 17, layout).generate();
+            assert_eq!((img.width(), img.height()), (31, 17));
+            assert_eq!(img.color().channel_count() as usize, layout.channels());
+        }
+    }
+
+    #[test]
+    fn checkerboard_aligned_with_tiles_keeps_cells() {
+        let img = SyntheticImage::new(Pattern::Checkerboard, 64, 48, ChannelLayout::Rgb)
+            .with_cell_side_length(16)
+            .generate();
+        for algorithm_type in [AlgorithmType::Serial, AlgorithmType::Parallel] {
+            let mosaic_factory = MosaicFactory::from_image_data(
+                ImageData::new(&img, 16),
+                algorithm_type.mosaic_builder(),
+                16,
+            );
+            assert_eq!(mosaic_factory.generate_mosaic(), img.as_bytes());
+        }
+    }
+
+    #[test]
+    fn invalid_specs_are_rejected() {
+        assert!(SyntheticImage::parse("synthetic:waves:10x10").is_err());
+        assert!(SyntheticImage::parse("synthetic:noise:10by10").is_err());
+        assert!(SyntheticImage::parse("synthetic:noise:0x10").is_err());
+        assert!(SyntheticImage::parse("synthetic:checkerboard:10x10:cell=0").is_err());
+        assert!(SyntheticImage::parse("synthetic:solid:10x10:color=zz0000").is_err());
+    }
+}
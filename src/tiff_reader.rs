@@ -0,0 +1,417 @@
+use std::fs::File;
+use std::io::{self, BufReader, Read, Seek};
+use std::path::Path;
+
+use image::{ColorType, DynamicImage, ImageBuffer, Luma};
+use tiff::decoder::{Decoder, DecodingResult, Limits};
+use tiff::tags::{PlanarConfiguration, Tag};
+
+use crate::streaming::RowSource;
+
+/// Reads a page of a stripped or tiled TIFF or BigTIFF file one row at a time. Only a single row of strips
+/// or tiles is decoded and kept in memory, 16-bit samples are reduced to 8 bits. Planar-separate pages are not
+/// supported.
+pub struct TiffRowSource {
+    decoder: Decoder<BufReader<File>>,
+    width: u32,
+    height: u32,
+    color: ColorType,
+    chunk_width: u32,
+    chunk_height: u32,
+    chunks_across: u32,
+    /// Decoded rows of the current row of chunks
+    band: Vec<u8>,
+    band_index: u32,
+    band_offset: usize,
+}
+
+fn to_io_error(e: tiff::TiffError) -> io::Error {
+    return io::Error::other(e.to_string());
+}
+
+/// Fails for planar-separate pages, which store every channel in its own strips or tiles.
+fn check_chunky<R: Read + Seek>(decoder: &mut Decoder<R>) -> io::Result<()> {
+    let planar_configuration = decoder
+        .find_tag_unsigned::<u16>(Tag::PlanarConfiguration)
+        .map_err(to_io_error)?;
+    return match planar_configuration {
+        Some(planar_configuration)
+            if planar_configuration != PlanarConfiguration::Chunky.to_u16() =>
+        {
+            Err(io::Error::other(format!(
+                "unsupported planar configuration {}, only chunky TIFF samples can be read",
+                planar_configuration
+            )))
+        }
+        _ => Ok(()),
+    };
+}
+
+/// Reduces 16-bit samples to 8 bits with the rounding conversion of image, so streamed pages match decoded ones.
+fn narrow_samples(samples: Vec<u16>) -> Vec<u8> {
+    let samples =
+        ImageBuffer::<Luma<u16>, Vec<u16>>::from_raw(samples.len() as u32, 1, samples).unwrap();
+    return DynamicImage::ImageLuma16(samples).to_luma8().into_raw();
+}
+
+impl TiffRowSource {
+    pub fn open(path: &Path, page: u32) -> io::Result<TiffRowSource> {
+        let page_count = TiffRowSource::page_count(path)?;
+        if page >= page_count {
+            return Err(io::Error::other(format!(
+                "page {} requested but the file has {} page(s)",
+                page, page_count
+            )));
+        }
+        let mut decoder = Decoder::new(BufReader::new(File::open(path)?))
+            .map_err(to_io_error)?
+            .with_limits(Limits::unlimited());
+        if page > 0 {
+            decoder.seek_to_image(page as usize).map_err(to_io_error)?;
+        }
+
+        check_chunky(&mut decoder)?;
+
+        let (width, height) = decoder.dimensions().map_err(to_io_error)?;
+        let color = match decoder.colortype().map_err(to_io_error)? {
+            tiff::ColorType::Gray(8 | 16) => ColorType::L8,
+            tiff::ColorType::GrayA(8 | 16) => ColorType::La8,
+            tiff::ColorType::RGB(8 | 16) => ColorType::Rgb8,
+            tiff::ColorType::RGBA(8 | 16) => ColorType::Rgba8,
+            color => {
+                return Err(io::Error::other(format!(
+                    "unsupported TIFF colour type {:?}",
+                    color
+                )))
+            }
+        };
+        let (chunk_width, chunk_height) = decoder.chunk_dimensions();
+        return Ok(TiffRowSource {
+            decoder,
+            width,
+            height,
+            color,
+            chunk_width,
+            chunk_height,
+            chunks_across: width.div_ceil(chunk_width),
+            band: Vec::new(),
+            band_index: 0,
+            band_offset: 0,
+        });
+    }
+
+    /// Fails for planar-separate first pages, which image cannot decode either and panics on.
+    pub fn check_first_page(path: &Path) -> io::Result<()> {
+        let mut decoder = Decoder::new(BufReader::new(File::open(path)?)).map_err(to_io_error)?;
+        return check_chunky(&mut decoder);
+    }
+
+    /// Number of pages in a TIFF file, pyramidal files store each resolution level as a page.
+    pub fn page_count(path: &Path) -> io::Result<u32> {
+        let mut decoder = Decoder::new(BufReader::new(File::open(path)?)).map_err(to_io_error)?;
+        let mut pages = 1;
+        while decoder.more_images() {
+            decoder.next_image().map_err(to_io_error)?;
+            pages += 1;
+        }
+        return Ok(pages);
+    }
+
+    /// Decodes every chunk in the next row of strips or tiles into the band buffer.
+    fn read_band(&mut self) -> io::Result<()> {
+        let channels = self.color.channel_count() as usize;
+        let row_length = self.width as usize * channels;
+        let band_rows = self
+            .chunk_height
+            .min(self.height - self.band_index * self.chunk_height);
+        self.band.resize(row_length * band_rows as usize, 0);
+
+        for chunk_column in 0..self.chunks_across {
+            let chunk_index = self.band_index * self.chunks_across + chunk_column;
+            let (data_width, data_height) = self.decoder.chunk_data_dimensions(chunk_index);
+            let chunk: Vec<u8> = match self.decoder.read_chunk(chunk_index).map_err(to_io_error)? {
+                DecodingResult::U8(chunk) => chunk,
+                DecodingResult::U16(chunk) => narrow_samples(chunk),
+                _ => return Err(io::Error::other("unsupported TIFF sample format")),
+            };
+
+            let chunk_row_length = data_width as usize * channels;
+            let band_x = (chunk_column * self.chunk_width) as usize * channels;
+            for (chunk_row, chunk_row_data) in chunk
+                .chunks_exact(chunk_row_length)
+                .take(data_height as usize)
+                .enumerate()
+            {
+                let start = chunk_row * row_length + band_x;
+                self.band[start..start + chunk_row_length].copy_from_slice(chunk_row_data);
+            }
+        }
+        self.band_index += 1;
+        self.band_offset = 0;
+        return Ok(());
+    }
+}
+
+impl RowSource for TiffRowSource {
+    fn width(&self) -> u32 {
+        return self.width;
+    }
+
+    fn height(&self) -> u32 {
+        return self.height;
+    }
+
+    fn color(&self) -> ColorType {
+        return self.color;
+    }
+
+    fn read_row(&mut self, row: &mut [u8]) -> io::Result<()> {
+        if self.band_offset >= self.band.len() {
+            self.read_band()?;
+        }
+        row.copy_from_slice(&self.band[self.band_offset..self.band_offset + row.len()]);
+        self.band_offset += row.len();
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::temp_dir::TempDir;
+    use tiff::encoder::{colortype, TiffEncoder};
+
+    /// Page of an uncompressed RGB test file, stored in strips of a row or in tiles of the given size.
+    struct TestPage {
+        width: u32,
+        height: u32,
+        data: Vec<u8>,
+        tile: Option<(u32, u32)>,
+        planar_configuration: u16,
+    }
+
+    impl TestPage {
+        fn new(width: u32, height: u32, seed: u32, tile: Option<(u32, u32)>) -> TestPage {
+            let data = (0..width * height * 3)
+                .map(|i| (i.wrapping_mul(31).wrapping_add(seed * 7) % 251) as u8)
+                .collect();
+            return TestPage {
+                width,
+                height,
+                data,
+                tile,
+                planar_configuration: 1,
+            };
+        }
+
+        /// Chunks in file order, tiles are padded to the full tile size like the TIFF specification requires.
+        /// Planar-separate pages store the chunks of each channel after another.
+        fn chunks(&self) -> Vec<Vec<u8>> {
+            let (tile_width, tile_height) = self.tile.unwrap_or((self.width, 1));
+            let planes: Vec<Vec<usize>> = match self.planar_configuration {
+                2 => (0..3).map(|channel| vec![channel]).collect(),
+                _ => vec![vec![0, 1, 2]],
+            };
+            let mut chunks = Vec::new();
+            for plane in &planes {
+                for tile_y in (0..self.height).step_by(tile_height as usize) {
+                    for tile_x in (0..self.width).step_by(tile_width as usize) {
+                        let mut chunk = vec![0; (tile_width * tile_height) as usize * plane.len()];
+                        for y in tile_y..(tile_y + tile_height).min(self.height) {
+                            for x in tile_x..(tile_x + tile_width).min(self.width) {
+                                let source = ((y * self.width + x) * 3) as usize;
+                                let target =
+                                    ((y - tile_y) * tile_width + x - tile_x) as usize * plane.len();
+                                for (offset, &channel) in plane.iter().enumerate() {
+                                    chunk[target + offset] = self.data[source + channel];
+                                }
+                            }
+                        }
+                        chunks.push(chunk);
+                    }
+                }
+            }
+            return chunks;
+        }
+    }
+
+    fn write_tiff(path: &Path, pages: &[TestPage], big: bool) {
+        let (offset_size, inline_size) = if big { (8, 8) } else { (4, 4) };
+        let mut file: Vec<u8> = Vec::new();
+        file.extend_from_slice(b"II");
+        if big {
+            file.extend_from_slice(&43u16.to_le_bytes());
+            file.extend_from_slice(&8u16.to_le_bytes());
+            file.extend_from_slice(&0u16.to_le_bytes());
+        } else {
+            file.extend_from_slice(&42u16.to_le_bytes());
+        }
+        let mut next_ifd_pointer = file.len();
+        file.resize(file.len() + offset_size, 0);
+
+        let write_value = |file: &mut Vec<u8>, value: u64, size: usize| {
+            file.extend_from_slice(&value.to_le_bytes()[..size]);
+        };
+        for page in pages {
+            let mut chunk_offsets = Vec::new();
+            let mut chunk_sizes = Vec::new();
+            for chunk in page.chunks() {
+                chunk_offsets.push(file.len() as u64);
+                chunk_sizes.push(chunk.len() as u64);
+                file.extend_from_slice(&chunk);
+            }
+
+            // (tag, type, value size, values)
+            let offset_type = if big { 16 } else { 4 };
+            let mut entries: Vec<(u16, u16, usize, Vec<u64>)> = vec![
+                (256, 4, 4, vec![page.width as u64]),
+                (257, 4, 4, vec![page.height as u64]),
+                (258, 3, 2, vec![8, 8, 8]),
+                (259, 3, 2, vec![1]),
+                (262, 3, 2, vec![2]),
+                (277, 3, 2, vec![3]),
+                (284, 3, 2, vec![page.planar_configuration as u64]),
+            ];
+            match page.tile {
+                Some((tile_width, tile_height)) => {
+                    entries.push((322, 4, 4, vec![tile_width as u64]));
+                    entries.push((323, 4, 4, vec![tile_height as u64]));
+                    entries.push((324, offset_type, offset_size, chunk_offsets));
+                    entries.push((325, offset_type, offset_size, chunk_sizes));
+                }
+                None => {
+                    entries.push((273, offset_type, offset_size, chunk_offsets));
+                    entries.push((278, 4, 4, vec![1]));
+                    entries.push((279, offset_type, offset_size, chunk_sizes));
+                }
+            }
+
+            let mut out_of_line_offsets = Vec::new();
+            for (_, _, value_size, values) in &entries {
+                if value_size * values.len() > inline_size {
+                    if file.len() % 2 == 1 {
+                        file.push(0);
+                    }
+                    out_of_line_offsets.push(Some(file.len() as u64));
+                    for value in values {
+                        write_value(&mut file, *value, *value_size);
+                    }
+                } else {
+                    out_of_line_offsets.push(None);
+                }
+            }
+
+            if file.len() % 2 == 1 {
+                file.push(0);
+            }
+            let ifd_offset = file.len() as u64;
+            file[next_ifd_pointer..next_ifd_pointer + offset_size]
+                .copy_from_slice(&ifd_offset.to_le_bytes()[..offset_size]);
+            write_value(&mut file, entries.len() as u64, if big { 8 } else { 2 });
+            for ((tag, field_type, value_size, values), out_of_line) in
+                entries.iter().zip(out_of_line_offsets)
+            {
+                file.extend_from_slice(&tag.to_le_bytes());
+                file.extend_from_slice(&field_type.to_le_bytes());
+                write_value(&mut file, values.len() as u64, offset_size);
+                let field_start = file.len();
+                match out_of_line {
+                    Some(offset) => write_value(&mut file, offset, offset_size),
+                    None => {
+                        for value in values {
+                            write_value(&mut file, *value, *value_size);
+                        }
+                    }
+                }
+                file.resize(field_start + inline_size, 0);
+            }
+            next_ifd_pointer = file.len();
+            file.resize(file.len() + offset_size, 0);
+        }
+        std::fs::write(path, file).unwrap();
+    }
+
+    fn read_all_rows(path: &Path, page: u32) -> (u32, u32, Vec<u8>) {
+        let mut row_source = TiffRowSource::open(path, page).unwrap();
+        assert_eq!(row_source.color(), ColorType::Rgb8);
+        let (width, height) = (row_source.width(), row_source.height());
+        let mut row = vec![0; width as usize * 3];
+        let mut data = Vec::new();
+        for _ in 0..height {
+            row_source.read_row(&mut row).unwrap();
+            data.extend_from_slice(&row);
+        }
+        return (width, height, data);
+    }
+
+    #[test]
+    fn reads_stripped_and_tiled_pages() {
+        let directory = TempDir::new("tiff");
+        for big in [false, true] {
+            let path = directory.join(format!("pages_{}.tiff", big));
+            let pages = [
+                TestPage::new(53, 37, 1, None),
+                TestPage::new(50, 35, 2, Some((16, 16))),
+                TestPage::new(16, 32, 3, Some((16, 16))),
+            ];
+            write_tiff(&path, &pages, big);
+            for (index, page) in pages.iter().enumerate() {
+                let (width, height, data) = read_all_rows(&path, index as u32);
+                assert_eq!((width, height), (page.width, page.height));
+                assert!(data == page.data, "page {} of big={} differs", index, big);
+            }
+        }
+    }
+
+    #[test]
+    fn rejects_missing_page() {
+        let directory = TempDir::new("tiff");
+        let path = directory.join("single.tiff");
+        write_tiff(&path, &[TestPage::new(8, 8, 0, Some((16, 16)))], false);
+        assert!(TiffRowSource::open(&path, 1).is_err());
+    }
+
+    #[test]
+    fn rejects_planar_separate_pages() {
+        let directory = TempDir::new("tiff");
+        let path = directory.join("planar.tiff");
+        let mut page = TestPage::new(8, 8, 0, None);
+        page.planar_configuration = 2;
+        write_tiff(&path, &[page], false);
+        let error = TiffRowSource::open(&path, 0).err().unwrap().to_string();
+        assert!(
+            error.contains("unsupported planar configuration"),
+            "{}",
+            error
+        );
+        let error = crate::ImageData::from_path(&path, 1)
+            .unwrap_err()
+            .to_string();
+        assert!(
+            error.contains("unsupported planar configuration"),
+            "{}",
+            error
+        );
+    }
+
+    #[test]
+    fn narrows_16_bit_samples_like_decoded_images() {
+        let directory = TempDir::new("tiff");
+        let path = directory.join("16_bit.tiff");
+        let (width, height) = (64, 32);
+        let data: Vec<u16> = (0..width * height * 3)
+            .map(|i: u32| i.wrapping_mul(10_007) as u16)
+            .collect();
+        TiffEncoder::new(File::create(&path).unwrap())
+            .unwrap()
+            .write_image::<colortype::RGB16>(width, height, &data)
+            .unwrap();
+
+        let (_, _, streamed) = read_all_rows(&path, 0);
+        assert!(streamed == crate::ImageData::from_path(&path, 1).unwrap().data);
+        assert!(streamed
+            .iter()
+            .zip(&data)
+            .any(|(&narrowed, &sample)| narrowed != (sample >> 8) as u8));
+    }
+}
@@ -0,0 +1,81 @@
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::MosaicFactory;
+
+/// Grid of tile averages of an image, lets other tools place or match tiles without reading the image again
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TileIndex {
+    pub path: String,
+    pub tile_side_length: u32,
+    pub tiles_x: u32,
+    pub tiles_y: u32,
+    pub channels: u8,
+    pub global_average: Vec<u8>,
+    /// Averages of tiles in row-major order with alpha resolved by the alpha mode
+    pub tiles: Vec<Vec<u8>>,
+}
+
+impl TileIndex {
+    pub fn new(input_image_path: &str, mosaic_factory: &MosaicFactory) -> TileIndex {
+        let (tile_averages, global_average) = mosaic_factory.tile_averages();
+        let channels = mosaic_factory.image_data.channels;
+        return TileIndex {
+            path: input_image_path.to_string(),
+            tile_side_length: mosaic_factory.tile_side_length,
+            tiles_x: mosaic_factory.tiles_x,
+            tiles_y: mosaic_factory.tiles_y,
+            channels,
+            global_average,
+            tiles: tile_averages
+                .chunks_exact(channels as usize)
+                .map(<[u8]>::to_vec)
+                .collect(),
+        };
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> std::io::Result<()> {
+        if let Some(parent) = path.as_ref().parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        return std::fs::write(path, serde_json::to_string_pretty(self).unwrap() + "\n");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::args::AlgorithmType;
+    use crate::image_data::ImageData;
+    use crate::synthetic::{ChannelLayout, Pattern, SyntheticImage};
+
+    #[test]
+    fn index_holds_tile_averages() {
+        let img = SyntheticImage::new(Pattern::Noise, 40, 24, ChannelLayout::Rgb)
+            .with_seed(5)
+            .generate();
+        let mosaic_factory = MosaicFactory::from_image_data(
+            ImageData::new(&img, 8),
+            AlgorithmType::Parallel.mosaic_builder(),
+            8,
+        );
+        let tile_index = TileIndex::new("noise.png", &mosaic_factory);
+        assert_eq!((tile_index.tiles_x, tile_index.tiles_y), (5, 3));
+        assert_eq!(tile_index.tiles.len(), 15);
+
+        // the mosaic repeats each tile average over its tile
+        let mosaic = mosaic_factory.generate_mosaic();
+        let last_tile_origin = ((2 * 8) * 40 + 4 * 8) * 3;
+        assert_eq!(
+            tile_index.tiles[14],
+            mosaic[last_tile_origin..last_tile_origin + 3].to_vec()
+        );
+
+        let json = serde_json::to_string(&tile_index).unwrap();
+        assert_eq!(
+            serde_json::from_str::<TileIndex>(&json).unwrap(),
+            tile_index
+        );
+    }
+}
@@ -0,0 +1,657 @@
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use image::{ColorType, DynamicImage, GenericImageView, ImageFormat};
+
+use crate::alpha::AlphaOptions;
+use crate::args::{AlgorithmType, OutputScale};
+use crate::image_data::ImageData;
+use crate::image_io::STANDARD_STREAM_PATH;
+use crate::mosaic_factory::MosaicFactory;
+
+pub trait FrameReader {
+    /// Reads the next frame, returns `None` after the last frame.
+    fn next_frame(&mut self) -> io::Result<Option<DynamicImage>>;
+
+    /// Header of y4m input, passed on to y4m output.
+    fn y4m_header(&self) -> Option<&Y4mHeader> {
+        return None;
+    }
+}
+
+pub trait FrameWriter {
+    fn write_frame(&mut self, frame: &DynamicImage) -> io::Result<()>;
+}
+
+/// Opens a directory of numbered frames, a y4m file or y4m from stdin.
+pub fn open_frame_reader(path: &str) -> io::Result<Box<dyn FrameReader>> {
+    if path == STANDARD_STREAM_PATH {
+        let reader: Box<dyn BufRead> = Box::new(BufReader::new(io::stdin()));
+        return Ok(Box::new(Y4mReader::new(reader)?));
+    }
+    if Path::new(path).is_dir() {
+        return Ok(Box::new(DirectoryFrameReader::new(Path::new(path))?));
+    }
+    let reader: Box<dyn BufRead> = Box::new(BufReader::new(File::open(path)?));
+    return Ok(Box::new(Y4mReader::new(reader)?));
+}
+
+/// Opens a y4m file, y4m on stdout or a directory receiving numbered PNG frames, y4m output repeats the frame rate
+/// and colour space of y4m input.
+pub fn open_frame_writer(
+    path: &str,
+    input_header: Option<&Y4mHeader>,
+) -> io::Result<Box<dyn FrameWriter>> {
+    let header = input_header.cloned().unwrap_or_default();
+    if path == STANDARD_STREAM_PATH {
+        let writer: Box<dyn Write> = Box::new(BufWriter::new(io::stdout()));
+        return Ok(Box::new(Y4mWriter::new(writer, header)));
+    }
+    if Path::new(path)
+        .extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("y4m"))
+    {
+        if let Some(parent) = Path::new(path).parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let writer: Box<dyn Write> = Box::new(BufWriter::new(File::create(path)?));
+        return Ok(Box::new(Y4mWriter::new(writer, header)));
+    }
+    std::fs::create_dir_all(path)?;
+    return Ok(Box::new(DirectoryFrameWriter {
+        directory: PathBuf::from(path),
+        frame_index: 0,
+    }));
+}
+
+/// Image files of a directory ordered by the last number in their names.
+struct DirectoryFrameReader {
+    paths: Vec<PathBuf>,
+    frame_index: usize,
+}
+
+impl DirectoryFrameReader {
+    fn new(directory: &Path) -> io::Result<DirectoryFrameReader> {
+        let mut paths: Vec<PathBuf> = std::fs::read_dir(directory)?
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect::<io::Result<_>>()?;
+        paths.retain(|path| path.is_file() && ImageFormat::from_path(path).is_ok());
+        paths.sort_by_cached_key(|path| {
+            let name = path.file_name().unwrap().to_string_lossy().into_owned();
+            return (frame_number(&name), name);
+        });
+        if paths.is_empty() {
+            return Err(io::Error::other(format!(
+                "no image frames in {}",
+                directory.display()
+            )));
+        }
+        return Ok(DirectoryFrameReader {
+            paths,
+            frame_index: 0,
+        });
+    }
+}
+
+/// Last run of digits in a file name, frames without a number sort first.
+fn frame_number(name: &str) -> Option<u64> {
+    let digits: String = name
+        .chars()
+        .rev()
+        .skip_while(|c| !c.is_ascii_digit())
+        .take_while(|c| c.is_ascii_digit())
+        .collect();
+    return digits.chars().rev().collect::<String>().parse().ok();
+}
+
+impl FrameReader for DirectoryFrameReader {
+    fn next_frame(&mut self) -> io::Result<Option<DynamicImage>> {
+        let Some(path) = self.paths.get(self.frame_index) else {
+            return Ok(None);
+        };
+        self.frame_index += 1;
+        return image::open(path)
+            .map(Some)
+            .map_err(|e| io::Error::other(format!("{}: {}", path.display(), e)));
+    }
+}
+
+struct DirectoryFrameWriter {
+    directory: PathBuf,
+    frame_index: u32,
+}
+
+impl FrameWriter for DirectoryFrameWriter {
+    fn write_frame(&mut self, frame: &DynamicImage) -> io::Result<()> {
+        self.frame_index += 1;
+        let path = self
+            .directory
+            .join(format!("frame_{:06}.png", self.frame_index));
+        return frame.save(&path).map_err(io::Error::other);
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Chroma {
+    C420,
+    C422,
+    C444,
+    Mono,
+}
+
+impl Chroma {
+    /// Horizontal and vertical chroma subsampling factors.
+    fn subsampling(&self) -> (u32, u32) {
+        return match self {
+            Chroma::C420 => (2, 2),
+            Chroma::C422 => (2, 1),
+            Chroma::C444 | Chroma::Mono => (1, 1),
+        };
+    }
+}
+
+/// Stream header of a YUV4MPEG2 video, parameters other than size and colour space are kept verbatim.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Y4mHeader {
+    pub width: u32,
+    pub height: u32,
+    pub chroma: Chroma,
+    /// Colour space tag written to output, `420jpeg` for example
+    pub colorspace: String,
+    /// Frame rate, interlacing, aspect ratio and other parameters
+    pub parameters: Vec<String>,
+}
+
+impl Default for Y4mHeader {
+    fn default() -> Y4mHeader {
+        return Y4mHeader {
+            width: 0,
+            height: 0,
+            chroma: Chroma::C420,
+            colorspace: "420jpeg".to_string(),
+            parameters: vec!["F25:1".to_string(), "Ip".to_string(), "A1:1".to_string()],
+        };
+    }
+}
+
+impl Y4mHeader {
+    fn parse(line: &str) -> io::Result<Y4mHeader> {
+        let mut tokens = line.split_ascii_whitespace();
+        if tokens.next() != Some("YUV4MPEG2") {
+            return Err(io::Error::other("input is not a YUV4MPEG2 stream"));
+        }
+        let mut header = Y4mHeader::default();
+        header.parameters.clear();
+        let mut has_frame_rate = false;
+        for token in tokens {
+            let (tag, value) = token.split_at(1);
+            let parse_size = |value: &str| {
+                return value
+                    .parse::<u32>()
+                    .map_err(|_| io::Error::other(format!("invalid y4m size: {}", token)));
+            };
+            match tag {
+                "W" => header.width = parse_size(value)?,
+                "H" => header.height = parse_size(value)?,
+                "C" => {
+                    header.chroma = match value {
+                        "420" | "420jpeg" | "420mpeg2" | "420paldv" => Chroma::C420,
+                        "422" => Chroma::C422,
+                        "444" => Chroma::C444,
+                        "mono" => Chroma::Mono,
+                        _ => {
+                            return Err(io::Error::other(format!(
+                                "unsupported y4m colour space: {}, only 8-bit 420, 422, 444 and mono are supported",
+                                value
+                            )))
+                        }
+                    };
+                    header.colorspace = value.to_string();
+                }
+                _ => {
+                    has_frame_rate |= tag == "F";
+                    header.parameters.push(token.to_string());
+                }
+            }
+        }
+        if !has_frame_rate {
+            header.parameters.insert(0, "F25:1".to_string());
+        }
+        if header.width == 0 || header.height == 0 {
+            return Err(io::Error::other("y4m header is missing the frame size"));
+        }
+        return Ok(header);
+    }
+
+    fn format(&self) -> String {
+        return format!(
+            "YUV4MPEG2 W{} H{} {} C{}\n",
+            self.width,
+            self.height,
+            self.parameters.join(" "),
+            self.colorspace
+        );
+    }
+
+    /// Sizes of the luma and chroma planes in bytes.
+    fn plane_sizes(&self) -> (usize, usize) {
+        let (subsampling_x, subsampling_y) = self.chroma.subsampling();
+        let luma = (self.width * self.height) as usize;
+        let chroma = match self.chroma {
+            Chroma::Mono => 0,
+            _ => {
+                (self.width.div_ceil(subsampling_x) * self.height.div_ceil(subsampling_y)) as usize
+            }
+        };
+        return (luma, chroma);
+    }
+}
+
+/// Limited range BT.601 conversion used by y4m streams without colour metadata.
+fn rgb_to_yuv(rgb: &[u8]) -> [f32; 3] {
+    let (r, g, b) = (rgb[0] as f32, rgb[1] as f32, rgb[2] as f32);
+    return [
+        16.0 + (65.481 * r + 128.553 * g + 24.966 * b) / 255.0,
+        128.0 + (-37.797 * r - 74.203 * g + 112.0 * b) / 255.0,
+        128.0 + (112.0 * r - 93.786 * g - 18.214 * b) / 255.0,
+    ];
+}
+
+fn yuv_to_rgb(y: u8, u: u8, v: u8) -> [u8; 3] {
+    let y = (y as f32 - 16.0) * 255.0 / 219.0;
+    let (u, v) = (u as f32 - 128.0, v as f32 - 128.0);
+    let clamp = |value: f32| value.round().clamp(0.0, 255.0) as u8;
+    return [
+        clamp(y + 1.402 * 255.0 / 224.0 * v),
+        clamp(y - 0.344136 * 255.0 / 224.0 * u - 0.714136 * 255.0 / 224.0 * v),
+        clamp(y + 1.772 * 255.0 / 224.0 * u),
+    ];
+}
+
+pub struct Y4mReader<R: BufRead> {
+    reader: R,
+    header: Y4mHeader,
+    frame: Vec<u8>,
+}
+
+impl<R: BufRead> Y4mReader<R> {
+    pub fn new(mut reader: R) -> io::Result<Y4mReader<R>> {
+        let mut line = String::new();
+        reader.read_line(&mut line)?;
+        let header = Y4mHeader::parse(line.trim_end())?;
+        let (luma, chroma) = header.plane_sizes();
+        return Ok(Y4mReader {
+            reader,
+            header,
+            frame: vec![0; luma + 2 * chroma],
+        });
+    }
+}
+
+impl<R: BufRead> FrameReader for Y4mReader<R> {
+    fn next_frame(&mut self) -> io::Result<Option<DynamicImage>> {
+        let mut line = Vec::new();
+        if self.reader.read_until(b'\n', &mut line)? == 0 {
+            return Ok(None);
+        }
+        if !line.starts_with(b"FRAME") {
+            return Err(io::Error::other("y4m frame header expected"));
+        }
+        self.reader.read_exact(&mut self.frame)?;
+
+        let header = &self.header;
+        let (width, height) = (header.width, header.height);
+        let (luma, chroma) = header.plane_sizes();
+        if header.chroma == Chroma::Mono {
+            let data = self.frame[..luma]
+                .iter()
+                .map(|y| {
+                    ((*y as f32 - 16.0) * 255.0 / 219.0)
+                        .round()
+                        .clamp(0.0, 255.0) as u8
+                })
+                .collect();
+            return Ok(Some(ImageData::image_from_buffer(
+                data,
+                width,
+                height,
+                ColorType::L8,
+            )));
+        }
+
+        let (subsampling_x, subsampling_y) = header.chroma.subsampling();
+        let chroma_width = width.div_ceil(subsampling_x) as usize;
+        let (y_plane, chroma_planes) = self.frame.split_at(luma);
+        let (u_plane, v_plane) = chroma_planes.split_at(chroma);
+        let mut data = Vec::with_capacity(luma * 3);
+        for y in 0..height as usize {
+            for x in 0..width as usize {
+                let chroma_index =
+                    (y / subsampling_y as usize) * chroma_width + x / subsampling_x as usize;
+                data.extend_from_slice(&yuv_to_rgb(
+                    y_plane[y * width as usize + x],
+                    u_plane[chroma_index],
+                    v_plane[chroma_index],
+                ));
+            }
+        }
+        return Ok(Some(ImageData::image_from_buffer(
+            data,
+            width,
+            height,
+            ColorType::Rgb8,
+        )));
+    }
+
+    fn y4m_header(&self) -> Option<&Y4mHeader> {
+        return Some(&self.header);
+    }
+}
+
+/// Writes frames as y4m, the stream header is written with the size of the first frame.
+pub struct Y4mWriter<W: Write> {
+    writer: W,
+    header: Y4mHeader,
+    header_written: bool,
+}
+
+impl<W: Write> Y4mWriter<W> {
+    pub fn new(writer: W, header: Y4mHeader) -> Y4mWriter<W> {
+        return Y4mWriter {
+            writer,
+            header,
+            header_written: false,
+        };
+    }
+}
+
+impl<W: Write> FrameWriter for Y4mWriter<W> {
+    fn write_frame(&mut self, frame: &DynamicImage) -> io::Result<()> {
+        let (width, height) = frame.dimensions();
+        if !self.header_written {
+            self.header.width = width;
+            self.header.height = height;
+            self.writer.write_all(self.header.format().as_bytes())?;
+            self.header_written = true;
+        }
+        self.writer.write_all(b"FRAME\n")?;
+
+        let to_byte = |value: f32| value.round().clamp(0.0, 255.0) as u8;
+        if self.header.chroma == Chroma::Mono {
+            let luma: Vec<u8> = frame
+                .to_luma8()
+                .iter()
+                .map(|y| to_byte(16.0 + *y as f32 * 219.0 / 255.0))
+                .collect();
+            self.writer.write_all(&luma)?;
+            return self.writer.flush();
+        }
+
+        let rgb = frame.to_rgb8();
+        let yuv: Vec<[f32; 3]> = rgb.chunks_exact(3).map(rgb_to_yuv).collect();
+        let luma: Vec<u8> = yuv.iter().map(|pixel| to_byte(pixel[0])).collect();
+        self.writer.write_all(&luma)?;
+
+        // chroma planes average the pixels covered by each chroma sample
+        let (subsampling_x, subsampling_y) = self.header.chroma.subsampling();
+        for channel in [1, 2] {
+            let mut plane = Vec::with_capacity(self.header.plane_sizes().1);
+            for chroma_y in 0..height.div_ceil(subsampling_y) {
+                for chroma_x in 0..width.div_ceil(subsampling_x) {
+                    let (mut sum, mut count) = (0.0, 0.0);
+                    for y in chroma_y * subsampling_y..((chroma_y + 1) * subsampling_y).min(height)
+                    {
+                        for x in
+                            chroma_x * subsampling_x..((chroma_x + 1) * subsampling_x).min(width)
+                        {
+                            sum += yuv[(y * width + x) as usize][channel];
+                            count += 1.0;
+                        }
+                    }
+                    plane.push(to_byte(sum / count));
+                }
+            }
+            self.writer.write_all(&plane)?;
+        }
+        return self.writer.flush();
+    }
+}
+
+/// Mosaics consecutive video frames with a shared tile grid, tile averages can be smoothed over time to avoid
+/// flicker of tiles whose colour changes slightly between frames.
+pub struct VideoMosaic {
+    pub algorithm_type: AlgorithmType,
+    pub tile_side_length: u32,
+    pub alpha_options: AlphaOptions,
+    pub output_scale: OutputScale,
+    pub output_size: Option<(u32, u32)>,
+    /// Weight of previous frames in the exponential moving average of tile averages, 0 disables smoothing
+    pub temporal_smoothing: f32,
+}
+
+impl VideoMosaic {
+    pub fn new(algorithm_type: AlgorithmType, tile_side_length: u32) -> VideoMosaic {
+        return VideoMosaic {
+            algorithm_type,
+            tile_side_length,
+            alpha_options: AlphaOptions::default(),
+            output_scale: OutputScale::Full,
+            output_size: None,
+            temporal_smoothing: 0.0,
+        };
+    }
+
+    pub fn with_alpha_options(mut self, alpha_options: AlphaOptions) -> VideoMosaic {
+        self.alpha_options = alpha_options;
+        return self;
+    }
+
+    pub fn with_output_size(
+        mut self,
+        output_scale: Option<OutputScale>,
+        output_size: Option<(u32, u32)>,
+    ) -> VideoMosaic {
+        self.output_scale = output_scale.unwrap_or(OutputScale::Full);
+        self.output_size = output_size;
+        return self;
+    }
+
+    pub fn with_temporal_smoothing(mut self, temporal_smoothing: f32) -> VideoMosaic {
+        self.temporal_smoothing = temporal_smoothing;
+        return self;
+    }
+
+    /// Processes frames one at a time and returns the number of frames written. Every frame must have the size and
+    /// colour type of the first frame so that tiles cover the same area in all frames.
+    pub fn process(
+        &self,
+        frame_reader: &mut dyn FrameReader,
+        frame_writer: &mut dyn FrameWriter,
+    ) -> io::Result<u32> {
+        let mut first_frame: Option<(u32, u32, ColorType)> = None;
+        let mut smoothed_averages: Vec<f32> = Vec::new();
+        let mut frame_count = 0;
+
+        while let Some(frame) = frame_reader.next_frame()? {
+            let (width, height) = frame.dimensions();
+            let layout = (width, height, frame.color());
+            match first_frame {
+                None => first_frame = Some(layout),
+                Some(first_layout) if first_layout != layout => {
+                    return Err(io::Error::other(format!(
+                        "frame {} is {}x{} {:?}, expected {}x{} {:?} like the first frame",
+                        frame_count + 1,
+                        layout.0,
+                        layout.1,
+                        layout.2,
+                        first_layout.0,
+                        first_layout.1,
+                        first_layout.2
+                    )));
+                }
+                Some(_) => {}
+            }
+
+            let mosaic_factory = MosaicFactory::from_image_data(
+                ImageData::new(&frame, self.tile_side_length),
+                self.algorithm_type.mosaic_builder(),
+                self.tile_side_length,
+            )
+            .with_alpha_options(self.alpha_options);
+            let (mut tile_averages, _) = mosaic_factory.tile_averages();
+            if self.temporal_smoothing > 0.0 {
+                if smoothed_averages.is_empty() {
+                    smoothed_averages = tile_averages.iter().map(|value| *value as f32).collect();
+                }
+                for (smoothed, value) in smoothed_averages.iter_mut().zip(&mut tile_averages) {
+                    *smoothed = self.temporal_smoothing * *smoothed
+                        + (1.0 - self.temporal_smoothing) * *value as f32;
+                    *value = smoothed.round() as u8;
+                }
+            }
+
+            let full_size = mosaic_factory.output_size(OutputScale::Full);
+            let (output_width, output_height) = self
+                .output_size
+                .unwrap_or(mosaic_factory.output_size(self.output_scale));
+            let mosaic = match (output_width, output_height) == full_size {
+                true => mosaic_factory
+                    .mosaic_builder
+                    .create_mosaic(&mosaic_factory, &tile_averages),
+                false => {
+                    mosaic_factory.render_tile_grid(&tile_averages, output_width, output_height)
+                }
+            };
+            frame_writer.write_frame(&ImageData::image_from_buffer(
+                mosaic,
+                output_width,
+                output_height,
+                mosaic_factory.image_data.color,
+            ))?;
+            frame_count += 1;
+        }
+        return Ok(frame_count);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::synthetic::{ChannelLayout, Pattern, SyntheticImage};
+    use crate::temp_dir::TempDir;
+    use std::io::Cursor;
+
+    struct MemoryFrames(std::vec::IntoIter<DynamicImage>);
+
+    impl FrameReader for MemoryFrames {
+        fn next_frame(&mut self) -> io::Result<Option<DynamicImage>> {
+            return Ok(self.0.next());
+        }
+    }
+
+    impl FrameWriter for Vec<DynamicImage> {
+        fn write_frame(&mut self, frame: &DynamicImage) -> io::Result<()> {
+            self.push(frame.clone());
+            return Ok(());
+        }
+    }
+
+    fn solid_frame(width: u32, height: u32, value: u8) -> DynamicImage {
+        return SyntheticImage::new(Pattern::Solid, width, height, ChannelLayout::L)
+            .with_color([value; 3], 255)
+            .generate();
+    }
+
+    #[test]
+    fn smoothing_averages_tiles_over_frames() {
+        let frames = Vec::from([0, 200, 200, 200].map(|value| solid_frame(16, 8, value)));
+        for (smoothing, expected) in [(0.0, [0, 200, 200, 200]), (0.5, [0, 100, 150, 175])] {
+            let mut output: Vec<DynamicImage> = Vec::new();
+            let frame_count = VideoMosaic::new(AlgorithmType::Parallel, 8)
+                .with_temporal_smoothing(smoothing)
+                .process(&mut MemoryFrames(frames.clone().into_iter()), &mut output)
+                .unwrap();
+            assert_eq!(frame_count, 4);
+            for (frame, expected) in output.iter().zip(expected) {
+                assert!(frame.as_bytes().iter().all(|value| *value == expected));
+            }
+        }
+    }
+
+    #[test]
+    fn frames_must_share_the_tile_grid() {
+        let frames = vec![solid_frame(16, 8, 0), solid_frame(24, 8, 0)];
+        let mut output: Vec<DynamicImage> = Vec::new();
+        let result = VideoMosaic::new(AlgorithmType::Serial, 8)
+            .process(&mut MemoryFrames(frames.into_iter()), &mut output);
+        assert!(result.is_err());
+        assert_eq!(output.len(), 1);
+    }
+
+    #[test]
+    fn y4m_round_trip_keeps_colours() {
+        for colorspace in ["420jpeg", "422", "444", "mono"] {
+            let header = Y4mHeader::parse(&format!(
+                "YUV4MPEG2 W1 H1 F30000:1001 It A1:1 C{}",
+                colorspace
+            ))
+            .unwrap();
+            assert_eq!(
+                header.parameters,
+                vec!["F30000:1001", "It", "A1:1"],
+                "{}",
+                colorspace
+            );
+
+            // 2x2 blocks of one colour are not affected by chroma subsampling
+            let frame = SyntheticImage::new(Pattern::Checkerboard, 10, 6, ChannelLayout::Rgb)
+                .with_cell_side_length(2)
+                .generate();
+            let mut encoded = Vec::new();
+            let mut writer = Y4mWriter::new(&mut encoded, header.clone());
+            writer.write_frame(&frame).unwrap();
+            writer.write_frame(&frame).unwrap();
+
+            let mut reader = Y4mReader::new(Cursor::new(encoded)).unwrap();
+            assert_eq!(
+                reader.y4m_header().unwrap(),
+                &Y4mHeader {
+                    width: 10,
+                    height: 6,
+                    ..header
+                }
+            );
+            let expected = match colorspace {
+                "mono" => DynamicImage::ImageLuma8(frame.to_luma8()),
+                _ => frame.clone(),
+            };
+            for _ in 0..2 {
+                let decoded = reader.next_frame().unwrap().unwrap();
+                assert_eq!(decoded.color(), expected.color(), "{}", colorspace);
+                for (decoded, expected) in decoded.as_bytes().iter().zip(expected.as_bytes()) {
+                    assert!(decoded.abs_diff(*expected) <= 2, "{}", colorspace);
+                }
+            }
+            assert!(reader.next_frame().unwrap().is_none());
+        }
+    }
+
+    #[test]
+    fn directory_frames_are_ordered_by_number() {
+        let temp_dir = TempDir::new("video");
+        let directory = temp_dir.join("frames");
+        std::fs::create_dir_all(&directory).unwrap();
+        for (name, value) in [("shot10.png", 10), ("shot2.png", 2), ("shot1.png", 1)] {
+            solid_frame(8, 8, value).save(directory.join(name)).unwrap();
+        }
+        std::fs::write(directory.join("notes.txt"), "not a frame").unwrap();
+
+        let mut reader = open_frame_reader(directory.to_str().unwrap()).unwrap();
+        let mut values = Vec::new();
+        while let Some(frame) = reader.next_frame().unwrap() {
+            values.push(frame.as_bytes()[0]);
+        }
+        assert_eq!(values, vec![1, 2, 10]);
+    }
+}
@@ -0,0 +1,266 @@
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+// the binary crate has no library target, so the guard is compiled into the tests from its source and not every
+// test uses all of it
+#[allow(dead_code)]
+#[path = "../src/temp_dir.rs"]
+mod temp_dir;
+
+use temp_dir::TempDir;
+
+/// Runs the binary without user-level config and returns its exit code with stdout and stderr.
+fn run(args: &[&str]) -> (i32, String, String) {
+    let output = Command::new(env!("CARGO_BIN_EXE_mosaic_generator"))
+        .args(args)
+        .env(
+            "XDG_CONFIG_HOME",
+            std::env::temp_dir().join("mosaic_generator_no_config"),
+        )
+        .output()
+        .expect("Failed to run mosaic generator");
+    return (
+        output
+            .status
+            .code()
+            .expect("mosaic generator was killed by a signal"),
+        String::from_utf8_lossy(&output.stdout).into_owned(),
+        String::from_utf8_lossy(&output.stderr).into_owned(),
+    );
+}
+
+/// Runs the binary without user-level config with bytes on stdin and returns its exit code with stdout and stderr.
+fn run_with_input(args: &[&str], input: &[u8]) -> (i32, Vec<u8>, Vec<u8>) {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_mosaic_generator"))
+        .args(args)
+        .env(
+            "XDG_CONFIG_HOME",
+            std::env::temp_dir().join("mosaic_generator_no_config"),
+        )
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("Failed to run mosaic generator");
+    // commands failing before they read stdin close it early
+    if let Err(e) = child.stdin.take().unwrap().write_all(input) {
+        assert_eq!(
+            e.kind(),
+            std::io::ErrorKind::BrokenPipe,
+            "Failed to write mosaic generator input: {}",
+            e
+        );
+    }
+    let output = child
+        .wait_with_output()
+        .expect("Failed to run mosaic generator");
+    return (
+        output
+            .status
+            .code()
+            .expect("mosaic generator was killed by a signal"),
+        output.stdout,
+        output.stderr,
+    );
+}
+
+#[test]
+fn successful_commands_exit_with_zero() {
+    let (code, stdout, _) = run(&["info", "synthetic:noise:64x40", "-t", "16"]);
+    assert_eq!(code, 0);
+    assert!(stdout.contains("Size: 64x40"), "{}", stdout);
+    assert!(stdout.contains("Tiles: 4x2"), "{}", stdout);
+
+    let (code, stdout, _) = run(&["verify", "synthetic:noise:64x40", "parallel", "-t", "8"]);
+    assert_eq!(code, 0, "{}", stdout);
+}
+
+#[test]
+fn invalid_arguments_exit_with_usage_code() {
+    let (code, _, stderr) = run(&["generate", "synthetic:noise:64x40"]);
+    assert_eq!(code, 2, "{}", stderr);
+    let (code, _, stderr) = run(&["info", "synthetic:noise:64x40", "--quiet", "--verbose"]);
+    assert_eq!(code, 2, "{}", stderr);
+    let (code, _, stderr) = run(&[
+        "generate",
+        "synthetic:noise:64x40",
+        "-o",
+        "mosaic.png",
+        "--tone-mapping",
+        "aces",
+    ]);
+    assert_eq!(code, 2, "{}", stderr);
+    assert!(stderr.contains("--tone-mapping"), "{}", stderr);
+    let (code, _, stderr) = run(&["sweep", "synthetic:noise:64x40", "--tile-sizes", "0"]);
+    assert_eq!(code, 2, "{}", stderr);
+    for args in [
+        &["generate", "synthetic:noise:64x40", "-o", "out.png"][..],
+        &["info", "synthetic:noise:64x40"],
+        &["verify", "synthetic:noise:64x40"],
+        &["batch", "images", "-o", "out"],
+    ] {
+        let (code, _, stderr) = run(&[args, &["-t", "0"]].concat());
+        assert_eq!(code, 2, "{:?}: {}", args, stderr);
+        assert!(stderr.contains("--tile-side-length"), "{}", stderr);
+    }
+    let (code, _, stderr) = run(&[
+        "generate",
+        "synthetic:noise:64x40",
+        "-t",
+        "8",
+        "--tiling",
+        "edges",
+        "--min-tile-side-length",
+        "16",
+        "-o",
+        "out.png",
+    ]);
+    assert_eq!(code, 2, "{}", stderr);
+}
+
+#[test]
+fn redaction_is_never_ignored() {
+    let directory = TempDir::new("exit_codes_redaction");
+    let hdr_path = directory.join("input.hdr");
+    let mut hdr = b"#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y 1 +X 1\n".to_vec();
+    hdr.extend_from_slice(&[128, 128, 128, 129]);
+    std::fs::write(&hdr_path, hdr).unwrap();
+    let output = |name: &str| directory.join(name).to_string_lossy().into_owned();
+
+    for args in [
+        vec![
+            "synthetic:noise:64x64",
+            "--reveal",
+            "-o",
+            &output("reveal.gif"),
+        ],
+        vec!["synthetic:noise:64x64", "--video", "-o", &output("frames")],
+        vec![
+            "synthetic:noise:64x64",
+            "--pyramid",
+            "dzi",
+            "-o",
+            &output("x.dzi"),
+        ],
+        vec![hdr_path.to_str().unwrap(), "-o", &output("mosaic.hdr")],
+    ] {
+        for region in [&["--redact"][..], &["--region", "0,0,8,8"]] {
+            let (code, _, stderr) = run(&[&["generate"], &args[..], region].concat());
+            assert_eq!(code, 2, "{:?} {:?}: {}", args, region, stderr);
+        }
+    }
+    assert!(!directory.join("reveal.gif").exists());
+}
+
+#[test]
+fn hdr_input_rejects_unsupported_options() {
+    let directory = TempDir::new("exit_codes_hdr");
+    let hdr_path = directory.join("input.hdr");
+    let mut hdr = b"#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y 2 +X 2\n".to_vec();
+    hdr.extend_from_slice(&[128, 128, 128, 129].repeat(4));
+    std::fs::write(&hdr_path, hdr).unwrap();
+    let hdr_path = hdr_path.to_str().unwrap();
+    let output_path = directory.join("mosaic.hdr");
+    let output_path = output_path.to_str().unwrap();
+
+    for options in [
+        &["--tiling", "edges"][..],
+        &["--tile-size-mask", hdr_path],
+        &["--pyramid", "dzi"],
+    ] {
+        let (code, _, stderr) = run(&[
+            &["generate", hdr_path, "-t", "1", "-o", output_path][..],
+            options,
+        ]
+        .concat());
+        assert_eq!(code, 2, "{:?}: {}", options, stderr);
+    }
+
+    let (code, _, stderr) = run(&[
+        "generate",
+        hdr_path,
+        "-t",
+        "1",
+        "--output-size",
+        "5x3",
+        "-o",
+        output_path,
+    ]);
+    assert_eq!(code, 0, "{}", stderr);
+    let (code, stdout, _) = run(&["info", output_path]);
+    assert_eq!(code, 0);
+    assert!(stdout.contains("Size: 5x3"), "{}", stdout);
+}
+
+#[test]
+fn errors_exit_with_error_code() {
+    let (code, _, stderr) = run(&["info", "missing/image.png"]);
+    assert_eq!(code, 3);
+    assert!(stderr.contains("Input image does not exist"), "{}", stderr);
+
+    // errors are returned up to main instead of panicking, so release builds aborting on panics report them too
+    let directory = TempDir::new("exit_codes_errors");
+    let corrupt_path = directory.join("corrupt.png");
+    std::fs::write(&corrupt_path, b"not a png").unwrap();
+    let output_path = directory.join("mosaic.png");
+    let (code, _, stderr) = run(&[
+        "generate",
+        corrupt_path.to_str().unwrap(),
+        "-o",
+        output_path.to_str().unwrap(),
+    ]);
+    assert_eq!(code, 3, "{}", stderr);
+    assert!(stderr.starts_with("Error: "), "{}", stderr);
+    assert!(!stderr.contains("panicked"), "{}", stderr);
+}
+
+#[test]
+fn quiet_mode_silences_status_messages() {
+    let directory = TempDir::new("exit_codes");
+    let output_path = directory.join("quiet.png");
+    let output_path = output_path.to_str().unwrap();
+    let (code, stdout, stderr) =
+        run(&["generate", "synthetic:noise:32x32", "-o", output_path, "-q"]);
+    assert_eq!(code, 0, "{}", stderr);
+    assert!(
+        stdout.is_empty() && stderr.is_empty(),
+        "{}{}",
+        stdout,
+        stderr
+    );
+
+    let (code, _, stderr) = run(&["generate", "synthetic:noise:32x32", "-o", output_path, "-v"]);
+    assert_eq!(code, 0, "{}", stderr);
+    assert!(stderr.contains("Image global average"), "{}", stderr);
+}
+
+#[test]
+fn images_pipe_through_standard_streams() {
+    let (code, png, stderr) = run_with_input(
+        &[
+            "generate",
+            "synthetic:noise:64x32",
+            "-o",
+            "-",
+            "--output-format",
+            "png",
+        ],
+        &[],
+    );
+    assert_eq!(code, 0, "{}", String::from_utf8_lossy(&stderr));
+    assert!(png.starts_with(b"\x89PNG"));
+
+    let (code, stdout, stderr) = run_with_input(&["info", "-", "-t", "16"], &png);
+    assert_eq!(code, 0, "{}", String::from_utf8_lossy(&stderr));
+    let stdout = String::from_utf8_lossy(&stdout);
+    assert!(stdout.contains("Format: Png"), "{}", stdout);
+    assert!(stdout.contains("Size: 64x32"), "{}", stdout);
+
+    let (code, _, stderr) = run_with_input(&["generate", "-", "-o", "-"], &png);
+    assert_eq!(code, 3);
+    assert!(
+        String::from_utf8_lossy(&stderr).contains("requires --output-format"),
+        "{}",
+        String::from_utf8_lossy(&stderr)
+    );
+}
@@ -0,0 +1,98 @@
+use std::path::Path;
+use std::process::Command;
+
+// the binary crate has no library target, so the guard is compiled into the tests from its source and not every
+// test uses all of it
+#[allow(dead_code)]
+#[path = "../src/temp_dir.rs"]
+mod temp_dir;
+
+use temp_dir::TempDir;
+
+/// Generates mosaics of synthetic images with the binary and compares them with stored golden images.
+/// Run with `UPDATE_GOLDEN=1` to regenerate golden images after an intended output change.
+fn check_golden(name: &str, input: &str, args: &[&str]) {
+    let golden_path = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join("golden")
+        .join(format!("{}.png", name));
+    let directory = TempDir::new("golden");
+    let output_path = directory.join(format!("{}.png", name));
+
+    let status = Command::new(env!("CARGO_BIN_EXE_mosaic_generator"))
+        .arg("generate")
+        .arg(input)
+        .args(args)
+        .arg("--output-image-path")
+        .arg(&output_path)
+        // settings of a user-level config file would change the output
+        .env(
+            "XDG_CONFIG_HOME",
+            std::env::temp_dir().join("mosaic_generator_no_config"),
+        )
+        .output()
+        .expect("Failed to run mosaic generator");
+    assert!(
+        status.status.success(),
+        "mosaic generator failed for {}: {}",
+        name,
+        String::from_utf8_lossy(&status.stderr)
+    );
+
+    if std::env::var_os("UPDATE_GOLDEN").is_some() {
+        std::fs::create_dir_all(golden_path.parent().unwrap()).unwrap();
+        std::fs::copy(&output_path, &golden_path).unwrap();
+        return;
+    }
+
+    let output = image::open(&output_path).unwrap();
+    let golden = image::open(&golden_path).unwrap();
+    assert_eq!(output.color(), golden.color(), "{} colour type", name);
+    assert_eq!(
+        (output.width(), output.height()),
+        (golden.width(), golden.height()),
+        "{} dimensions",
+        name
+    );
+    assert!(
+        output.as_bytes() == golden.as_bytes(),
+        "{} differs from golden image",
+        name
+    );
+}
+
+#[test]
+fn gradient_rgba_serial() {
+    check_golden(
+        "gradient_rgba_serial",
+        "synthetic:gradient:100x70:layout=rgba",
+        &["serial", "-t", "16"],
+    );
+}
+
+#[test]
+fn noise_rgb_parallel() {
+    check_golden(
+        "noise_rgb_parallel",
+        "synthetic:noise:96x64:seed=42",
+        &["parallel", "-t", "8"],
+    );
+}
+
+#[test]
+fn checkerboard_luma_slow_parallel() {
+    check_golden(
+        "checkerboard_luma_slow_parallel",
+        "synthetic:checkerboard:90x60:layout=l:cell=6",
+        &["slow-parallel", "-t", "4"],
+    );
+}
+
+#[test]
+fn solid_luma_alpha_flatten() {
+    check_golden(
+        "solid_luma_alpha_flatten",
+        "synthetic:solid:40x40:layout=la:color=ff8000:alpha=128",
+        &["serial", "-t", "10", "--alpha-mode", "flatten"],
+    );
+}